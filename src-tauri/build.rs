@@ -1,5 +1,5 @@
 use cfg_if::cfg_if;
-use std::{env, fs, path::PathBuf};
+use std::{env, fs, path::PathBuf, process::Command};
 cfg_if! {
     if #[cfg(any(windows))] {
         fn main() {
@@ -44,8 +44,45 @@ cfg_if! {
               }
             }
 
+            build_plugin_host(&manifest_dir, profile);
+
             tauri_build::build()
         }
+
+        // tauri wants sidecars at `resource/<name>-<target triple>.exe` before it builds anything,
+        // so the plugin host is built here, with its own target dir so it doesn't wait on ours
+        fn build_plugin_host(manifest_dir: &PathBuf, profile: &str) {
+            let target = env::var("TARGET").unwrap();
+            let host_dir = manifest_dir.join("plugin-host");
+            let target_dir = manifest_dir.join("target/plugin-host");
+
+            println!("cargo:rerun-if-changed=plugin-host/src");
+            println!("cargo:rerun-if-changed=plugin-host/Cargo.toml");
+
+            let mut cargo = Command::new(env::var("CARGO").unwrap());
+            cargo
+                .arg("build")
+                .arg("--manifest-path")
+                .arg(host_dir.join("Cargo.toml"))
+                .arg("--target")
+                .arg(&target)
+                .arg("--target-dir")
+                .arg(&target_dir);
+            if profile == "release" {
+                cargo.arg("--release");
+            }
+
+            let status = cargo.status().expect("FAILED TO RUN CARGO FOR PLUGIN-HOST");
+            if !status.success() {
+                panic!("FAILED TO BUILD PLUGIN-HOST");
+            }
+
+            let from_path = target_dir.join(format!("{}/{}/plugin-host.exe", target, profile));
+            let to_path = manifest_dir.join(format!("resource/plugin-host-{}.exe", target));
+
+            fs::create_dir_all(manifest_dir.join("resource")).expect("FAILED TO CREATE RESOURCE DIR");
+            fs::copy(from_path, to_path).expect("FAILED TO COPY PLUGIN-HOST");
+        }
     }
     else {
         fn main() {
@@ -0,0 +1,156 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use std::{env, fs, process};
+
+use rquickjs::{CatchResultExt, Context, Ctx, Function, Runtime};
+
+// NOTE(plugin host)
+//
+// runs one plugin's `backend_main_script` in QuickJS, see NOTE(backend plugins) in
+// src/plugin/backend.rs for the protocol. the only thing this reads from disk is the script
+// itself, which happens before any of it runs.
+//
+// the sandbox is the engine: a plain context has the ECMAScript builtins and nothing else,
+// there's no `std`/`os` module, no `require`, no `import`. what the plugin gets on top is
+// `prelude.js`, whose only way out is a JSON-RPC line on stdout, so files, the network and
+// everything else go through Cider and the permissions the user gave the plugin.
+// the process is also started with an empty environment.
+
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+const STACK_LIMIT: usize = 1024 * 1024;
+
+const PRELUDE: &str = include_str!("prelude.js");
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(p) => p,
+        None => fail("usage: plugin-host <script>"),
+    };
+    let source = fs::read_to_string(&path)
+        .unwrap_or_else(|e| fail(&format!("unable to read {}, {}", path, e)));
+    let id = env::var("CIDER_PLUGIN_ID").unwrap_or_default();
+
+    let runtime = Runtime::new().unwrap_or_else(|e| fail(&e.to_string()));
+    runtime.set_memory_limit(MEMORY_LIMIT);
+    runtime.set_max_stack_size(STACK_LIMIT);
+    let context = Context::full(&runtime).unwrap_or_else(|e| fail(&e.to_string()));
+
+    // stdin is read on its own thread, so timers can run while nothing is coming in
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    context.with(|ctx| {
+        if let Err(e) = setup(&ctx, &id) {
+            fail(&format!("unable to set up the plugin api, {}", e));
+        }
+    });
+
+    // `initialize` comes first, so `CiderPlugin.appVersion` is there when the script starts
+    match rx.recv() {
+        Ok(line) => receive(&context, &line),
+        Err(_) => return,
+    }
+    run_jobs(&runtime);
+
+    context.with(|ctx| {
+        if let Err(e) = ctx.eval::<(), _>(source).catch(&ctx) {
+            fail(&e.to_string());
+        }
+    });
+    run_jobs(&runtime);
+
+    loop {
+        let wait = context.with(|ctx| {
+            ctx.globals()
+                .get::<_, Function>("__nextTimer")
+                .and_then(|f| f.call::<_, f64>(()))
+                .unwrap_or(-1.0)
+        });
+
+        let line = if wait < 0.0 {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(Duration::from_millis(wait as u64))
+        };
+
+        match line {
+            Ok(line) => receive(&context, &line),
+            Err(RecvTimeoutError::Timeout) => context.with(|ctx| {
+                if let Err(e) = ctx
+                    .globals()
+                    .get::<_, Function>("__runTimers")
+                    .and_then(|f| f.call::<_, ()>(()))
+                    .catch(&ctx)
+                {
+                    eprintln!("{}", e);
+                }
+            }),
+            // Cider closed stdin, it's stopping the plugin
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        run_jobs(&runtime);
+    }
+}
+
+fn setup(ctx: &Ctx, id: &str) -> rquickjs::Result<()> {
+    let globals = ctx.globals();
+
+    globals.set(
+        "__send",
+        Function::new(ctx.clone(), |line: String| {
+            let mut stdout = io::stdout().lock();
+            // nothing to do about it, Cider is gone and stdin will close too
+            writeln!(stdout, "{}", line).ok();
+            stdout.flush().ok();
+        })?,
+    )?;
+    globals.set(
+        "__print",
+        Function::new(ctx.clone(), |message: String| eprintln!("{}", message))?,
+    )?;
+    globals.set("__id", id)?;
+
+    ctx.eval::<(), _>(PRELUDE)
+}
+
+fn receive(context: &Context, line: &str) {
+    context.with(|ctx| {
+        if let Err(e) = ctx
+            .globals()
+            .get::<_, Function>("__receive")
+            .and_then(|f| f.call::<_, ()>((line,)))
+            .catch(&ctx)
+        {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+// promise callbacks, e.g. whatever was waiting on `CiderPlugin.call`
+fn run_jobs(runtime: &Runtime) {
+    loop {
+        match runtime.execute_pending_job() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("{:?}", e),
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
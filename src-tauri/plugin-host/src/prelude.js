@@ -0,0 +1,138 @@
+// evaluated before the plugin's script. everything a backend can reach is defined here, the
+// engine itself has nothing that touches files, the network or the environment. `__send`,
+// `__print` and `__id` come from main.rs and are removed from the globals once this has run.
+(function (global) {
+  const send = global.__send;
+  const print = global.__print;
+  const id = global.__id;
+
+  let next = 0;
+  const pending = new Map();
+  const handlers = new Map();
+  let info = {};
+
+  const request = (method, params) =>
+    new Promise((resolve, reject) => {
+      const call = next++;
+      pending.set(call, { resolve, reject });
+      send(JSON.stringify({ jsonrpc: "2.0", id: call, method, params: params || {} }));
+    });
+
+  const notify = (method, params) =>
+    send(JSON.stringify({ jsonrpc: "2.0", method, params: params || {} }));
+
+  const report = (e) => print(`${e}${e && e.stack ? "\n" + e.stack : ""}`);
+
+  // timers are kept here and run by main.rs, which sleeps until `__nextTimer` says so
+  let nextTimer = 1;
+  const timers = new Map();
+
+  const schedule = (repeat) => (callback, delay, ...args) => {
+    const timer = nextTimer++;
+    const interval = Math.max(0, Number(delay) || 0);
+    timers.set(timer, { callback, args, interval, repeat, due: Date.now() + interval });
+    return timer;
+  };
+  const clear = (timer) => timers.delete(timer);
+
+  const hidden = (value) => ({ value, enumerable: false });
+
+  Object.defineProperties(global, {
+    setTimeout: hidden(schedule(false)),
+    setInterval: hidden(schedule(true)),
+    clearTimeout: hidden(clear),
+    clearInterval: hidden(clear),
+    console: hidden(
+      Object.freeze({
+        log: (...args) => print(args.join(" ")),
+        info: (...args) => print(args.join(" ")),
+        warn: (...args) => print(args.join(" ")),
+        error: (...args) => print(args.join(" ")),
+      })
+    ),
+    CiderPlugin: hidden(
+      Object.freeze({
+        id,
+        get appVersion() {
+          return info.app_version;
+        },
+        get apiVersion() {
+          return info.api_version;
+        },
+        call: request,
+        log: (message) => notify("log", { message: String(message) }),
+        // resolves once Cider has the subscription, events before that are missed
+        on: (name, handler) => {
+          if (!handlers.has(name)) handlers.set(name, new Set());
+          handlers.get(name).add(handler);
+          return request("events.subscribe", { events: [name] });
+        },
+        off: (name, handler) => {
+          const set = handlers.get(name);
+          if (!set) return Promise.resolve();
+          set.delete(handler);
+          if (set.size > 0) return Promise.resolve();
+          handlers.delete(name);
+          return request("events.unsubscribe", { events: [name] });
+        },
+      })
+    ),
+    __receive: hidden((line) => {
+      let message;
+      try {
+        message = JSON.parse(line);
+      } catch (e) {
+        return report(e);
+      }
+
+      if (message.method === "initialize") {
+        info = message.params || {};
+        return;
+      }
+
+      if (message.method === "event") {
+        const { name, payload } = message.params || {};
+        for (const handler of handlers.get(name) || []) {
+          try {
+            handler(payload);
+          } catch (e) {
+            report(e);
+          }
+        }
+        return;
+      }
+
+      const waiting = pending.get(message.id);
+      if (!waiting) return;
+      pending.delete(message.id);
+      "error" in message ? waiting.reject(message.error) : waiting.resolve(message.result);
+    }),
+    __nextTimer: hidden(() => {
+      let due = -1;
+      for (const timer of timers.values()) {
+        if (due < 0 || timer.due < due) due = timer.due;
+      }
+      return due < 0 ? -1 : Math.max(0, due - Date.now());
+    }),
+    __runTimers: hidden(() => {
+      const now = Date.now();
+      for (const [timer, entry] of [...timers]) {
+        if (entry.due > now || !timers.has(timer)) continue;
+        if (entry.repeat) {
+          entry.due = now + entry.interval;
+        } else {
+          timers.delete(timer);
+        }
+        try {
+          entry.callback(...entry.args);
+        } catch (e) {
+          report(e);
+        }
+      }
+    }),
+  });
+
+  delete global.__send;
+  delete global.__print;
+  delete global.__id;
+})(globalThis);
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::error::AirPlayError;
+use crate::dlna::Renderer;
+
+// NOTE(airplay discovery)
+//
+// receivers advertise `_raop._tcp` as `<mac>@<name>`. what they support is in the TXT record:
+//
+//   cn   codecs, 0 PCM, 1 ALAC, 2 AAC, 3 AAC-ELD
+//   et   encryption, 0 none, 1 RSA, 3 FairPlay, 4 MFiSAP, 5 FairPlay SAPv2.5
+//   md   metadata, 0 text, 1 artwork, 2 progress
+//   pw   `true` if it wants a password
+//   sr/ss/ch   sample rate, sample size and channels
+//   am   model
+
+pub const SERVICE_TYPE: &str = "_raop._tcp.local.";
+pub const DEVICES_EVENT: &str = "airplay-devices";
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct AirPlayDevice {
+    // the full service name, stable for as long as the receiver keeps its name
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub addresses: Vec<String>,
+    pub port: u16,
+    pub model: Option<String>,
+    pub capabilities: Capabilities,
+}
+
+/// anything the device commands can stream to
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Device {
+    AirPlay(Box<AirPlayDevice>),
+    Dlna(Renderer),
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Capabilities {
+    pub codecs: Vec<String>,
+    pub encryption: Vec<String>,
+    pub metadata: Vec<String>,
+    pub password: bool,
+    pub sample_rate: Option<u32>,
+    pub sample_size: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    devices: Arc<RwLock<HashMap<String, AirPlayDevice>>>,
+}
+
+impl Discovery {
+    /// browses until dropped, telling the webview whenever the list changes
+    pub fn start<R>(handle: AppHandle<R>) -> Result<Self, AirPlayError>
+    where
+        R: Runtime,
+    {
+        let daemon = ServiceDaemon::new().map_err(|e| AirPlayError::Discovery(e.to_string()))?;
+        let receiver = daemon
+            .browse(SERVICE_TYPE)
+            .map_err(|e| AirPlayError::Discovery(e.to_string()))?;
+
+        let devices: Arc<RwLock<HashMap<String, AirPlayDevice>>> = Default::default();
+        let found = devices.clone();
+
+        // ends by itself once the daemon shuts down and the channel closes
+        tauri::async_runtime::spawn_blocking(move || {
+            while let Ok(event) = receiver.recv() {
+                let changed = match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let device = device(&info);
+                        found.write().unwrap().insert(device.id.clone(), device);
+                        true
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        found.write().unwrap().remove(&fullname).is_some()
+                    }
+                    _ => false,
+                };
+
+                if changed {
+                    handle.emit_all(DEVICES_EVENT, list(&found)).ok();
+                }
+            }
+        });
+
+        Ok(Self { daemon, devices })
+    }
+
+    pub fn devices(&self) -> Vec<AirPlayDevice> {
+        list(&self.devices)
+    }
+
+    pub fn device(&self, id: &str) -> Option<AirPlayDevice> {
+        self.devices.read().unwrap().get(id).cloned()
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        self.daemon.shutdown().ok();
+    }
+}
+
+fn list(devices: &RwLock<HashMap<String, AirPlayDevice>>) -> Vec<AirPlayDevice> {
+    let mut devices: Vec<AirPlayDevice> = devices.read().unwrap().values().cloned().collect();
+    devices.sort_by_key(|d| d.name.to_lowercase());
+    devices
+}
+
+fn device(info: &ServiceInfo) -> AirPlayDevice {
+    let fullname = info.get_fullname().to_string();
+    let txt = |key: &str| info.get_properties().get_property_val_str(key);
+
+    // `<mac>@<name>._raop._tcp.local.`
+    let instance = fullname
+        .strip_suffix(&format!(".{}", SERVICE_TYPE))
+        .unwrap_or(&fullname);
+    let name = instance
+        .split_once('@')
+        .map(|(_, name)| name)
+        .unwrap_or(instance)
+        .to_string();
+
+    let mut addresses: Vec<String> = info.get_addresses().iter().map(|a| a.to_string()).collect();
+    addresses.sort();
+
+    AirPlayDevice {
+        id: fullname.clone(),
+        name,
+        host: info.get_hostname().trim_end_matches('.').to_string(),
+        addresses,
+        port: info.get_port(),
+        model: txt("am").map(|s| s.to_string()),
+        capabilities: Capabilities {
+            codecs: names(txt("cn"), &["PCM", "ALAC", "AAC", "AAC-ELD"]),
+            encryption: names(
+                txt("et"),
+                &["none", "RSA", "", "FairPlay", "MFiSAP", "FairPlay SAPv2.5"],
+            ),
+            metadata: names(txt("md"), &["text", "artwork", "progress"]),
+            password: txt("pw").map(|s| s == "true").unwrap_or(false),
+            sample_rate: txt("sr").and_then(|s| s.parse().ok()),
+            sample_size: txt("ss").and_then(|s| s.parse().ok()),
+            channels: txt("ch").and_then(|s| s.parse().ok()),
+        },
+    }
+}
+
+// "0,1,2" into names, anything unknown is kept as the raw number
+fn names(value: Option<&str>, known: &[&str]) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse::<usize>()
+                .ok()
+                .and_then(|i| known.get(i))
+                .filter(|n| !n.is_empty())
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| v.to_string())
+        })
+        .collect()
+}
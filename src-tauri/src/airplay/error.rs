@@ -1,8 +1,24 @@
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::dlna::error::DlnaError;
+
 #[derive(Debug, Serialize, Error)]
 pub enum AirPlayError {
     #[error("Initialisation Error: {0}")]
     Init(String),
+    #[error("Invalid Audio: {0}")]
+    Audio(String),
+    #[error("Unable to Write Audio: {0}")]
+    Write(String),
+    #[error("Discovery Error: {0}")]
+    Discovery(String),
+    #[error("Device Error: {0}")]
+    Device(String),
+    #[error("Metadata Error: {0}")]
+    Metadata(String),
+    #[error("Recording Error: {0}")]
+    Recording(String),
+    #[error(transparent)]
+    Dlna(#[from] DlnaError),
 }
@@ -0,0 +1,98 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::error::AirPlayError;
+
+// receivers are happy with anything up to about this size, and it keeps SET_PARAMETER small
+const ARTWORK_SIZE: u32 = 600;
+const MAX_ARTWORK_BYTES: usize = 2 * 1024 * 1024;
+
+/// the last thing `send_query` was told about, replayed to receivers as they connect
+#[derive(Clone, Default)]
+pub struct NowPlaying {
+    pub daap: Vec<u8>,
+    pub artwork_url: String,
+    pub artwork: Option<Vec<u8>>,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl NowPlaying {
+    /// `(elapsed, duration)` in milliseconds, as of now
+    pub fn progress(&self) -> Option<(u32, u32)> {
+        let start = millis(self.start);
+        let end = millis(self.end);
+        if start <= 0 || end <= start {
+            return None;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .ok()?;
+
+        let duration = end - start;
+        let elapsed = (now - start).clamp(0, duration);
+
+        Some((elapsed as u32, duration as u32))
+    }
+}
+
+// DAAP is a tree of `[tag: 4 bytes][length: u32 be][data]` items, receivers want the track
+// wrapped in an `mlit` (listing item) container
+pub fn daap(title: &str, artist: &str, album: &str) -> Vec<u8> {
+    let mut item = vec![];
+    item.extend(tag(b"minm", title.as_bytes()));
+    item.extend(tag(b"asar", artist.as_bytes()));
+    item.extend(tag(b"asal", album.as_bytes()));
+
+    tag(b"mlit", &item)
+}
+
+fn tag(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 8);
+    out.extend_from_slice(name);
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+pub async fn fetch_artwork(url: &str) -> Result<Vec<u8>, AirPlayError> {
+    // apple music artwork urls are templates
+    let url = url
+        .replace("{w}", &ARTWORK_SIZE.to_string())
+        .replace("{h}", &ARTWORK_SIZE.to_string())
+        .replace("{f}", "jpg");
+
+    let response = reqwest::get(&url)
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| AirPlayError::Metadata(e.to_string()))?;
+
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| AirPlayError::Metadata(e.to_string()))?;
+
+    if bytes.len() > MAX_ARTWORK_BYTES {
+        return Err(AirPlayError::Metadata(format!(
+            "artwork is {} bytes, more than receivers will take",
+            bytes.len()
+        )));
+    }
+
+    // receivers only take jpeg and png
+    if !(bytes.starts_with(&[0xff, 0xd8, 0xff]) || bytes.starts_with(b"\x89PNG")) {
+        return Err(AirPlayError::Metadata("artwork isn't a jpeg or png".into()));
+    }
+
+    Ok(bytes.to_vec())
+}
+
+// the webview sends the same timestamps as the discord presence, which can be either
+fn millis(timestamp: i64) -> i64 {
+    if timestamp < 100_000_000_000 {
+        timestamp * 1000
+    } else {
+        timestamp
+    }
+}
@@ -1,136 +1,1023 @@
-#![allow(unused)]
-
-use tokio::sync::mpsc::Receiver;
-
 use tauri::{
-    api::process::{Command, CommandChild, CommandEvent},
-    async_runtime::{JoinHandle, RwLock},
+    async_runtime::{JoinHandle, Mutex, RwLock},
     plugin::{Builder as PluginBuilder, TauriPlugin},
     AppHandle, Manager, Runtime,
 };
 
+mod discovery;
 pub mod error;
+mod metadata;
+mod output;
+mod raop;
+mod recorder;
+mod resample;
+mod rtsp;
+mod transport;
+use discovery::{AirPlayDevice, Device, Discovery};
 use error::AirPlayError;
+use metadata::NowPlaying;
+use output::{Output, OutputInfo, OutputStatus, StatusEvent, DEFAULT_OUTPUT, STATUS_EVENT};
+use raop::{Packetizer, StreamClock};
+use recorder::{Recorder, RecordingFormat, RecordingStatus};
+use resample::Resampler;
+use transport::{AudioMetrics, AudioRing, Endpoint};
 
-use std::convert::TryInto;
+use crate::dlna::DlnaClient;
 
-use base64::{
-    alphabet,
-    engine::{self, general_purpose},
-    Engine as _,
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use fon::chan::{Ch16, Ch32};
-use fon::Audio;
+
+use base64::{engine::general_purpose, Engine as _};
+
+// what the webview's audio context ran at before it could tell us
+const DEFAULT_SOURCE_RATE: u32 = 96_000;
+// how long receivers get to connect
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+// an output that keeps going away is given up on after this many restarts in a row
+const MAX_RESTARTS: u32 = 5;
+
+// NOTE(airplay outputs)
+//
+// every output is fed the same resampled 44.1 kHz stream. receivers picked with
+// `start_device(s)` are streamed to directly (see NOTE(raop)), `start_client` is the bundled
+// sidecar picking one by itself (see NOTE(airtunes sidecar)).
+//
+// receivers are all timed off the stream's clock, so any number of them play in step,
+// including ones added while it's running. outputs started together are still held back until
+// every one of them is connected, so none starts before the others. volume, mute and pause are
+// per output, receivers apply their own volume.
+//
+// audio is handed to each output's own thread, nothing waits on a receiver while the outputs
+// are locked. an output that goes away by itself is restarted with a growing delay, and goes
+// back to getting audio as soon as it's connected again. every status change is emitted as
+// `airplay-status`.
+//
+// the device commands also cover DLNA renderers (see NOTE(dlna)), which get the same
+// resampled stream. ids say which is which, a renderer's is its `uuid:...`.
 
 pub struct AirPlayClient {
-    airtunes_child: RwLock<(Option<CommandChild>, Option<JoinHandle<()>>)>,
+    outputs: RwLock<HashMap<String, Output>>,
+    // lives as long as the stream, a new one would start with a click
+    resampler: Mutex<Option<Resampler>>,
+    // the stream's clock, and the audio cut up for receivers
+    packetizer: Mutex<Option<Packetizer>>,
+    ring: Arc<AudioRing>,
+    // the binary endpoint and the task feeding the outputs from the ring
+    transport: Mutex<Option<(Endpoint, JoinHandle<()>)>>,
+    discovery: Mutex<Option<Discovery>>,
+    now_playing: Mutex<NowPlaying>,
+    // keeps the stream going with no outputs, see NOTE(recording)
+    recorder: Mutex<Option<Recorder>>,
+    dlna: DlnaClient,
 }
 
 impl AirPlayClient {
     pub fn new() -> Self {
         Self {
-            airtunes_child: RwLock::new((None, None)),
+            outputs: RwLock::new(HashMap::new()),
+            resampler: Mutex::new(None),
+            packetizer: Mutex::new(None),
+            ring: Arc::new(AudioRing::new()),
+            transport: Mutex::new(None),
+            discovery: Mutex::new(None),
+            now_playing: Mutex::new(NowPlaying::default()),
+            recorder: Mutex::new(None),
+            dlna: DlnaClient::new(),
+        }
+    }
+
+    /// starts browsing the first time it's asked, returns what's been found so far
+    pub async fn devices<R>(&self, handle: AppHandle<R>) -> Result<Vec<Device>, AirPlayError>
+    where
+        R: Runtime,
+    {
+        let mut devices: Vec<Device> = {
+            let mut discovery = self.discovery.lock().await;
+            if discovery.is_none() {
+                *discovery = Some(Discovery::start(handle.clone())?);
+            }
+
+            discovery
+                .as_ref()
+                .map(|d| d.devices())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| Device::AirPlay(Box::new(d)))
+                .collect()
+        };
+
+        // a network without UPnP shouldn't hide the AirPlay receivers
+        match self.dlna.renderers(handle).await {
+            Ok(renderers) => devices.extend(renderers.into_iter().map(Device::Dlna)),
+            Err(e) => println!("{}", e),
+        }
+
+        Ok(devices)
+    }
+
+    /// adds receivers from `devices` to the stream, starting them in step with each other.
+    /// `devices` is `(id, password)`, receivers that are already playing are left alone
+    pub async fn start_devices<R>(
+        &self,
+        handle: AppHandle<R>,
+        devices: Vec<(String, Option<String>)>,
+    ) -> Result<(), AirPlayError>
+    where
+        R: Runtime,
+    {
+        let mut targets = vec![];
+        let mut renderers = vec![];
+        {
+            let discovery = self.discovery.lock().await;
+            let outputs = self.outputs.read().await;
+
+            for (id, password) in devices {
+                if self.dlna.owns(&id).await {
+                    renderers.push(id);
+                    continue;
+                }
+
+                if outputs.contains_key(&id) {
+                    continue;
+                }
+
+                let device = discovery
+                    .as_ref()
+                    .and_then(|d| d.device(&id))
+                    .ok_or_else(|| AirPlayError::Device(format!("no receiver named {}", id)))?;
+
+                if device.capabilities.password && password.is_none() {
+                    return Err(AirPlayError::Device(format!(
+                        "{} needs a password",
+                        device.name
+                    )));
+                }
+
+                targets.push((id, Some(device), password));
+            }
+        }
+
+        // renderers buffer too much to be started in step, they just join
+        if !renderers.is_empty() {
+            self.ensure_session(handle.clone()).await?;
+            for id in &renderers {
+                if let Err(e) = self.dlna.start(id).await {
+                    self.stop_if_idle().await;
+                    return Err(e.into());
+                }
+            }
+        }
+
+        self.start_outputs(handle, targets).await
+    }
+
+    pub async fn stop_device(&self, id: &str) {
+        if self.dlna.owns(id).await {
+            self.dlna.stop(id).await;
+        } else if let Some(output) = self.outputs.write().await.remove(id) {
+            output.kill();
+        }
+
+        self.stop_if_idle().await;
+    }
+
+    pub async fn set_volume(&self, id: &str, volume: f32) -> Result<(), AirPlayError> {
+        if self.dlna.owns(id).await {
+            return Ok(self.dlna.set_volume(id, volume).await?);
+        }
+
+        self.with_output(id, |o| o.set_volume(volume)).await
+    }
+
+    pub async fn set_muted(&self, id: &str, muted: bool) -> Result<(), AirPlayError> {
+        if self.dlna.owns(id).await {
+            return Ok(self.dlna.set_muted(id, muted).await?);
         }
+
+        self.with_output(id, |o| o.set_muted(muted)).await
+    }
+
+    pub async fn set_paused(&self, id: &str, paused: bool) -> Result<(), AirPlayError> {
+        if self.dlna.owns(id).await {
+            return Ok(self.dlna.set_paused(id, paused).await?);
+        }
+
+        self.with_output(id, |o| o.set_paused(paused)).await
+    }
+
+    pub async fn outputs(&self) -> Vec<OutputInfo> {
+        let mut outputs: Vec<OutputInfo> = self
+            .outputs
+            .read()
+            .await
+            .iter()
+            .map(|(id, o)| o.info(id))
+            .collect();
+
+        outputs.extend(
+            self.dlna
+                .outputs()
+                .await
+                .into_iter()
+                .map(|(id, p)| OutputInfo {
+                    id,
+                    status: if p.paused {
+                        OutputStatus::Ready
+                    } else {
+                        OutputStatus::Streaming
+                    },
+                    volume: p.volume,
+                    muted: p.muted,
+                    paused: p.paused,
+                    device: Some(Device::Dlna(p.renderer)),
+                }),
+        );
+
+        outputs.sort_by(|a, b| a.id.cmp(&b.id));
+        outputs
+    }
+
+    /// queues interleaved stereo f32 at `sample_rate`, dropped if the client isn't running
+    pub fn send_audio(&self, audio: Vec<u8>, sample_rate: u32) -> Result<(), AirPlayError> {
+        self.ring.push(audio, sample_rate)
+    }
+
+    pub fn metrics(&self) -> AudioMetrics {
+        self.ring.metrics()
+    }
+
+    pub async fn audio_endpoint(&self) -> Option<String> {
+        self.transport
+            .lock()
+            .await
+            .as_ref()
+            .map(|(endpoint, _)| endpoint.url.clone())
     }
 
-    pub async fn send_audio(&self, audio: String) {
-        let mut lock = self.airtunes_child.write().await;
+    async fn with_output(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut Output) -> Result<(), AirPlayError>,
+    ) -> Result<(), AirPlayError> {
+        let mut outputs = self.outputs.write().await;
+        let output = outputs
+            .get_mut(id)
+            .ok_or_else(|| AirPlayError::Device(format!("{} isn't playing", id)))?;
+        f(output)
+    }
 
-        let c = &mut lock.0;
+    async fn write_audio(&self, audio: &[u8], sample_rate: u32) -> Result<(), AirPlayError> {
+        // copied out, nothing below holds the outputs while it writes
+        let writers: Vec<_> = self
+            .outputs
+            .read()
+            .await
+            .iter()
+            .filter(|(_, o)| o.live && !o.paused && !o.failed)
+            .map(|(id, o)| (id.clone(), o.writer()))
+            .collect();
 
-        if let Some(client) = c {
-            let orig_bytes = general_purpose::STANDARD.decode(audio).unwrap();
+        let recording = self.recorder.lock().await.is_some();
+        if writers.is_empty() && !recording && !self.dlna.is_playing().await {
+            return Ok(());
+        }
 
-            let mut audio0 = Vec::new();
-            for sample in orig_bytes.chunks(4) {
-                audio0.push(f32::from_le_bytes(sample.try_into().unwrap()));
+        let bytes = {
+            let mut resampler = self.resampler.lock().await;
+            if resampler.as_ref().map(|r| r.source_rate()) != Some(sample_rate) {
+                *resampler = Some(Resampler::new(sample_rate)?);
             }
-            let audio1 = Audio::<Ch32, 2>::with_f32_buffer(96000, audio0);
-            // Stream resampler into new audio type.
-            let mut audio2 = Audio::<Ch16, 2>::with_audio(44100, &audio1);
-            // Write file as i16 buffer.
-            let mut bytes = Vec::new();
-            for sample in audio2.as_i16_slice() {
-                bytes.extend(sample.to_le_bytes());
+
+            resampler
+                .as_mut()
+                .expect("resampler was just created")
+                .process(audio)?
+        };
+
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(r) = self.recorder.lock().await.as_mut() {
+            if let Err(e) = r.write(&bytes) {
+                println!("{}", e);
             }
-            client.write(&bytes).unwrap();
         }
+
+        self.dlna.write(&bytes).await;
+
+        // cut up once for every receiver, after a gap the packetizer catches up with the clock
+        let packets = Arc::new(match self.packetizer.lock().await.as_mut() {
+            Some(packetizer) => packetizer.push(&bytes),
+            None => vec![],
+        });
+
+        // one output going away shouldn't stop the others, it'll be restarted when it exits
+        let mut failed = vec![];
+        for (id, writer) in writers {
+            if let Err(e) = writer.write(&bytes, &packets) {
+                println!("Pausing AirPlay output {}, {}", id, e);
+                failed.push(id);
+            }
+        }
+
+        if !failed.is_empty() {
+            let mut outputs = self.outputs.write().await;
+            for id in failed {
+                if let Some(output) = outputs.get_mut(&id) {
+                    output.failed = true;
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn start_client(&self) -> Result<(), String> {
-        let mut lock = self.airtunes_child.write().await;
-        if lock.0.is_some() {
+    pub async fn start_client<R>(&self, handle: AppHandle<R>) -> Result<(), String>
+    where
+        R: Runtime,
+    {
+        if !self.outputs.read().await.is_empty() {
             return Ok(());
         }
 
-        match Command::new_sidecar("airtunes2") {
-            Ok(mut airtunes) => {
-                let (mut rx, mut child) = airtunes.spawn().expect("Failed to spawn sidecar");
-                let join_handle = tauri::async_runtime::spawn(async move {
-                    // read events such as stdout
-                    while let Some(event) = rx.recv().await {
-                        if let CommandEvent::Stdout(message) = event {
-                            println!("{}", message);
+        self.start_outputs(handle, vec![(DEFAULT_OUTPUT.to_string(), None, None)])
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn start_outputs<R>(
+        &self,
+        handle: AppHandle<R>,
+        targets: Vec<(String, Option<AirPlayDevice>, Option<String>)>,
+    ) -> Result<(), AirPlayError>
+    where
+        R: Runtime,
+    {
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        // receivers joining a running stream are timed off the clock it already has
+        let clock = self.ensure_session(handle.clone()).await?;
+
+        let mut started = vec![];
+        for (id, device, password) in targets {
+            match Output::spawn(handle.clone(), &id, device, password, clock.clone(), 0) {
+                Ok(output) => {
+                    self.outputs.write().await.insert(id.clone(), output);
+                    started.push(id);
+                }
+                Err(e) => {
+                    // all or nothing, a half-started group isn't what was asked for
+                    self.remove_outputs(&started).await;
+                    self.stop_if_idle().await;
+                    return Err(e);
+                }
+            }
+        }
+
+        let waiting: Vec<String> = {
+            let outputs = self.outputs.read().await;
+            started
+                .iter()
+                .filter(|id| {
+                    outputs
+                        .get(*id)
+                        .map(|o| o.waits_for_connection())
+                        .unwrap_or(false)
+                })
+                .cloned()
+                .collect()
+        };
+
+        if let Err(e) = self.wait_until_connected(&waiting).await {
+            self.remove_outputs(&started).await;
+            self.stop_if_idle().await;
+            return Err(e);
+        }
+
+        let mut outputs = self.outputs.write().await;
+        for id in started {
+            if let Some(output) = outputs.get_mut(&id) {
+                output.live = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn wait_until_connected(&self, ids: &[String]) -> Result<(), AirPlayError> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+
+        loop {
+            {
+                let outputs = self.outputs.read().await;
+                let mut connected = true;
+
+                for id in ids {
+                    match outputs.get(id).map(|o| &o.status) {
+                        Some(OutputStatus::Error(e)) => {
+                            return Err(AirPlayError::Device(format!("{}: {}", id, e)))
+                        }
+                        Some(OutputStatus::DeviceLost) | None => {
+                            return Err(AirPlayError::Device(format!("{} went away", id)))
                         }
-                        // else if let CommandEvent::Stderr(message) = event {
-                        //     println!("{}", message);
-                        // } else if let CommandEvent::Error(message) = event {
-                        //     println!("{}", message);
-                        // }
+                        Some(status) => connected &= status.connected(),
                     }
-                });
+                }
+
+                if connected {
+                    return Ok(());
+                }
+            }
 
-                *lock = (Some(child), Some(join_handle));
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                Ok(())
+            if Instant::now() > deadline {
+                return Err(AirPlayError::Device(
+                    "timed out waiting for the receivers to connect".into(),
+                ));
             }
 
-            Err(e) => Err(e.to_string()),
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn set_status<R>(
+        &self,
+        handle: &AppHandle<R>,
+        id: &str,
+        generation: u64,
+        status: OutputStatus,
+    ) where
+        R: Runtime,
+    {
+        {
+            let mut outputs = self.outputs.write().await;
+            let output = match outputs.get_mut(id) {
+                Some(o) if o.generation == generation => o,
+                _ => return,
+            };
+
+            if output.status == status {
+                return;
+            }
+
+            let was_connected = output.status.connected();
+            output.status = status.clone();
+            if output.rejoin && status.connected() {
+                output.rejoin = false;
+                output.restarts = 0;
+                output.live = true;
+            }
+
+            // otherwise its display stays blank until the next track
+            if status.connected() && !was_connected {
+                let now_playing = self.now_playing.lock().await.clone();
+                if let Err(e) = output.send_now_playing(&now_playing) {
+                    println!("Unable to send metadata to {}, {}", id, e);
+                }
+            }
+        }
+
+        handle
+            .emit_all(
+                STATUS_EVENT,
+                StatusEvent {
+                    id: id.to_string(),
+                    status,
+                },
+            )
+            .ok();
+    }
+
+    async fn output_exited<R>(&self, handle: AppHandle<R>, id: &str, generation: u64)
+    where
+        R: Runtime,
+    {
+        let (device, password, restarts) = {
+            let mut outputs = self.outputs.write().await;
+            let output = match outputs.get_mut(id) {
+                Some(o) if o.generation == generation => o,
+                _ => return,
+            };
+
+            // still starting up, `wait_until_connected` reports it
+            if !output.live && !output.rejoin {
+                if !matches!(output.status, OutputStatus::Error(_)) {
+                    output.status = OutputStatus::Error("exited before connecting".into());
+                }
+                return;
+            }
+
+            output.live = false;
+            (
+                output.device.clone(),
+                output.password.clone(),
+                output.restarts,
+            )
+        };
+
+        if restarts >= MAX_RESTARTS {
+            self.outputs.write().await.remove(id);
+            let message = format!("kept going away, gave up after {} restarts", restarts);
+            handle
+                .emit_all(
+                    STATUS_EVENT,
+                    StatusEvent {
+                        id: id.to_string(),
+                        status: OutputStatus::Error(message),
+                    },
+                )
+                .ok();
+
+            self.stop_if_idle().await;
+            return;
+        }
+
+        self.set_status(&handle, id, generation, OutputStatus::Restarting)
+            .await;
+        tokio::time::sleep(Duration::from_secs(1 << restarts)).await;
+
+        // gone with the stream, there's nothing to restart into
+        let clock = match self.packetizer.lock().await.as_ref() {
+            Some(packetizer) => packetizer.clock(),
+            None => return,
+        };
+        let restarted = Output::spawn(handle.clone(), id, device, password, clock, generation + 1);
+
+        let mut outputs = self.outputs.write().await;
+        // stopped while we were waiting
+        let previous = match outputs.get(id) {
+            Some(o) if o.generation == generation => o,
+            _ => {
+                if let Ok(output) = restarted {
+                    output.kill();
+                }
+                return;
+            }
+        };
+
+        let restarted = restarted.and_then(|mut output| {
+            output.set_volume(previous.volume)?;
+            output.set_muted(previous.muted)?;
+            output.set_paused(previous.paused)?;
+            output.restarts = restarts + 1;
+            // the sidecar won't say when it's connected, it goes live straight away
+            output.live = !output.waits_for_connection();
+            output.rejoin = !output.live;
+            Ok(output)
+        });
+
+        match restarted {
+            Ok(output) => {
+                let status = output.status.clone();
+                // the old one is already gone, there's nothing to kill
+                outputs.insert(id.to_string(), output);
+                drop(outputs);
+
+                handle
+                    .emit_all(
+                        STATUS_EVENT,
+                        StatusEvent {
+                            id: id.to_string(),
+                            status,
+                        },
+                    )
+                    .ok();
+            }
+            Err(e) => {
+                outputs.remove(id);
+                drop(outputs);
+
+                handle
+                    .emit_all(
+                        STATUS_EVENT,
+                        StatusEvent {
+                            id: id.to_string(),
+                            status: OutputStatus::Error(e.to_string()),
+                        },
+                    )
+                    .ok();
+            }
+        }
+    }
+
+    async fn remove_outputs(&self, ids: &[String]) {
+        let mut outputs = self.outputs.write().await;
+        for id in ids {
+            if let Some(output) = outputs.remove(id) {
+                output.kill();
+            }
+        }
+    }
+
+    // the ring, binary endpoint, writer and clock are shared by every output
+    async fn ensure_session<R>(
+        &self,
+        handle: AppHandle<R>,
+    ) -> Result<Arc<StreamClock>, AirPlayError>
+    where
+        R: Runtime,
+    {
+        let mut transport = self.transport.lock().await;
+        let clock = self
+            .packetizer
+            .lock()
+            .await
+            .get_or_insert_with(|| Packetizer::new(Arc::new(StreamClock::new())))
+            .clock();
+        if transport.is_some() {
+            return Ok(clock);
+        }
+
+        *self.resampler.lock().await = None;
+        self.ring.open();
+
+        let endpoint = transport::serve(self.ring.clone(), DEFAULT_SOURCE_RATE)?;
+        let writer = tauri::async_runtime::spawn(async move {
+            let client = handle.state::<AirPlayClient>();
+            while let Some(chunk) = client.ring.pop().await {
+                match client.write_audio(&chunk.data, chunk.sample_rate).await {
+                    Ok(_) => client.ring.written(chunk.data.len()),
+                    Err(e) => println!("{}", e),
+                }
+            }
+        });
+        *transport = Some((endpoint, writer));
+
+        Ok(clock)
+    }
+
+    /// sends the track's metadata, cover art and progress to every connected receiver
+    pub async fn send_metadata(
+        &self,
+        title: &str,
+        artist: &str,
+        album: &str,
+        artwork_url: &str,
+        start: i64,
+        end: i64,
+    ) {
+        let previous = self.now_playing.lock().await.clone();
+
+        // progress updates come a lot more often than new tracks
+        let artwork = if artwork_url == previous.artwork_url {
+            previous.artwork
+        } else if artwork_url.is_empty() {
+            None
+        } else {
+            match metadata::fetch_artwork(artwork_url).await {
+                Ok(artwork) => Some(artwork),
+                Err(e) => {
+                    println!("{}", e);
+                    None
+                }
+            }
+        };
+
+        let now_playing = NowPlaying {
+            daap: metadata::daap(title, artist, album),
+            artwork_url: artwork_url.to_string(),
+            artwork,
+            start,
+            end,
+        };
+        *self.now_playing.lock().await = now_playing.clone();
+
+        if now_playing.daap != previous.daap {
+            let track = [artist, title]
+                .iter()
+                .filter(|s| !s.is_empty())
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" - ");
+            self.split_recording(&track).await;
+        }
+
+        for (id, output) in self.outputs.read().await.iter() {
+            if !output.status.connected() {
+                continue;
+            }
+
+            if let Err(e) = output.send_now_playing(&now_playing) {
+                println!("Unable to send metadata to {}, {}", id, e);
+            }
+        }
+    }
+
+    pub async fn start_recording<R>(
+        &self,
+        handle: AppHandle<R>,
+        dir: PathBuf,
+        format: RecordingFormat,
+    ) -> Result<(), AirPlayError>
+    where
+        R: Runtime,
+    {
+        {
+            let mut recorder = self.recorder.lock().await;
+            if recorder.is_some() {
+                return Err(AirPlayError::Recording("already recording".into()));
+            }
+            *recorder = Some(Recorder::start(dir, format)?);
+        }
+
+        self.ensure_session(handle).await.map(|_| ())
+    }
+
+    /// returns everything that was recorded, once the last track has been written out
+    pub async fn stop_recording(&self) -> Result<Option<RecordingStatus>, AirPlayError> {
+        let recorder = self.recorder.lock().await.take();
+        self.stop_if_idle().await;
+
+        let (status, pending) = match recorder {
+            Some(recorder) => recorder.finish()?,
+            None => return Ok(None),
+        };
+
+        if let Some(wav) = pending {
+            tauri::async_runtime::spawn_blocking(move || recorder::to_flac(&wav))
+                .await
+                .map_err(|e| AirPlayError::Recording(e.to_string()))??;
+        }
+
+        Ok(Some(status))
+    }
+
+    pub async fn recording_status(&self) -> Option<RecordingStatus> {
+        self.recorder.lock().await.as_ref().map(|r| r.status())
+    }
+
+    async fn split_recording(&self, track: &str) {
+        let pending = match self.recorder.lock().await.as_mut().map(|r| r.split(track)) {
+            Some(Ok(pending)) => pending,
+            Some(Err(e)) => {
+                println!("{}", e);
+                None
+            }
+            None => None,
+        };
+
+        if let Some(wav) = pending {
+            tauri::async_runtime::spawn_blocking(move || {
+                if let Err(e) = recorder::to_flac(&wav) {
+                    println!("{}", e);
+                }
+            });
         }
     }
 
     pub async fn stop_client(&self) {
-        let mut lock = self.airtunes_child.write().await;
+        let outputs = std::mem::take(&mut *self.outputs.write().await);
+        for (_, output) in outputs {
+            output.kill();
+        }
+        self.dlna.stop_all().await;
 
-        // std::mem::take replaced with defaults (`None` in this case) so we don't need to set
-        let c = std::mem::take(&mut lock.0);
-        let j = std::mem::take(&mut lock.1);
+        self.stop_if_idle().await;
+    }
 
-        if let Some(c) = c {
-            c.kill().ok();
+    // the stream keeps going while anything is still listening to it
+    async fn stop_if_idle(&self) {
+        if !self.outputs.read().await.is_empty()
+            || self.recorder.lock().await.is_some()
+            || self.dlna.is_playing().await
+        {
+            return;
         }
 
-        if let Some(j) = j {
-            j.abort();
+        self.ring.close();
+        // dropping the endpoint stops it listening
+        if let Some((_, writer)) = self.transport.lock().await.take() {
+            writer.abort();
         }
+        *self.resampler.lock().await = None;
+        *self.packetizer.lock().await = None;
     }
 }
 
+/// `start` and `end` are when the track started and will end, since the epoch in seconds or ms.
+/// takes the names the discord presence uses too, `details` is the title, `state` the artist,
+/// `large_image_text` the album and `artwork` the artwork url
 #[tauri::command]
-fn send_query<R>(
+#[allow(clippy::too_many_arguments)]
+async fn send_query<R>(
     handle: AppHandle<R>,
-    state: String,
-    details: String,
-    artwork: String,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    artwork_url: Option<String>,
+    details: Option<String>,
+    state: Option<String>,
+    large_image_text: Option<String>,
+    artwork: Option<String>,
     start: i64,
     end: i64,
-    large_image_text: String,
 ) where
     R: Runtime,
 {
+    let title = title.or(details).unwrap_or_default();
+    let artist = artist.or(state).unwrap_or_default();
+    let album = album.or(large_image_text).unwrap_or_default();
+    let artwork_url = artwork_url.or(artwork).unwrap_or_default();
+
+    let client = handle.state::<AirPlayClient>();
+    client
+        .send_metadata(&title, &artist, &album, &artwork_url, start, end)
+        .await;
+}
+
+#[tauri::command]
+async fn send_audio<R>(
+    handle: AppHandle<R>,
+    state: String,
+    sample_rate: Option<u32>,
+) -> Result<(), AirPlayError>
+where
+    R: Runtime,
+{
+    let audio = general_purpose::STANDARD
+        .decode(state)
+        .map_err(|e| AirPlayError::Audio(e.to_string()))?;
+
+    let client = handle.state::<AirPlayClient>();
+    client.send_audio(audio, sample_rate.unwrap_or(DEFAULT_SOURCE_RATE))
+}
+
+/// where to send raw PCM instead of going through `send_audio`, while the client is running
+#[tauri::command]
+async fn audio_endpoint<R>(handle: AppHandle<R>) -> Option<String>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.audio_endpoint().await
+}
+
+#[tauri::command]
+fn audio_metrics<R>(handle: AppHandle<R>) -> AudioMetrics
+where
+    R: Runtime,
+{
+    handle.state::<AirPlayClient>().metrics()
+}
+
+#[tauri::command]
+async fn list_devices<R>(handle: AppHandle<R>) -> Result<Vec<Device>, AirPlayError>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.devices(handle.clone()).await
+}
+
+#[tauri::command]
+async fn list_outputs<R>(handle: AppHandle<R>) -> Vec<OutputInfo>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.outputs().await
+}
+
+/// adds a receiver to the stream, the others keep playing
+#[tauri::command]
+async fn start_device<R>(
+    handle: AppHandle<R>,
+    id: String,
+    password: Option<String>,
+) -> Result<(), AirPlayError>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client
+        .start_devices(handle.clone(), vec![(id, password)])
+        .await
+}
+
+#[derive(serde::Deserialize)]
+struct DeviceRequest {
+    id: String,
+    password: Option<String>,
+}
+
+/// starts several receivers in step with each other
+#[tauri::command]
+async fn start_devices<R>(
+    handle: AppHandle<R>,
+    devices: Vec<DeviceRequest>,
+) -> Result<(), AirPlayError>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    let devices = devices.into_iter().map(|d| (d.id, d.password)).collect();
+    client.start_devices(handle.clone(), devices).await
 }
 
 #[tauri::command]
-async fn send_audio<R>(handle: AppHandle<R>, state: String)
+async fn stop_device<R>(handle: AppHandle<R>, id: String)
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.stop_device(&id).await;
+}
+
+/// `volume` is 0 to 1
+#[tauri::command]
+async fn set_device_volume<R>(
+    handle: AppHandle<R>,
+    id: String,
+    volume: f32,
+) -> Result<(), AirPlayError>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.set_volume(&id, volume).await
+}
+
+#[tauri::command]
+async fn set_device_muted<R>(
+    handle: AppHandle<R>,
+    id: String,
+    muted: bool,
+) -> Result<(), AirPlayError>
 where
     R: Runtime,
 {
-    tauri::async_runtime::spawn(async move {
-        let client = handle.state::<AirPlayClient>();
-        client.send_audio(state).await;
-    })
-    .await
-    .unwrap();
+    let client = handle.state::<AirPlayClient>();
+    client.set_muted(&id, muted).await
+}
+
+/// a paused receiver stays connected, it just isn't sent anything until it's resumed
+#[tauri::command]
+async fn set_device_paused<R>(
+    handle: AppHandle<R>,
+    id: String,
+    paused: bool,
+) -> Result<(), AirPlayError>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.set_paused(&id, paused).await
+}
+
+/// records the stream into `dir`, or a new folder under the app's data dir
+#[tauri::command]
+async fn start_recording<R>(
+    handle: AppHandle<R>,
+    dir: Option<PathBuf>,
+    format: Option<RecordingFormat>,
+) -> Result<PathBuf, AirPlayError>
+where
+    R: Runtime,
+{
+    let dir = match dir {
+        Some(dir) => dir,
+        None => handle
+            .path_resolver()
+            .app_data_dir()
+            .ok_or_else(|| AirPlayError::Recording("Unknown Application Data Dir".into()))?
+            .join("recordings")
+            .join(chrono::Local::now().format("%Y-%m-%d %H-%M-%S").to_string()),
+    };
+
+    let client = handle.state::<AirPlayClient>();
+    client
+        .start_recording(
+            handle.clone(),
+            dir.clone(),
+            format.unwrap_or(RecordingFormat::Wav),
+        )
+        .await?;
+
+    Ok(dir)
+}
+
+#[tauri::command]
+async fn stop_recording<R>(handle: AppHandle<R>) -> Result<Option<RecordingStatus>, AirPlayError>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.stop_recording().await
+}
+
+#[tauri::command]
+async fn recording_status<R>(handle: AppHandle<R>) -> Option<RecordingStatus>
+where
+    R: Runtime,
+{
+    let client = handle.state::<AirPlayClient>();
+    client.recording_status().await
 }
 
 #[tauri::command]
@@ -139,7 +1026,7 @@ where
     R: Runtime,
 {
     let client = handle.state::<AirPlayClient>();
-    client.start_client().await
+    client.start_client(handle.clone()).await
 }
 
 #[tauri::command]
@@ -163,8 +1050,21 @@ where
         .invoke_handler(tauri::generate_handler![
             send_query,
             send_audio,
+            audio_endpoint,
+            audio_metrics,
             start_client,
-            stop_client
+            stop_client,
+            list_devices,
+            list_outputs,
+            start_device,
+            start_devices,
+            stop_device,
+            set_device_volume,
+            set_device_muted,
+            set_device_paused,
+            start_recording,
+            stop_recording,
+            recording_status
         ])
         .build()
 }
@@ -0,0 +1,360 @@
+use std::sync::{
+    mpsc::{self, SyncSender, TrySendError},
+    Arc,
+};
+
+use tauri::{
+    api::process::{Command, CommandEvent},
+    async_runtime::JoinHandle,
+    AppHandle, Manager, Runtime,
+};
+
+use super::{
+    discovery::{AirPlayDevice, Device},
+    error::AirPlayError,
+    metadata::NowPlaying,
+    raop::{Packets, Session, StreamClock},
+    AirPlayClient,
+};
+
+// the sidecar picking a receiver itself, what `start_client` has always done
+pub const DEFAULT_OUTPUT: &str = "default";
+pub const STATUS_EVENT: &str = "airplay-status";
+
+// about a second of audio, a sidecar further behind than that gets chunks dropped
+const SIDECAR_QUEUE: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "state", content = "message", rename_all = "snake_case")]
+pub enum OutputStatus {
+    Connecting,
+    // connected, waiting for audio
+    Ready,
+    Streaming,
+    Restarting,
+    DeviceLost,
+    Error(String),
+}
+
+impl OutputStatus {
+    pub fn connected(&self) -> bool {
+        matches!(self, OutputStatus::Ready | OutputStatus::Streaming)
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct StatusEvent {
+    pub id: String,
+    pub status: OutputStatus,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct OutputInfo {
+    pub id: String,
+    pub device: Option<Device>,
+    pub status: OutputStatus,
+    pub volume: f32,
+    pub muted: bool,
+    pub paused: bool,
+}
+
+enum Sink {
+    // the bundled airtunes2, fed raw PCM by its own thread
+    Sidecar {
+        audio: SyncSender<Vec<u8>>,
+        events: JoinHandle<()>,
+    },
+    Raop(Session),
+}
+
+/// what the audio writer needs from an output, so the outputs aren't locked while it sends
+pub enum Writer {
+    Sidecar {
+        audio: SyncSender<Vec<u8>>,
+        gain: f32,
+    },
+    Raop(Session),
+}
+
+impl Writer {
+    /// `pcm` is 44.1 kHz stereo i16 for the sidecar, `packets` the same audio for receivers.
+    /// neither waits, an error means the output has stopped taking audio
+    pub fn write(&self, pcm: &[u8], packets: &Packets) -> Result<(), AirPlayError> {
+        match self {
+            Writer::Sidecar { audio, gain } => {
+                let pcm = if *gain >= 1.0 {
+                    pcm.to_vec()
+                } else {
+                    pcm.chunks_exact(2)
+                        .flat_map(|s| {
+                            let sample = i16::from_le_bytes([s[0], s[1]]) as f32 * gain;
+                            (sample as i16).to_le_bytes()
+                        })
+                        .collect()
+                };
+
+                match audio.try_send(pcm) {
+                    Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+                    Err(TrySendError::Disconnected(_)) => {
+                        Err(AirPlayError::Write("airtunes stopped taking audio".into()))
+                    }
+                }
+            }
+            Writer::Raop(session) if !packets.is_empty() => session.send_audio(packets.clone()),
+            Writer::Raop(_) => Ok(()),
+        }
+    }
+}
+
+/// one receiver being streamed to, or the sidecar for `DEFAULT_OUTPUT`
+pub struct Output {
+    pub device: Option<AirPlayDevice>,
+    pub password: Option<String>,
+    sink: Sink,
+    pub volume: f32,
+    pub muted: bool,
+    // stays connected, just isn't sent any audio
+    pub paused: bool,
+    pub status: OutputStatus,
+    // outputs started together only get audio once all of them are up
+    pub live: bool,
+    // stopped taking audio, it's restarted once it has exited
+    pub failed: bool,
+    // bumped on every restart, so a late exit from an old sidecar or session is ignored
+    pub generation: u64,
+    pub restarts: u32,
+    // a restarted output goes live by itself once it's connected again
+    pub rejoin: bool,
+}
+
+impl Output {
+    /// a receiver is connected to in the background, `DEFAULT_OUTPUT` is the sidecar
+    pub fn spawn<R>(
+        handle: AppHandle<R>,
+        id: &str,
+        device: Option<AirPlayDevice>,
+        password: Option<String>,
+        clock: Arc<StreamClock>,
+        generation: u64,
+    ) -> Result<Self, AirPlayError>
+    where
+        R: Runtime,
+    {
+        let sink = match &device {
+            Some(device) => Sink::Raop(start_session(
+                handle,
+                id,
+                device.clone(),
+                password.clone(),
+                clock,
+                generation,
+            )?),
+            None => start_sidecar(handle, id, generation)?,
+        };
+
+        Ok(Self {
+            device,
+            password,
+            sink,
+            volume: 1.0,
+            muted: false,
+            paused: false,
+            status: OutputStatus::Connecting,
+            live: false,
+            failed: false,
+            generation,
+            restarts: 0,
+            rejoin: false,
+        })
+    }
+
+    /// receivers say when they're connected. the sidecar never does, it's given audio straight
+    /// away and shows as connecting until the first of it has been written
+    pub fn waits_for_connection(&self) -> bool {
+        matches!(self.sink, Sink::Raop(_))
+    }
+
+    pub fn info(&self, id: &str) -> OutputInfo {
+        OutputInfo {
+            id: id.to_string(),
+            device: self.device.clone().map(|d| Device::AirPlay(Box::new(d))),
+            status: self.status.clone(),
+            volume: self.volume,
+            muted: self.muted,
+            paused: self.paused,
+        }
+    }
+
+    pub fn writer(&self) -> Writer {
+        match &self.sink {
+            Sink::Sidecar { audio, .. } => Writer::Sidecar {
+                audio: audio.clone(),
+                gain: if self.muted { 0.0 } else { self.volume },
+            },
+            Sink::Raop(session) => Writer::Raop(session.clone()),
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) -> Result<(), AirPlayError> {
+        self.volume = if volume.is_finite() {
+            volume.clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        self.apply_volume()
+    }
+
+    pub fn set_muted(&mut self, muted: bool) -> Result<(), AirPlayError> {
+        self.muted = muted;
+        self.apply_volume()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) -> Result<(), AirPlayError> {
+        self.paused = paused;
+        match &self.sink {
+            Sink::Raop(session) => session.set_paused(paused),
+            Sink::Sidecar { .. } => Ok(()),
+        }
+    }
+
+    // receivers change their own volume, the sidecar's audio is scaled by `writer`
+    fn apply_volume(&self) -> Result<(), AirPlayError> {
+        match &self.sink {
+            Sink::Raop(session) => session.set_volume(self.gain()),
+            Sink::Sidecar { .. } => Ok(()),
+        }
+    }
+
+    // `None` is muted
+    fn gain(&self) -> Option<f32> {
+        Some(self.volume).filter(|_| !self.muted)
+    }
+
+    /// everything a receiver's display shows. the sidecar has no way to take it
+    pub fn send_now_playing(&self, now_playing: &NowPlaying) -> Result<(), AirPlayError> {
+        match &self.sink {
+            Sink::Raop(session) => session.send_now_playing(now_playing.clone()),
+            Sink::Sidecar { .. } => Ok(()),
+        }
+    }
+
+    pub fn kill(self) {
+        match self.sink {
+            Sink::Sidecar { audio, events } => {
+                // first, so the exit isn't taken for a crash. the writer kills it once the
+                // channel is gone
+                events.abort();
+                drop(audio);
+            }
+            Sink::Raop(session) => session.stop(),
+        }
+    }
+}
+
+fn start_session<R>(
+    handle: AppHandle<R>,
+    id: &str,
+    device: AirPlayDevice,
+    password: Option<String>,
+    clock: Arc<StreamClock>,
+    generation: u64,
+) -> Result<Session, AirPlayError>
+where
+    R: Runtime,
+{
+    let (status_handle, status_id) = (handle.clone(), id.to_string());
+    let id = id.to_string();
+
+    Session::start(
+        device,
+        password,
+        clock,
+        Some(1.0),
+        // on the session's own thread, waiting keeps the statuses in order
+        move |status| {
+            let client = status_handle.state::<AirPlayClient>();
+            tauri::async_runtime::block_on(client.set_status(
+                &status_handle,
+                &status_id,
+                generation,
+                status,
+            ));
+        },
+        move || {
+            tauri::async_runtime::spawn(async move {
+                let client = handle.state::<AirPlayClient>();
+                client.output_exited(handle.clone(), &id, generation).await;
+            });
+        },
+    )
+}
+
+// NOTE(airtunes sidecar)
+//
+// the bundled airtunes2 takes no arguments, picks a receiver itself and plays raw 44.1 kHz
+// stereo i16 from stdin. it only prints human readable logs, so the only sign it's working
+// is stdin taking the audio: it's `Connecting` until the first write goes through, then
+// `Streaming`, and `DeviceLost` once a write fails, after which it's killed and restarted.
+fn start_sidecar<R>(handle: AppHandle<R>, id: &str, generation: u64) -> Result<Sink, AirPlayError>
+where
+    R: Runtime,
+{
+    let (mut rx, mut child) = Command::new_sidecar("airtunes2")
+        .map_err(|e| AirPlayError::Init(e.to_string()))?
+        .spawn()
+        .map_err(|e| AirPlayError::Init(e.to_string()))?;
+
+    let (audio, pcm) = mpsc::sync_channel::<Vec<u8>>(SIDECAR_QUEUE);
+
+    let (writer_handle, writer_id) = (handle.clone(), id.to_string());
+    tauri::async_runtime::spawn_blocking(move || {
+        let set_status = |status| {
+            let client = writer_handle.state::<AirPlayClient>();
+            tauri::async_runtime::block_on(client.set_status(
+                &writer_handle,
+                &writer_id,
+                generation,
+                status,
+            ));
+        };
+
+        let mut streaming = false;
+        while let Ok(pcm) = pcm.recv() {
+            match child.write(&pcm) {
+                Ok(()) if !streaming => {
+                    streaming = true;
+                    set_status(OutputStatus::Streaming);
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    println!("Unable to write to airtunes, {}", e);
+                    set_status(OutputStatus::DeviceLost);
+                    break;
+                }
+            }
+        }
+
+        // either it's being stopped or it stopped taking audio
+        child.kill().ok();
+    });
+
+    let id = id.to_string();
+    let events = tauri::async_runtime::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            match event {
+                CommandEvent::Stdout(line) | CommandEvent::Stderr(line) => {
+                    println!("[airtunes:{}] {}", id, line)
+                }
+                CommandEvent::Error(e) => println!("[airtunes:{}] {}", id, e),
+                CommandEvent::Terminated(_) => break,
+                _ => {}
+            }
+        }
+
+        // killing it on purpose aborts this task first, so getting here means it died
+        let client = handle.state::<AirPlayClient>();
+        client.output_exited(handle.clone(), &id, generation).await;
+    });
+
+    Ok(Sink::Sidecar { audio, events })
+}
@@ -0,0 +1,712 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    discovery::AirPlayDevice, error::AirPlayError, metadata::NowPlaying, output::OutputStatus,
+    resample::TARGET_RATE, rtsp::Rtsp,
+};
+
+// NOTE(raop)
+//
+// AirPlay 1 (RAOP) receivers are streamed to from here, one `Session` per receiver:
+//
+//   RTSP    OPTIONS, ANNOUNCE (SDP for 44.1 kHz 16 bit stereo ALAC), SETUP, RECORD, then
+//           SET_PARAMETER for volume and now playing, FLUSH on pause and TEARDOWN at the end.
+//           a 401 is answered with digest auth
+//   audio   RTP over UDP, 352 frames per packet as uncompressed ALAC
+//   control UDP, a sync packet every second, and resends of whatever the receiver missed
+//   timing  UDP, the receiver asks for our clock and is told
+//
+// only receivers that take unencrypted audio (`et` has 0) are supported, the RSA and FairPlay
+// ones need keys we don't have.
+//
+// every session is timed off the same `StreamClock`. frame `n` of the stream plays at
+// `start + n / 44100 + LATENCY` on every receiver, which is what keeps a group in step, and
+// lets a receiver join a running stream without being out of step with the others.
+
+pub const FRAMES_PER_PACKET: usize = 352;
+const BYTES_PER_FRAME: usize = 4;
+const PACKET_BYTES: usize = FRAMES_PER_PACKET * BYTES_PER_FRAME;
+// how far behind the head of the stream receivers play, the same for all of them. two
+// seconds is what iTunes used
+pub const LATENCY: i64 = 88_200;
+// lost packets are resent from this many of the most recent ones
+const HISTORY: usize = 1024;
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+const SOCKET_TIMEOUT: Duration = Duration::from_millis(500);
+// seconds between 1900 and 1970
+const NTP_EPOCH: u64 = 2_208_988_800;
+
+// 44100 Hz, 16 bit, 2 channels, everything else is what the ALAC encoder would have used
+const SDP_FORMAT: &str = "96 352 0 16 40 10 14 2 255 0 0 44100";
+
+/// the stream's timeline, shared by everything playing it
+pub struct StreamClock {
+    started: Instant,
+    // when `started` was, as wall clock time
+    unix: Duration,
+}
+
+impl StreamClock {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            unix: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+
+    // the frame the stream is at, by the clock rather than by what has been sent
+    fn head(&self) -> i64 {
+        (self.started.elapsed().as_secs_f64() * TARGET_RATE as f64) as i64
+    }
+
+    /// the frame receivers are playing right now
+    pub fn playing(&self) -> i64 {
+        self.head() - LATENCY
+    }
+
+    // the same clock as `head`, so the receivers' idea of our time agrees with the frames
+    fn ntp(&self) -> u64 {
+        let now = self.unix + self.started.elapsed();
+        let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+        ((now.as_secs() + NTP_EPOCH) << 32) | fraction
+    }
+}
+
+/// 352 frames of 44.1 kHz stereo i16 and where they are in the stream
+pub struct AudioPacket {
+    pub frame: i64,
+    pub pcm: Vec<u8>,
+}
+
+/// cuts the stream into packets and gives each one its place on the clock
+pub struct Packetizer {
+    clock: Arc<StreamClock>,
+    pending: Vec<u8>,
+    next: Option<i64>,
+}
+
+impl Packetizer {
+    pub fn new(clock: Arc<StreamClock>) -> Self {
+        Self {
+            clock,
+            pending: Vec::with_capacity(PACKET_BYTES * 2),
+            next: None,
+        }
+    }
+
+    pub fn clock(&self) -> Arc<StreamClock> {
+        self.clock.clone()
+    }
+
+    pub fn push(&mut self, pcm: &[u8]) -> Vec<Arc<AudioPacket>> {
+        self.pending.extend_from_slice(pcm);
+
+        let mut packets = vec![];
+        while self.pending.len() >= PACKET_BYTES {
+            let pcm: Vec<u8> = self.pending.drain(..PACKET_BYTES).collect();
+            let head = self.clock.head();
+            let mut next = self.next.unwrap_or(head);
+
+            // the webview stalled or chunks were dropped, and this would be played in less than
+            // half the latency. it's moved up to now, receivers fill the gap with silence
+            if next < head - LATENCY / 2 {
+                next = head;
+            }
+
+            // the webview's clock runs faster than ours, receivers won't buffer this far ahead
+            if next > head + LATENCY {
+                continue;
+            }
+
+            packets.push(Arc::new(AudioPacket { frame: next, pcm }));
+            self.next = Some(next + FRAMES_PER_PACKET as i64);
+        }
+
+        packets
+    }
+}
+
+pub type Packets = Arc<Vec<Arc<AudioPacket>>>;
+
+enum Command {
+    Audio(Packets),
+    // `None` is muted
+    Volume(Option<f32>),
+    Paused(bool),
+    NowPlaying(NowPlaying),
+    Stop,
+}
+
+/// one receiver being streamed to, everything happens on its own threads
+#[derive(Clone)]
+pub struct Session {
+    commands: Sender<Command>,
+}
+
+impl Session {
+    /// connects in the background. `status` is told `Ready` once the receiver has accepted
+    /// the stream and `Streaming` once audio is going out, `exited` is called when the
+    /// session ends by itself, after `DeviceLost` or `Error`
+    pub fn start(
+        device: AirPlayDevice,
+        password: Option<String>,
+        clock: Arc<StreamClock>,
+        volume: Option<f32>,
+        status: impl Fn(OutputStatus) + Send + 'static,
+        exited: impl FnOnce() + Send + 'static,
+    ) -> Result<Self, AirPlayError> {
+        supported(&device)?;
+
+        let (commands, rx) = mpsc::channel();
+        tauri::async_runtime::spawn_blocking(move || {
+            let stream = match Stream::connect(&device, password, clock, volume) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    status(OutputStatus::Error(e.to_string()));
+                    exited();
+                    return;
+                }
+            };
+
+            status(OutputStatus::Ready);
+            if let Err(e) = stream.run(rx, &status) {
+                println!("Lost AirPlay receiver {}, {}", device.name, e);
+                status(OutputStatus::DeviceLost);
+                exited();
+            }
+        });
+
+        Ok(Self { commands })
+    }
+
+    /// fails once the session has ended
+    pub fn send_audio(&self, packets: Packets) -> Result<(), AirPlayError> {
+        self.send(Command::Audio(packets))
+    }
+
+    pub fn set_volume(&self, volume: Option<f32>) -> Result<(), AirPlayError> {
+        self.send(Command::Volume(volume))
+    }
+
+    pub fn set_paused(&self, paused: bool) -> Result<(), AirPlayError> {
+        self.send(Command::Paused(paused))
+    }
+
+    pub fn send_now_playing(&self, now_playing: NowPlaying) -> Result<(), AirPlayError> {
+        self.send(Command::NowPlaying(now_playing))
+    }
+
+    pub fn stop(&self) {
+        self.send(Command::Stop).ok();
+    }
+
+    fn send(&self, command: Command) -> Result<(), AirPlayError> {
+        self.commands
+            .send(command)
+            .map_err(|_| AirPlayError::Write("the receiver isn't connected".into()))
+    }
+}
+
+fn supported(device: &AirPlayDevice) -> Result<(), AirPlayError> {
+    let capabilities = &device.capabilities;
+
+    // receivers that don't say are assumed to be fine, shairport didn't use to
+    if !capabilities.encryption.is_empty() && !capabilities.encryption.iter().any(|e| e == "none") {
+        return Err(AirPlayError::Device(format!(
+            "{} only takes encrypted audio ({}), which isn't supported",
+            device.name,
+            capabilities.encryption.join(", ")
+        )));
+    }
+
+    if !capabilities.codecs.is_empty() && !capabilities.codecs.iter().any(|c| c == "ALAC") {
+        return Err(AirPlayError::Device(format!(
+            "{} doesn't take ALAC",
+            device.name
+        )));
+    }
+
+    Ok(())
+}
+
+// the last packets sent, by sequence number, for resending
+type History = Arc<Mutex<HashMap<u16, Vec<u8>>>>;
+
+struct Stream {
+    rtsp: Rtsp,
+    clock: Arc<StreamClock>,
+    audio: UdpSocket,
+    control: UdpSocket,
+    // where the receiver wants audio and sync packets
+    server_address: SocketAddr,
+    control_address: SocketAddr,
+    ssrc: u32,
+    rtp_start: u32,
+    seq: u16,
+    history: History,
+    // the next packet is the first after connecting or resuming
+    first: bool,
+    first_sync: bool,
+    streaming: bool,
+    paused: bool,
+    metadata: Vec<String>,
+    sent_daap: Vec<u8>,
+    sent_artwork: Option<Vec<u8>>,
+    // tells the timing and control threads to stop
+    running: Arc<AtomicBool>,
+}
+
+impl Stream {
+    fn connect(
+        device: &AirPlayDevice,
+        password: Option<String>,
+        clock: Arc<StreamClock>,
+        volume: Option<f32>,
+    ) -> Result<Self, AirPlayError> {
+        let address = address(device)?;
+        let mut rtsp = Rtsp::connect(address, password)?;
+
+        let bind = match rtsp.local {
+            IpAddr::V4(_) => "0.0.0.0:0",
+            IpAddr::V6(_) => "[::]:0",
+        };
+        let udp = || UdpSocket::bind(bind).map_err(|e| AirPlayError::Init(e.to_string()));
+        let (audio, control, timing) = (udp()?, udp()?, udp()?);
+        let port = |s: &UdpSocket| {
+            s.local_addr()
+                .map(|a| a.port())
+                .map_err(|e| AirPlayError::Init(e.to_string()))
+        };
+
+        rtsp.request("OPTIONS", &[], None)?;
+
+        let family = match rtsp.local {
+            IpAddr::V4(_) => "IP4",
+            IpAddr::V6(_) => "IP6",
+        };
+        let session_id = rtsp.url.rsplit('/').next().unwrap_or_default().to_string();
+        let sdp = format!(
+            "v=0\r\no=iTunes {} 0 IN {} {}\r\ns=iTunes\r\nc=IN {} {}\r\nt=0 0\r\n\
+             m=audio 0 RTP/AVP 96\r\na=rtpmap:96 AppleLossless\r\na=fmtp:{}\r\n",
+            session_id, family, rtsp.local, family, rtsp.remote, SDP_FORMAT
+        );
+        rtsp.request("ANNOUNCE", &[], Some(("application/sdp", sdp.as_bytes())))?;
+
+        let transport = format!(
+            "RTP/AVP/UDP;unicast;interleaved=0-1;mode=record;control_port={};timing_port={}",
+            port(&control)?,
+            port(&timing)?
+        );
+        let setup = rtsp.request("SETUP", &[("Transport", transport)], None)?;
+        let transport = setup.header("Transport").unwrap_or_default();
+        let server_port = transport_port(transport, "server_port")
+            .ok_or_else(|| AirPlayError::Device("SETUP didn't say where to send audio".into()))?;
+        // some receivers don't bother with one, sync packets go nowhere then
+        let control_port = transport_port(transport, "control_port").unwrap_or(server_port);
+
+        let ssrc: u32 = rand::random();
+        let rtp_start: u32 = rand::random();
+        let seq: u16 = rand::random();
+
+        let mut stream = Self {
+            rtsp,
+            clock,
+            audio,
+            control,
+            server_address: SocketAddr::new(address.ip(), server_port),
+            control_address: SocketAddr::new(address.ip(), control_port),
+            ssrc,
+            rtp_start,
+            seq,
+            history: Arc::new(Mutex::new(HashMap::new())),
+            first: true,
+            first_sync: true,
+            streaming: false,
+            paused: false,
+            metadata: device.capabilities.metadata.clone(),
+            sent_daap: vec![],
+            sent_artwork: None,
+            running: Arc::new(AtomicBool::new(true)),
+        };
+
+        let rtp_info = stream.rtp_info();
+        stream.rtsp.request(
+            "RECORD",
+            &[("Range", "npt=0-".into()), ("RTP-Info", rtp_info)],
+            None,
+        )?;
+        stream.set_volume(volume)?;
+
+        answer_timing(timing, stream.clock.clone(), stream.running.clone());
+        answer_resends(
+            stream
+                .control
+                .try_clone()
+                .map_err(|e| AirPlayError::Init(e.to_string()))?,
+            stream.history.clone(),
+            stream.running.clone(),
+        );
+        stream.sync()?;
+
+        Ok(stream)
+    }
+
+    /// until told to stop, which isn't an error, or until the receiver goes away
+    fn run(
+        mut self,
+        commands: Receiver<Command>,
+        status: &impl Fn(OutputStatus),
+    ) -> Result<(), AirPlayError> {
+        let result = self.serve(commands, status);
+        self.running.store(false, Ordering::Relaxed);
+        result
+    }
+
+    fn serve(
+        &mut self,
+        commands: Receiver<Command>,
+        status: &impl Fn(OutputStatus),
+    ) -> Result<(), AirPlayError> {
+        let mut last_sync = Instant::now();
+
+        loop {
+            let wait = SYNC_INTERVAL.saturating_sub(last_sync.elapsed());
+            match commands.recv_timeout(wait) {
+                Ok(Command::Audio(packets)) => {
+                    if self.paused {
+                        continue;
+                    }
+
+                    for packet in packets.iter() {
+                        self.send_packet(packet)?;
+                    }
+
+                    if !self.streaming && !packets.is_empty() {
+                        self.streaming = true;
+                        status(OutputStatus::Streaming);
+                    }
+                }
+                Ok(Command::Volume(volume)) => self.set_volume(volume)?,
+                Ok(Command::Paused(paused)) => self.set_paused(paused)?,
+                Ok(Command::NowPlaying(now_playing)) => self.send_now_playing(&now_playing)?,
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    // it's going away either way
+                    self.rtsp.request("TEARDOWN", &[], None).ok();
+                    return Ok(());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            if last_sync.elapsed() >= SYNC_INTERVAL {
+                last_sync = Instant::now();
+
+                if self.rtsp.closed() {
+                    return Err(AirPlayError::Device("the receiver hung up".into()));
+                }
+                self.sync()?;
+            }
+        }
+    }
+
+    fn rtp(&self, frame: i64) -> u32 {
+        self.rtp_start.wrapping_add(frame as u32)
+    }
+
+    // where the next packet will be, for RECORD and FLUSH
+    fn rtp_info(&self) -> String {
+        format!(
+            "seq={};rtptime={}",
+            self.seq,
+            self.rtp(self.clock.playing() + LATENCY)
+        )
+    }
+
+    fn send_packet(&mut self, packet: &AudioPacket) -> Result<(), AirPlayError> {
+        let mut bytes = Vec::with_capacity(12 + PACKET_BYTES + 8);
+        // version 2, and the marker bit on the first packet after starting or resuming
+        bytes.push(0x80);
+        bytes.push(if self.first { 0xe0 } else { 0x60 });
+        bytes.extend(self.seq.to_be_bytes());
+        bytes.extend(self.rtp(packet.frame).to_be_bytes());
+        bytes.extend(self.ssrc.to_be_bytes());
+        bytes.extend(alac(&packet.pcm));
+
+        self.audio
+            .send_to(&bytes, self.server_address)
+            .map_err(|e| AirPlayError::Write(e.to_string()))?;
+
+        {
+            let mut history = self.history.lock().unwrap();
+            history.remove(&self.seq.wrapping_sub(HISTORY as u16));
+            history.insert(self.seq, bytes);
+        }
+
+        self.seq = self.seq.wrapping_add(1);
+        self.first = false;
+        Ok(())
+    }
+
+    // tells the receiver which frame should be playing now
+    fn sync(&mut self) -> Result<(), AirPlayError> {
+        let playing = self.clock.playing();
+
+        let mut bytes = Vec::with_capacity(20);
+        bytes.push(if self.first_sync { 0x90 } else { 0x80 });
+        bytes.extend([0xd4, 0x00, 0x07]);
+        bytes.extend(self.rtp(playing).to_be_bytes());
+        bytes.extend(self.clock.ntp().to_be_bytes());
+        bytes.extend(self.rtp(playing + LATENCY).to_be_bytes());
+
+        self.control
+            .send_to(&bytes, self.control_address)
+            .map_err(|e| AirPlayError::Write(e.to_string()))?;
+
+        self.first_sync = false;
+        Ok(())
+    }
+
+    fn set_volume(&mut self, volume: Option<f32>) -> Result<(), AirPlayError> {
+        // -30 to 0 dB, and -144 for muted
+        let db = match volume {
+            Some(v) if v > 0.0 => -30.0 + 30.0 * v.clamp(0.0, 1.0),
+            _ => -144.0,
+        };
+        let body = format!("volume: {:.6}\r\n", db);
+
+        self.rtsp
+            .request(
+                "SET_PARAMETER",
+                &[],
+                Some(("text/parameters", body.as_bytes())),
+            )
+            .map(|_| ())
+    }
+
+    fn set_paused(&mut self, paused: bool) -> Result<(), AirPlayError> {
+        if paused == self.paused {
+            return Ok(());
+        }
+        self.paused = paused;
+
+        if paused {
+            // drops whatever the receiver still has buffered
+            let rtp_info = self.rtp_info();
+            self.rtsp
+                .request("FLUSH", &[("RTP-Info", rtp_info)], None)?;
+        } else {
+            self.first = true;
+            self.first_sync = true;
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    // only what the receiver said it can show, in the order receivers expect it
+    fn send_now_playing(&mut self, now_playing: &NowPlaying) -> Result<(), AirPlayError> {
+        let playing = self.clock.playing();
+        let rtp_info = [("RTP-Info", format!("rtptime={}", self.rtp(playing)))];
+        let shows = |what: &str| self.metadata.iter().any(|m| m == what);
+        let (text, artwork, progress) = (shows("text"), shows("artwork"), shows("progress"));
+
+        if text && !now_playing.daap.is_empty() && now_playing.daap != self.sent_daap {
+            self.rtsp.request(
+                "SET_PARAMETER",
+                &rtp_info,
+                Some(("application/x-dmap-tagged", &now_playing.daap)),
+            )?;
+            self.sent_daap = now_playing.daap.clone();
+        }
+
+        if artwork && now_playing.artwork != self.sent_artwork {
+            if let Some(artwork) = &now_playing.artwork {
+                let content_type = if artwork.starts_with(b"\x89PNG") {
+                    "image/png"
+                } else {
+                    "image/jpeg"
+                };
+                self.rtsp
+                    .request("SET_PARAMETER", &rtp_info, Some((content_type, artwork)))?;
+            }
+            self.sent_artwork = now_playing.artwork.clone();
+        }
+
+        if progress {
+            if let Some((elapsed, duration)) = now_playing.progress() {
+                let frames = |ms: u32| ms as i64 * TARGET_RATE as i64 / 1000;
+                let start = playing - frames(elapsed);
+                let body = format!(
+                    "progress: {}/{}/{}\r\n",
+                    self.rtp(start),
+                    self.rtp(playing),
+                    self.rtp(start + frames(duration))
+                );
+                self.rtsp.request(
+                    "SET_PARAMETER",
+                    &rtp_info,
+                    Some(("text/parameters", body.as_bytes())),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// an address skips another lookup, and receivers are happiest on IPv4
+fn address(device: &AirPlayDevice) -> Result<SocketAddr, AirPlayError> {
+    let mut addresses: Vec<IpAddr> = device
+        .addresses
+        .iter()
+        .filter_map(|a| a.parse().ok())
+        .collect();
+    addresses.sort_by_key(|a| !a.is_ipv4());
+
+    if let Some(ip) = addresses.first() {
+        return Ok(SocketAddr::new(*ip, device.port));
+    }
+
+    (device.host.as_str(), device.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut a| a.next())
+        .ok_or_else(|| AirPlayError::Device(format!("unable to resolve {}", device.host)))
+}
+
+// `RTP/AVP/UDP;unicast;mode=record;server_port=6000;control_port=6001;timing_port=6002`
+fn transport_port(transport: &str, name: &str) -> Option<u16> {
+    transport.split(';').find_map(|part| {
+        let (key, value) = part.split_once('=')?;
+        (key.trim() == name)
+            .then(|| value.trim().parse().ok())
+            .flatten()
+    })
+}
+
+// receivers keep asking what time it is, to line their clock up with ours
+fn answer_timing(socket: UdpSocket, clock: Arc<StreamClock>, running: Arc<AtomicBool>) {
+    socket.set_read_timeout(Some(SOCKET_TIMEOUT)).ok();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut buf = [0u8; 128];
+        while running.load(Ordering::Relaxed) {
+            let (length, from) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            if length < 32 || buf[1] & 0x7f != 0x52 {
+                continue;
+            }
+
+            // their send time goes back as the origin, both of ours are now
+            let now = clock.ntp().to_be_bytes();
+            let mut reply = Vec::with_capacity(32);
+            reply.extend([0x80, 0xd3, 0x00, 0x07, 0, 0, 0, 0]);
+            reply.extend_from_slice(&buf[24..32]);
+            reply.extend(now);
+            reply.extend(now);
+
+            socket.send_to(&reply, from).ok();
+        }
+    });
+}
+
+// `[0x80, 0xd5, seq: u16][first missing seq: u16][count: u16]`
+fn answer_resends(socket: UdpSocket, history: History, running: Arc<AtomicBool>) {
+    socket.set_read_timeout(Some(SOCKET_TIMEOUT)).ok();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut buf = [0u8; 64];
+        while running.load(Ordering::Relaxed) {
+            let (length, from) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            if length < 8 || buf[1] & 0x7f != 0x55 {
+                continue;
+            }
+
+            let first = u16::from_be_bytes([buf[4], buf[5]]);
+            let count = u16::from_be_bytes([buf[6], buf[7]]);
+            for seq in (0..count).map(|i| first.wrapping_add(i)) {
+                let packet = match history.lock().unwrap().get(&seq) {
+                    Some(packet) => packet.clone(),
+                    None => continue,
+                };
+
+                let mut reply = Vec::with_capacity(packet.len() + 4);
+                reply.extend([0x80, 0xd6, 0x00, 0x01]);
+                reply.extend(packet);
+                socket.send_to(&reply, from).ok();
+            }
+        }
+    });
+}
+
+// an ALAC frame that isn't compressed, which every receiver can play and costs nothing to
+// make: a stereo element header, the samples as big endian, and the end tag
+fn alac(pcm: &[u8]) -> Vec<u8> {
+    let mut bits = Bits::with_capacity(pcm.len() + 8);
+
+    bits.write(1, 3); // channel pair element
+    bits.write(0, 4); // element instance
+    bits.write(0, 12); // unused
+    bits.write(0, 1); // no sample count, packets are always full
+    bits.write(0, 2); // no shifted bytes
+    bits.write(1, 1); // not compressed
+
+    for sample in pcm.chunks_exact(2) {
+        bits.write(u16::from_le_bytes([sample[0], sample[1]]) as u32, 16);
+    }
+
+    bits.write(7, 3); // end
+    bits.finish()
+}
+
+struct Bits {
+    bytes: Vec<u8>,
+    current: u32,
+    used: u32,
+}
+
+impl Bits {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(capacity),
+            current: 0,
+            used: 0,
+        }
+    }
+
+    // the lowest `count` bits of `value`, most significant first
+    fn write(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.current = (self.current << 1) | ((value >> i) & 1);
+            self.used += 1;
+
+            if self.used == 8 {
+                self.bytes.push(self.current as u8);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.bytes.push((self.current << (8 - self.used)) as u8);
+        }
+        self.bytes
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use flacenc::{component::BitRepr, error::Verify};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use super::{error::AirPlayError, resample::TARGET_RATE};
+
+// NOTE(recording)
+//
+// writes exactly what the receivers get (44.1 kHz stereo i16, after resampling, before
+// per-device volume) into a folder, one file per track. it doesn't need any outputs to be
+// running, which makes it the easiest way to check the pipeline end to end.
+//
+// tracks are always recorded as WAV, when FLAC was asked for each one is transcoded once it's
+// finished and the WAV is removed. that's left to the caller (see `to_flac`), it takes long
+// enough that it shouldn't happen while the audio is waiting.
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    Wav,
+    Flac,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct RecordingStatus {
+    pub dir: PathBuf,
+    pub format: RecordingFormat,
+    pub files: Vec<PathBuf>,
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    format: RecordingFormat,
+    track: String,
+    index: u32,
+    current: Option<(PathBuf, WavWriter<BufWriter<File>>)>,
+    files: Vec<PathBuf>,
+}
+
+impl Recorder {
+    pub fn start(dir: PathBuf, format: RecordingFormat) -> Result<Self, AirPlayError> {
+        fs::create_dir_all(&dir).map_err(|e| AirPlayError::Recording(e.to_string()))?;
+
+        Ok(Self {
+            dir,
+            format,
+            track: "recording".into(),
+            index: 0,
+            current: None,
+            files: vec![],
+        })
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            dir: self.dir.clone(),
+            format: self.format,
+            files: self.files.clone(),
+        }
+    }
+
+    /// `pcm` is 44.1 kHz stereo i16, the file for the current track is opened on the first write
+    pub fn write(&mut self, pcm: &[u8]) -> Result<(), AirPlayError> {
+        if self.current.is_none() {
+            self.index += 1;
+            let path = self
+                .dir
+                .join(format!("{:03} {}.wav", self.index, file_name(&self.track)));
+
+            let spec = WavSpec {
+                channels: 2,
+                sample_rate: TARGET_RATE,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+            let writer = WavWriter::create(&path, spec)
+                .map_err(|e| AirPlayError::Recording(e.to_string()))?;
+            self.current = Some((path, writer));
+        }
+
+        let (_, writer) = self.current.as_mut().expect("file was just opened");
+        for sample in pcm.chunks_exact(2) {
+            writer
+                .write_sample(i16::from_le_bytes([sample[0], sample[1]]))
+                .map_err(|e| AirPlayError::Recording(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// closes the file for the current track, whatever comes next goes into a new one.
+    /// returns the WAV that still needs transcoding, if any
+    pub fn split(&mut self, track: &str) -> Result<Option<PathBuf>, AirPlayError> {
+        let pending = self.finish_track()?;
+        self.track = track.to_string();
+        Ok(pending)
+    }
+
+    pub fn finish(mut self) -> Result<(RecordingStatus, Option<PathBuf>), AirPlayError> {
+        let pending = self.finish_track()?;
+        Ok((self.status(), pending))
+    }
+
+    fn finish_track(&mut self) -> Result<Option<PathBuf>, AirPlayError> {
+        let (path, writer) = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(None),
+        };
+
+        writer
+            .finalize()
+            .map_err(|e| AirPlayError::Recording(e.to_string()))?;
+
+        match self.format {
+            RecordingFormat::Wav => {
+                self.files.push(path);
+                Ok(None)
+            }
+            RecordingFormat::Flac => {
+                self.files.push(path.with_extension("flac"));
+                Ok(Some(path))
+            }
+        }
+    }
+}
+
+/// replaces a finished WAV with a FLAC next to it
+pub fn to_flac(wav: &Path) -> Result<PathBuf, AirPlayError> {
+    let reader = WavReader::open(wav).map_err(|e| AirPlayError::Recording(e.to_string()))?;
+    let samples: Vec<i32> = reader
+        .into_samples::<i16>()
+        .map(|s| s.map(|s| s as i32))
+        .collect::<Result<_, _>>()
+        .map_err(|e| AirPlayError::Recording(e.to_string()))?;
+
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| AirPlayError::Recording(format!("{:?}", e)))?;
+    let source = flacenc::source::MemSource::from_samples(&samples, 2, 16, TARGET_RATE as usize);
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| AirPlayError::Recording(format!("{:?}", e)))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| AirPlayError::Recording(format!("{:?}", e)))?;
+
+    let flac = wav.with_extension("flac");
+    fs::write(&flac, sink.as_slice()).map_err(|e| AirPlayError::Recording(e.to_string()))?;
+    fs::remove_file(wav).ok();
+
+    Ok(flac)
+}
+
+fn file_name(track: &str) -> String {
+    let name: String = track
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(120)
+        .collect();
+
+    let name = name.trim().trim_matches('.').to_string();
+    if name.is_empty() {
+        "track".into()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    // a ramp, so every sample is different and anything out of place shows
+    fn samples(start: i32, count: usize) -> Vec<i16> {
+        (0..count as i32)
+            .map(|i| ((start + i * 37) % 65_536 - 32_768) as i16)
+            .collect()
+    }
+
+    fn bytes(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cider-recorder-{}-{}", name, process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn read_wav(path: &Path) -> Vec<i16> {
+        let reader = WavReader::open(path).unwrap();
+        let spec = reader.spec();
+        assert_eq!((spec.channels, spec.sample_rate), (2, TARGET_RATE));
+        reader.into_samples().collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn splits_wav_on_track_change() {
+        let dir = temp_dir("wav");
+        let (first, second) = (samples(0, 4_000), samples(9_000, 2_000));
+
+        let mut recorder = Recorder::start(dir.clone(), RecordingFormat::Wav).unwrap();
+        recorder.write(&bytes(&first[..1_000])).unwrap();
+        recorder.write(&bytes(&first[1_000..])).unwrap();
+        assert_eq!(recorder.split("B/side: two?").unwrap(), None);
+        recorder.write(&bytes(&second)).unwrap();
+
+        let (status, pending) = recorder.finish().unwrap();
+        assert_eq!(pending, None);
+        assert_eq!(
+            status.files,
+            vec![
+                dir.join("001 recording.wav"),
+                dir.join("002 B_side_ two_.wav")
+            ]
+        );
+        assert_eq!(read_wav(&status.files[0]), first);
+        assert_eq!(read_wav(&status.files[1]), second);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn transcodes_each_track_to_flac() {
+        let dir = temp_dir("flac");
+        let (first, second) = (samples(0, 10_000), samples(123, 6_000));
+
+        let mut recorder = Recorder::start(dir.clone(), RecordingFormat::Flac).unwrap();
+        recorder.write(&bytes(&first)).unwrap();
+        let wav = recorder.split("next").unwrap().unwrap();
+        // the next track starts before the last one has been transcoded
+        recorder.write(&bytes(&second)).unwrap();
+        assert_eq!(read_wav(&wav), first);
+        let flac = to_flac(&wav).unwrap();
+
+        let (status, pending) = recorder.finish().unwrap();
+        let second_flac = to_flac(&pending.unwrap()).unwrap();
+        assert_eq!(status.files, vec![flac.clone(), second_flac.clone()]);
+        assert_eq!(flac, dir.join("001 recording.flac"));
+        assert!(!wav.exists());
+
+        for (path, expected) in [(flac, first), (second_flac, second)] {
+            let mut reader = claxon::FlacReader::open(&path).unwrap();
+            let info = reader.streaminfo();
+            assert_eq!((info.channels, info.sample_rate), (2, TARGET_RATE));
+
+            let decoded: Vec<i16> = reader.samples().map(|s| s.unwrap() as i16).collect();
+            assert_eq!(decoded, expected);
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,134 @@
+use std::f64::consts::PI;
+
+use super::error::AirPlayError;
+
+// NOTE(resampler)
+//
+// the webview hands us interleaved stereo f32 at whatever rate the audio context runs at,
+// airtunes wants interleaved stereo i16 at 44.1 kHz. resampling every chunk on its own
+// restarts the filter at each boundary, which is where the clicks came from, so this keeps
+// the tail of the previous chunk and the fractional read position between calls.
+//
+// it's a plain windowed-sinc interpolator, with the cutoff lowered when downsampling so
+// nothing above the new nyquist folds back down.
+
+pub const TARGET_RATE: u32 = 44_100;
+const CHANNELS: usize = 2;
+const BYTES_PER_FRAME: usize = CHANNELS * 4;
+// taps on each side of the read position
+const HALF_TAPS: usize = 16;
+
+type Frame = [f32; CHANNELS];
+
+pub struct Resampler {
+    source_rate: u32,
+    // source frames per output frame
+    step: f64,
+    // 1.0 when upsampling, below that it's the low-pass cutoff relative to the source nyquist
+    cutoff: f64,
+    // frames not needed by the filter anymore are dropped from the front
+    history: Vec<Frame>,
+    // where the next output frame is read from, in `history` frames
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(source_rate: u32) -> Result<Self, AirPlayError> {
+        if source_rate == 0 {
+            return Err(AirPlayError::Audio("sample rate can't be 0".into()));
+        }
+
+        let step = source_rate as f64 / TARGET_RATE as f64;
+
+        Ok(Self {
+            source_rate,
+            step,
+            cutoff: (1.0 / step).min(1.0),
+            // silence in front, so the first real frame has a full window to the left of it
+            history: vec![[0.0; CHANNELS]; HALF_TAPS],
+            position: HALF_TAPS as f64,
+        })
+    }
+
+    pub fn source_rate(&self) -> u32 {
+        self.source_rate
+    }
+
+    /// takes little-endian interleaved stereo f32, returns little-endian interleaved stereo i16.
+    /// the last few frames of every chunk are held back until the next one arrives
+    pub fn process(&mut self, bytes: &[u8]) -> Result<Vec<u8>, AirPlayError> {
+        if !bytes.len().is_multiple_of(BYTES_PER_FRAME) {
+            return Err(AirPlayError::Audio(format!(
+                "chunk of {} bytes isn't a whole number of stereo f32 frames",
+                bytes.len()
+            )));
+        }
+
+        for frame in bytes.chunks_exact(BYTES_PER_FRAME) {
+            let left = f32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]);
+            let right = f32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+
+            // a NaN would spread through the whole filter window
+            let clean = |s: f32| if s.is_finite() { s } else { 0.0 };
+            self.history.push([clean(left), clean(right)]);
+        }
+
+        let mut out = Vec::with_capacity((bytes.len() as f64 / self.step) as usize + 4);
+
+        while (self.position as usize) + HALF_TAPS < self.history.len() {
+            for sample in self.interpolate(self.position) {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                out.extend_from_slice(&sample.to_le_bytes());
+            }
+
+            self.position += self.step;
+        }
+
+        // keep just enough behind the read position for the next window
+        let consumed = (self.position as usize).saturating_sub(HALF_TAPS - 1);
+        self.history.drain(..consumed);
+        self.position -= consumed as f64;
+
+        Ok(out)
+    }
+
+    fn interpolate(&self, position: f64) -> Frame {
+        let center = position as usize;
+        let start = center + 1 - HALF_TAPS;
+
+        let mut sum = [0.0f64; CHANNELS];
+        let mut weight_sum = 0.0;
+
+        for (i, frame) in self.history[start..=center + HALF_TAPS].iter().enumerate() {
+            let distance = position - (start + i) as f64;
+            let weight = self.kernel(distance);
+
+            weight_sum += weight;
+            for (s, sample) in sum.iter_mut().zip(frame) {
+                *s += *sample as f64 * weight;
+            }
+        }
+
+        // normalising keeps DC at unity whatever the cutoff
+        if weight_sum.abs() > f64::EPSILON {
+            sum.iter_mut().for_each(|s| *s /= weight_sum);
+        }
+
+        [sum[0] as f32, sum[1] as f32]
+    }
+
+    // blackman-windowed sinc
+    fn kernel(&self, distance: f64) -> f64 {
+        let x = distance * self.cutoff;
+        let sinc = if x.abs() < 1e-9 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+
+        let n = (distance / HALF_TAPS as f64 + 1.0) / 2.0;
+        let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+
+        sinc * window.max(0.0)
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use super::error::AirPlayError;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// receivers only ever answer with a few headers, anything bigger isn't one
+const MAX_BODY: usize = 64 * 1024;
+
+pub const USER_AGENT: &str = "iTunes/11.0.4 (Windows; N)";
+
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Digest {
+    realm: String,
+    nonce: String,
+}
+
+/// one RTSP connection to a receiver, requests are made one at a time
+pub struct Rtsp {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    pub url: String,
+    pub local: IpAddr,
+    pub remote: IpAddr,
+    cseq: u32,
+    session: Option<String>,
+    password: Option<String>,
+    digest: Option<Digest>,
+    client_instance: String,
+    active_remote: u32,
+}
+
+impl Rtsp {
+    pub fn connect(address: SocketAddr, password: Option<String>) -> Result<Self, AirPlayError> {
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+            .map_err(|e| AirPlayError::Device(format!("unable to connect, {}", e)))?;
+        stream.set_read_timeout(Some(REPLY_TIMEOUT)).ok();
+        stream.set_nodelay(true).ok();
+
+        let local = stream
+            .local_addr()
+            .map_err(|e| AirPlayError::Device(e.to_string()))?
+            .ip();
+        let reader = BufReader::new(
+            stream
+                .try_clone()
+                .map_err(|e| AirPlayError::Device(e.to_string()))?,
+        );
+
+        let session_id: u32 = rand::random();
+        Ok(Self {
+            stream,
+            reader,
+            url: format!("rtsp://{}/{}", local, session_id),
+            local,
+            remote: address.ip(),
+            cseq: 0,
+            session: None,
+            password,
+            digest: None,
+            client_instance: format!("{:016X}", rand::random::<u64>()),
+            active_remote: rand::random(),
+        })
+    }
+
+    /// `body` is `(content type, bytes)`. a 401 is answered once with the password, anything
+    /// other than a 200 after that is an error
+    pub fn request(
+        &mut self,
+        method: &str,
+        headers: &[(&str, String)],
+        body: Option<(&str, &[u8])>,
+    ) -> Result<Response, AirPlayError> {
+        let uri = if method == "OPTIONS" {
+            "*".to_string()
+        } else {
+            self.url.clone()
+        };
+
+        let mut response = self.send(method, &uri, headers, body)?;
+
+        if response.status == 401 && self.digest.is_none() {
+            let challenge = response
+                .header("WWW-Authenticate")
+                .unwrap_or_default()
+                .to_string();
+            self.digest = Some(parse_challenge(&challenge).ok_or_else(|| {
+                AirPlayError::Device(format!("unsupported authentication {}", challenge))
+            })?);
+
+            if self.password.is_none() {
+                return Err(AirPlayError::Device("the receiver needs a password".into()));
+            }
+            response = self.send(method, &uri, headers, body)?;
+        }
+
+        match response.status {
+            200 => {
+                if let Some(session) = response.header("Session") {
+                    // `<id>;timeout=60`
+                    let session = session.split(';').next().unwrap_or_default().trim();
+                    self.session = Some(session.to_string());
+                }
+                Ok(response)
+            }
+            401 => Err(AirPlayError::Device("wrong password".into())),
+            status => Err(AirPlayError::Device(format!(
+                "{} failed, {} {}",
+                method, status, response.reason
+            ))),
+        }
+    }
+
+    /// whether the receiver has hung up, without waiting for anything
+    pub fn closed(&self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return true;
+        }
+
+        let mut buf = [0; 1];
+        let closed = match self.stream.peek(&mut buf) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) => e.kind() != ErrorKind::WouldBlock,
+        };
+
+        self.stream.set_nonblocking(false).ok();
+        closed
+    }
+
+    fn send(
+        &mut self,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, String)],
+        body: Option<(&str, &[u8])>,
+    ) -> Result<Response, AirPlayError> {
+        self.cseq += 1;
+
+        let mut request = format!("{} {} RTSP/1.0\r\n", method, uri);
+        request += &format!("CSeq: {}\r\n", self.cseq);
+        request += &format!("User-Agent: {}\r\n", USER_AGENT);
+        request += &format!("Client-Instance: {}\r\n", self.client_instance);
+        request += &format!("DACP-ID: {}\r\n", self.client_instance);
+        request += &format!("Active-Remote: {}\r\n", self.active_remote);
+        if let Some(session) = &self.session {
+            request += &format!("Session: {}\r\n", session);
+        }
+        if let Some(authorization) = self.authorization(method, uri) {
+            request += &format!("Authorization: {}\r\n", authorization);
+        }
+        for (name, value) in headers {
+            request += &format!("{}: {}\r\n", name, value);
+        }
+
+        let bytes = match body {
+            Some((content_type, body)) => {
+                request += &format!("Content-Type: {}\r\n", content_type);
+                request += &format!("Content-Length: {}\r\n\r\n", body.len());
+                let mut bytes = request.into_bytes();
+                bytes.extend_from_slice(body);
+                bytes
+            }
+            None => {
+                request += "\r\n";
+                request.into_bytes()
+            }
+        };
+
+        self.stream
+            .write_all(&bytes)
+            .map_err(|e| AirPlayError::Write(e.to_string()))?;
+
+        self.read_response()
+    }
+
+    fn read_response(&mut self) -> Result<Response, AirPlayError> {
+        let mut line = String::new();
+        let read_line = |reader: &mut BufReader<TcpStream>, line: &mut String| {
+            line.clear();
+            match reader.read_line(line) {
+                Ok(0) => Err(AirPlayError::Device("the receiver hung up".into())),
+                Ok(_) => Ok(()),
+                Err(e) => Err(AirPlayError::Device(e.to_string())),
+            }
+        };
+
+        read_line(&mut self.reader, &mut line)?;
+        // `RTSP/1.0 200 OK`
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let status = parts
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| AirPlayError::Device(format!("bad status line {:?}", line)))?;
+        let reason = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = vec![];
+        loop {
+            read_line(&mut self.reader, &mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        let response = Response {
+            status,
+            reason,
+            headers,
+        };
+
+        // nothing a receiver sends back is needed, it just has to be out of the way
+        let length: usize = response
+            .header("Content-Length")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(0);
+        if length > MAX_BODY {
+            return Err(AirPlayError::Device(format!(
+                "reply of {} bytes is too big",
+                length
+            )));
+        }
+        let mut body = vec![0; length];
+        self.reader
+            .read_exact(&mut body)
+            .map_err(|e| AirPlayError::Device(e.to_string()))?;
+
+        Ok(response)
+    }
+
+    fn authorization(&self, method: &str, uri: &str) -> Option<String> {
+        let digest = self.digest.as_ref()?;
+        let password = self.password.as_ref()?;
+
+        // receivers always expect this user
+        let user = "iTunes";
+        let ha1 = md5::compute(format!("{}:{}:{}", user, digest.realm, password));
+        let ha2 = md5::compute(format!("{}:{}", method, uri));
+        let response = md5::compute(format!("{:x}:{}:{:x}", ha1, digest.nonce, ha2));
+
+        Some(format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{:x}\"",
+            user, digest.realm, digest.nonce, uri, response
+        ))
+    }
+}
+
+// `Digest realm="raop", nonce="..."`
+fn parse_challenge(challenge: &str) -> Option<Digest> {
+    let params = challenge.trim().strip_prefix("Digest")?;
+    let param = |name: &str| {
+        params.split(',').find_map(|p| {
+            let (key, value) = p.trim().split_once('=')?;
+            (key.trim() == name).then(|| value.trim().trim_matches('"').to_string())
+        })
+    };
+
+    Some(Digest {
+        realm: param("realm")?,
+        nonce: param("nonce")?,
+    })
+}
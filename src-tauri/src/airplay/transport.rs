@@ -0,0 +1,242 @@
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::{channel::oneshot, StreamExt};
+use tauri::async_runtime::JoinHandle;
+use tokio::sync::Notify;
+use warp::{http::StatusCode, ws::WebSocket, Filter, Reply};
+
+use super::error::AirPlayError;
+
+// NOTE(audio transport)
+//
+// audio reaches the sidecar through a bounded ring: the webview pushes chunks in, one writer
+// task takes them out, resamples and writes them to airtunes. when the sidecar falls behind
+// the oldest chunks are dropped rather than letting latency grow, and counted in the metrics.
+//
+// chunks can be pushed by `send_audio` (base64 over IPC, the old way) or as binary messages on
+// `ws://127.0.0.1:<port>/audio?token=<token>&rate=<hz>`, which skips the encoding entirely.
+// the endpoint only listens on loopback and only for as long as the client is running.
+
+const BYTES_PER_FRAME: u64 = 8;
+// about two seconds at 96 kHz
+const CAPACITY_FRAMES: u64 = 192_000;
+
+pub struct Chunk {
+    pub sample_rate: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct AudioMetrics {
+    pub received_frames: u64,
+    pub written_frames: u64,
+    pub dropped_frames: u64,
+    pub invalid_chunks: u64,
+    pub buffered_frames: u64,
+    pub received_bytes: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    received_frames: AtomicU64,
+    written_frames: AtomicU64,
+    dropped_frames: AtomicU64,
+    invalid_chunks: AtomicU64,
+    received_bytes: AtomicU64,
+}
+
+pub struct AudioRing {
+    chunks: Mutex<VecDeque<Chunk>>,
+    notify: Notify,
+    closed: AtomicBool,
+    counters: Counters,
+}
+
+impl AudioRing {
+    /// closed until the client starts
+    pub fn new() -> Self {
+        Self {
+            chunks: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(true),
+            counters: Counters::default(),
+        }
+    }
+
+    /// queues a chunk of interleaved stereo f32, dropping the oldest ones if it's full
+    pub fn push(&self, data: Vec<u8>, sample_rate: u32) -> Result<(), AirPlayError> {
+        // nothing to play it on
+        if self.closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let len = data.len() as u64;
+        if !len.is_multiple_of(BYTES_PER_FRAME) || sample_rate == 0 {
+            self.counters.invalid_chunks.fetch_add(1, Ordering::Relaxed);
+            return Err(AirPlayError::Audio(format!(
+                "chunk of {} bytes at {} Hz isn't whole stereo f32 frames",
+                len, sample_rate
+            )));
+        }
+
+        self.counters
+            .received_bytes
+            .fetch_add(len, Ordering::Relaxed);
+        self.counters
+            .received_frames
+            .fetch_add(len / BYTES_PER_FRAME, Ordering::Relaxed);
+
+        {
+            let mut chunks = self.chunks.lock().unwrap();
+            chunks.push_back(Chunk { sample_rate, data });
+
+            let mut buffered = frames(&chunks);
+            while buffered > CAPACITY_FRAMES && chunks.len() > 1 {
+                if let Some(dropped) = chunks.pop_front() {
+                    let n = dropped.data.len() as u64 / BYTES_PER_FRAME;
+                    buffered -= n;
+                    self.counters.dropped_frames.fetch_add(n, Ordering::Relaxed);
+                }
+            }
+        }
+
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// waits for the next chunk, `None` once the ring is closed
+    pub async fn pop(&self) -> Option<Chunk> {
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            if let Some(chunk) = self.chunks.lock().unwrap().pop_front() {
+                return Some(chunk);
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn written(&self, data_len: usize) {
+        self.counters
+            .written_frames
+            .fetch_add(data_len as u64 / BYTES_PER_FRAME, Ordering::Relaxed);
+    }
+
+    pub fn open(&self) {
+        self.chunks.lock().unwrap().clear();
+        self.closed.store(false, Ordering::SeqCst);
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.chunks.lock().unwrap().clear();
+        self.notify.notify_one();
+    }
+
+    pub fn metrics(&self) -> AudioMetrics {
+        let c = &self.counters;
+        AudioMetrics {
+            received_frames: c.received_frames.load(Ordering::Relaxed),
+            written_frames: c.written_frames.load(Ordering::Relaxed),
+            dropped_frames: c.dropped_frames.load(Ordering::Relaxed),
+            invalid_chunks: c.invalid_chunks.load(Ordering::Relaxed),
+            buffered_frames: frames(&self.chunks.lock().unwrap()),
+            received_bytes: c.received_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn frames(chunks: &VecDeque<Chunk>) -> u64 {
+    chunks
+        .iter()
+        .map(|c| c.data.len() as u64 / BYTES_PER_FRAME)
+        .sum()
+}
+
+/// the binary endpoint, stops when dropped
+pub struct Endpoint {
+    pub url: String,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        self.task.abort();
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct AudioQuery {
+    token: String,
+    rate: Option<u32>,
+}
+
+pub fn serve(ring: Arc<AudioRing>, default_rate: u32) -> Result<Endpoint, AirPlayError> {
+    // anything else running on this machine could connect to loopback too
+    let token: String = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let expected = token.clone();
+
+    let route = warp::path("audio")
+        .and(warp::path::end())
+        .and(warp::query::<AudioQuery>())
+        .and(warp::ws())
+        .map(move |query: AudioQuery, ws: warp::ws::Ws| {
+            if query.token != expected {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+
+            let ring = ring.clone();
+            let rate = query.rate.unwrap_or(default_rate);
+            ws.on_upgrade(move |socket| receive(socket, ring, rate))
+                .into_response()
+        });
+
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let (address, server) = warp::serve(route)
+        .try_bind_with_graceful_shutdown(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), async {
+            signal.await.ok();
+        })
+        .map_err(|e| AirPlayError::Init(format!("unable to start audio endpoint: {}", e)))?;
+
+    Ok(Endpoint {
+        url: format!("ws://{}/audio?token={}", address, token),
+        shutdown: Some(shutdown),
+        task: tauri::async_runtime::spawn(server),
+    })
+}
+
+async fn receive(socket: WebSocket, ring: Arc<AudioRing>, sample_rate: u32) {
+    let (_, mut incoming) = socket.split();
+
+    while let Some(Ok(message)) = incoming.next().await {
+        if message.is_close() {
+            break;
+        }
+
+        if !message.is_binary() {
+            continue;
+        }
+
+        // counted in the metrics, one bad chunk shouldn't end the stream
+        if let Err(e) = ring.push(message.into_bytes(), sample_rate) {
+            println!("{}", e);
+        }
+    }
+}
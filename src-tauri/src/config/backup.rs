@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::error::ConfigError;
+
+const BACKUP_DIR: &str = "config-backups";
+const MAX_BACKUPS: usize = 10;
+
+// the frontend writes on every settings change, only keep one backup per interval or the
+// whole set would just be the last few toggles
+const BACKUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, serde::Serialize)]
+pub struct BackupInfo {
+    pub name: String,
+    // unix millis
+    pub created: u64,
+    pub bytes: u64,
+}
+
+/// writes next to `path` and renames over it, so a crash leaves either the old or the new file
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), ConfigError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| ConfigError::Write(e.to_string()))?;
+    }
+
+    let tmp = path.with_extension("json.tmp");
+
+    let mut file = fs::File::create(&tmp).map_err(|e| ConfigError::Write(e.to_string()))?;
+    file.write_all(contents.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| ConfigError::Write(e.to_string()))?;
+    drop(file);
+
+    fs::rename(&tmp, path).map_err(|e| ConfigError::Write(e.to_string()))
+}
+
+/// backs up whatever is at `path` if the last backup is old enough, then replaces it
+pub fn save(path: &Path, contents: &str) -> Result<(), ConfigError> {
+    if path.exists() && backup_due(path) {
+        create(path)?;
+    }
+
+    write_atomic(path, contents)
+}
+
+/// copies the current config into the backup folder and drops the oldest beyond MAX_BACKUPS
+pub fn create(path: &Path) -> Result<BackupInfo, ConfigError> {
+    let dir = backup_dir(path);
+    fs::create_dir_all(&dir).map_err(|e| ConfigError::Write(e.to_string()))?;
+
+    let name = format!(
+        "spa-config-{}.json",
+        chrono::Local::now().format("%Y%m%d-%H%M%S%.3f")
+    );
+    let dest = dir.join(&name);
+    fs::copy(path, &dest).map_err(|e| ConfigError::Write(e.to_string()))?;
+
+    let backups = list(path)?;
+    for old in backups.iter().skip(MAX_BACKUPS) {
+        fs::remove_file(dir.join(&old.name)).ok();
+    }
+
+    info(&dest).ok_or_else(|| ConfigError::Write(format!("backup {} went missing", name)))
+}
+
+/// newest first
+pub fn list(path: &Path) -> Result<Vec<BackupInfo>, ConfigError> {
+    let dir = backup_dir(path);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut backups: Vec<BackupInfo> = fs::read_dir(&dir)
+        .map_err(|e| ConfigError::Read(e.to_string()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|p| is_backup_name(&p.file_name().unwrap_or_default().to_string_lossy()))
+        .filter_map(|p| info(&p))
+        .collect();
+
+    // the timestamp in the name sorts the same as the time itself
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+
+    Ok(backups)
+}
+
+pub fn read(path: &Path, name: &str) -> Result<String, ConfigError> {
+    if !is_backup_name(name) {
+        return Err(ConfigError::Read(format!(
+            "{} is not a config backup",
+            name
+        )));
+    }
+
+    fs::read_to_string(backup_dir(path).join(name)).map_err(|e| ConfigError::Read(e.to_string()))
+}
+
+/// moves an unreadable config out of the way instead of deleting it
+pub fn set_aside(path: &Path) {
+    if path.exists() {
+        if let Err(e) = fs::rename(path, path.with_extension("json.corrupt")) {
+            println!("Unable to move corrupt config aside, {}", e);
+        }
+    }
+}
+
+fn backup_due(path: &Path) -> bool {
+    let newest = match list(path).ok().and_then(|b| b.into_iter().next()) {
+        Some(b) => b,
+        None => return true,
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+
+    now.saturating_sub(newest.created) >= BACKUP_INTERVAL.as_millis() as u64
+}
+
+fn backup_dir(path: &Path) -> PathBuf {
+    path.parent().unwrap_or(path).join(BACKUP_DIR)
+}
+
+// names only ever come from `create`, anything else (including paths) is refused
+fn is_backup_name(name: &str) -> bool {
+    name.starts_with("spa-config-")
+        && name.ends_with(".json")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+fn info(path: &Path) -> Option<BackupInfo> {
+    let metadata = fs::metadata(path).ok()?;
+    let created = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_millis() as u64;
+
+    Some(BackupInfo {
+        name: path.file_name()?.to_string_lossy().to_string(),
+        created,
+        bytes: metadata.len(),
+    })
+}
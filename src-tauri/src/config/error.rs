@@ -0,0 +1,20 @@
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Serialize, Error)]
+pub enum ConfigError {
+    #[error("Read Config Error: {0}")]
+    Read(String),
+    #[error("Write Config Error: {0}")]
+    Write(String),
+    #[error("Parse Config Error: {0}")]
+    Parse(String),
+    #[error("Invalid Config: {0}")]
+    Invalid(String),
+    #[error("Config Migration Error: {0}")]
+    Migration(String),
+    #[error("Profile Error: {0}")]
+    Profile(String),
+    #[error("Archive Error: {0}")]
+    Archive(String),
+}
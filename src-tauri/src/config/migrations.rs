@@ -0,0 +1,71 @@
+use serde_json::{Map, Value};
+
+use super::error::ConfigError;
+
+pub const CURRENT_VERSION: u32 = 2;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), ConfigError>;
+
+// MIGRATIONS[n] takes a file from version n to n + 1, append new steps and bump CURRENT_VERSION
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// brings `config` up to CURRENT_VERSION, returns whether anything had to change
+pub fn migrate(config: &mut Value) -> Result<bool, ConfigError> {
+    let map = config
+        .as_object_mut()
+        .ok_or_else(|| ConfigError::Migration("config is not an object".into()))?;
+
+    // files from before versioning have no `version` at all
+    let version = match map.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .map(|v| v as u32)
+            .ok_or_else(|| ConfigError::Migration("`version` is not a number".into()))?,
+    };
+
+    // written by a newer build, read what we understand and leave the rest alone
+    if version >= CURRENT_VERSION {
+        return Ok(false);
+    }
+
+    for (from, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        step(map)
+            .map_err(|e| ConfigError::Migration(format!("v{} -> v{}: {}", from, from + 1, e)))?;
+    }
+
+    map.insert("version".into(), CURRENT_VERSION.into());
+
+    Ok(true)
+}
+
+// the typed sections didn't exist yet, everything that was there belongs to the frontend
+// and the sections get their defaults when deserialised
+fn v0_to_v1(_config: &mut Map<String, Value>) -> Result<(), ConfigError> {
+    Ok(())
+}
+
+// rpc and the websocket moved onto one server, its settings were under `rpc` and the
+// websocket's own port is gone. whatever else is under `server` is kept
+fn v1_to_v2(config: &mut Map<String, Value>) -> Result<(), ConfigError> {
+    let mut server = match config.remove("server") {
+        Some(Value::Object(server)) => server,
+        _ => Map::new(),
+    };
+
+    if let Some(Value::Object(rpc)) = config.get_mut("rpc") {
+        for key in ["port", "bind_address", "allow_lan", "allowed_origins"] {
+            if let Some(value) = rpc.remove(key) {
+                server.insert(key.into(), value);
+            }
+        }
+    }
+
+    if let Some(Value::Object(websocket)) = config.get_mut("websocket") {
+        websocket.remove("port");
+    }
+
+    config.insert("server".into(), Value::Object(server));
+
+    Ok(())
+}
@@ -1,54 +1,569 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
 use tauri::{
+    async_runtime::RwLock,
     plugin::{Builder as PluginBuilder, TauriPlugin},
-    AppHandle, Runtime,
+    AppHandle, EventHandler, Manager, Runtime,
 };
 
-use tokio::fs::{
-  read_to_string,
-  write as write_str,
-  create_dir_all,
-};
+mod backup;
+pub mod error;
+mod migrations;
+mod patch;
+mod profiles;
+pub mod settings;
+mod transfer;
 
-#[tauri::command]
-async fn read<R>(handle: AppHandle<R>) -> Result<Option<String>, String>
+use backup::BackupInfo;
+use error::ConfigError;
+use profiles::ProfileList;
+pub use settings::{DiscordSettings, LastFmSettings, ServerSettings, Settings, TraySettings};
+use transfer::{ArchiveManifest, ImportReport, PluginEntry};
+
+// emitted to the frontend and to backend listeners (see `on_change`) whenever a setting changes
+pub const CHANGED_EVENT: &str = "config-changed";
+
+pub struct ConfigState {
+    settings: RwLock<Settings>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ConfigChanged {
+    // JSON pointers of everything that changed, e.g. `/rpc/port`
+    pub keys: Vec<String>,
+    pub settings: Settings,
+}
+
+impl ConfigChanged {
+    /// true if `pointer` or anything below it changed
+    pub fn touches(&self, pointer: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|k| k == pointer || k.starts_with(&format!("{}/", pointer)))
+    }
+}
+
+/// reads, migrates and validates `spa-config.json`. if it can't be used the newest backup
+/// that can takes its place, and failing that the defaults are used
+fn load<R>(handle: &AppHandle<R>) -> Settings
 where
     R: Runtime,
 {
-    let cfg_path = handle
-        .path_resolver()
-        .app_config_dir()
-        .expect("Unknown Application Config Dir");
-    let file_path = cfg_path.join("spa-config.json");
+    let file_path = config_path(handle);
+    if !file_path.exists() {
+        return Settings::default();
+    }
+
+    let result = std::fs::read_to_string(&file_path)
+        .map_err(|e| ConfigError::Read(e.to_string()))
+        .and_then(|s| parse_str(&s))
+        .and_then(|(settings, migrated)| {
+            if migrated {
+                // keep the pre-migration file around no matter how recent the last backup is
+                println!("Migrated config to v{}", settings.version);
+                backup::create(&file_path)?;
+                backup::write_atomic(&file_path, &to_string(&settings)?)?;
+            }
+
+            Ok(settings)
+        });
+
+    let e = match result {
+        Ok(settings) => return settings,
+        Err(e) => e,
+    };
+
+    println!("Unable to load config, {}", e);
+
+    for info in backup::list(&file_path).unwrap_or_default() {
+        let settings = match backup::read(&file_path, &info.name).and_then(|s| parse_str(&s)) {
+            Ok((settings, _)) => settings,
+            Err(e) => {
+                println!("Skipping backup {}, {}", info.name, e);
+                continue;
+            }
+        };
 
-    if file_path.exists() {
-        match read_to_string(file_path).await {
-            Ok(s) => Ok(Some(s)),
-            Err(e) => Err(format!("Read Config Error: {}", e.to_string())),
+        println!("Recovering config from backup {}", info.name);
+        backup::set_aside(&file_path);
+        if let Err(e) = to_string(&settings).and_then(|s| backup::write_atomic(&file_path, &s)) {
+            println!("Unable to write recovered config, {}", e);
         }
-    } else {
-        Ok(None)
+
+        return settings;
     }
+
+    println!("No usable config backup, using defaults");
+    Settings::default()
 }
 
-#[tauri::command]
-async fn write<R>(handle: AppHandle<R>, content: String) -> Result<(), String>
+/// parses and migrates a whole config file, returns whether it had to be migrated
+fn parse_str(s: &str) -> Result<(Settings, bool), ConfigError> {
+    let mut value: Value =
+        serde_json::from_str(s).map_err(|e| ConfigError::Parse(e.to_string()))?;
+    let migrated = migrations::migrate(&mut value)?;
+
+    let settings = Settings::from_value_lenient(value)?;
+    settings.validate()?;
+
+    Ok((settings, migrated))
+}
+
+fn to_string(settings: &Settings) -> Result<String, ConfigError> {
+    serde_json::to_string(settings).map_err(|e| ConfigError::Write(e.to_string()))
+}
+
+/// works out the new settings from the current ones, writes them out and swaps them in.
+/// the lock is held over the write so the file and what the backend sees can't disagree
+async fn update<R, F>(
+    handle: &AppHandle<R>,
+    force_backup: bool,
+    f: F,
+) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+    F: FnOnce(&Settings) -> Result<Settings, ConfigError>,
+{
+    let file_path = config_path(handle);
+
+    let state = handle.state::<ConfigState>();
+    let mut lock = state.settings.write().await;
+
+    let settings = f(&lock)?;
+    let contents = to_string(&settings)?;
+
+    let keys = patch::diff(
+        &serde_json::to_value(&*lock).unwrap_or_default(),
+        &serde_json::to_value(&settings).unwrap_or_default(),
+    );
+
+    tauri::async_runtime::spawn_blocking(move || {
+        if force_backup && file_path.exists() {
+            backup::create(&file_path)?;
+        }
+
+        backup::save(&file_path, &contents)
+    })
+    .await
+    .map_err(|e| ConfigError::Write(e.to_string()))??;
+
+    *lock = settings.clone();
+    drop(lock);
+
+    if !keys.is_empty() {
+        let payload = ConfigChanged {
+            keys,
+            settings: settings.clone(),
+        };
+
+        // emit_all only reaches the windows, the backend listens globally
+        handle.emit_all(CHANGED_EVENT, &payload).ok();
+        handle.trigger_global(CHANGED_EVENT, serde_json::to_string(&payload).ok());
+    }
+
+    Ok(settings)
+}
+
+/// applies `f` to the settings as JSON, the result has to be valid settings again
+async fn update_value<R, F>(handle: &AppHandle<R>, f: F) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+    F: FnOnce(&mut Value) -> Result<(), ConfigError>,
+{
+    update(handle, false, |current| {
+        let mut value =
+            serde_json::to_value(current).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        f(&mut value)?;
+
+        // the schema version only ever moves through migrations
+        let mut settings = parse(value)?;
+        settings.version = current.version;
+
+        Ok(settings)
+    })
+    .await
+}
+
+/// calls `f` whenever a setting changes, from whichever thread changed it
+pub fn on_change<R, F>(handle: &AppHandle<R>, f: F) -> EventHandler
 where
     R: Runtime,
+    F: Fn(ConfigChanged) + Send + 'static,
 {
-    let cfg_path = handle
+    handle.listen_global(CHANGED_EVENT, move |event| {
+        if let Some(Ok(change)) = event.payload().map(serde_json::from_str::<ConfigChanged>) {
+            f(change);
+        }
+    })
+}
+
+// for what the frontend writes, a wrong type there is a mistake to report, not to paper over
+fn parse(value: Value) -> Result<Settings, ConfigError> {
+    let settings: Settings =
+        serde_json::from_value(value).map_err(|e| ConfigError::Parse(e.to_string()))?;
+    settings.validate()?;
+
+    Ok(settings)
+}
+
+fn config_dir<R>(handle: &AppHandle<R>) -> PathBuf
+where
+    R: Runtime,
+{
+    handle
         .path_resolver()
         .app_config_dir()
-        .expect("Unknown Application Config Dir");
+        .expect("Unknown Application Config Dir")
+}
+
+fn config_path<R>(handle: &AppHandle<R>) -> PathBuf
+where
+    R: Runtime,
+{
+    config_dir(handle).join("spa-config.json")
+}
+
+pub async fn get<R>(handle: &AppHandle<R>) -> Settings
+where
+    R: Runtime,
+{
+    handle.state::<ConfigState>().settings.read().await.clone()
+}
+
+/// same as `get`, for sync code that isn't running on the async runtime
+pub fn get_blocking<R>(handle: &AppHandle<R>) -> Settings
+where
+    R: Runtime,
+{
+    handle
+        .state::<ConfigState>()
+        .settings
+        .blocking_read()
+        .clone()
+}
+
+pub async fn discord<R>(handle: &AppHandle<R>) -> DiscordSettings
+where
+    R: Runtime,
+{
+    handle
+        .state::<ConfigState>()
+        .settings
+        .read()
+        .await
+        .discord
+        .clone()
+}
+
+pub async fn lastfm<R>(handle: &AppHandle<R>) -> LastFmSettings
+where
+    R: Runtime,
+{
+    handle
+        .state::<ConfigState>()
+        .settings
+        .read()
+        .await
+        .lastfm
+        .clone()
+}
+
+pub async fn server<R>(handle: &AppHandle<R>) -> ServerSettings
+where
+    R: Runtime,
+{
+    handle
+        .state::<ConfigState>()
+        .settings
+        .read()
+        .await
+        .server
+        .clone()
+}
 
-    if !cfg_path.exists() {
-      create_dir_all(cfg_path.clone()).await.map_err(|e| format!("Write Config Error: {}", e.to_string()))?;
+#[tauri::command]
+async fn read<R>(handle: AppHandle<R>) -> Result<Option<String>, ConfigError>
+where
+    R: Runtime,
+{
+    // the frontend sets itself up from scratch when there's no config yet
+    if !config_path(&handle).exists() {
+        return Ok(None);
     }
 
-    let file_path = cfg_path.join("spa-config.json");
+    let settings = get(&handle).await;
+    serde_json::to_string(&settings)
+        .map(Some)
+        .map_err(|e| ConfigError::Read(e.to_string()))
+}
 
-    write_str(file_path, content)
+#[tauri::command]
+async fn write<R>(handle: AppHandle<R>, content: String) -> Result<(), ConfigError>
+where
+    R: Runtime,
+{
+    // the frontend writes what it was handed, which is already current. without a `version`
+    // it'd be taken for a file from before versioning and migrated again
+    let mut value: Value =
+        serde_json::from_str(&content).map_err(|e| ConfigError::Parse(e.to_string()))?;
+    if let Some(map) = value.as_object_mut() {
+        map.entry("version")
+            .or_insert_with(|| migrations::CURRENT_VERSION.into());
+    }
+
+    migrations::migrate(&mut value)?;
+    let settings = parse(value)?;
+    update(&handle, false, |_| Ok(settings)).await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_settings<R>(handle: AppHandle<R>) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+{
+    Ok(get(&handle).await)
+}
+
+/// `path` is a JSON pointer (`/rpc/port`) or a key path (`rpc.port`)
+#[tauri::command]
+async fn get_value<R>(handle: AppHandle<R>, path: String) -> Result<Option<Value>, ConfigError>
+where
+    R: Runtime,
+{
+    let settings =
+        serde_json::to_value(get(&handle).await).map_err(|e| ConfigError::Read(e.to_string()))?;
+    Ok(settings.pointer(&patch::to_pointer(&path)).cloned())
+}
+
+#[tauri::command]
+async fn set_value<R>(
+    handle: AppHandle<R>,
+    path: String,
+    value: Value,
+) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+{
+    let pointer = patch::to_pointer(&path);
+    update_value(&handle, |settings| patch::set(settings, &pointer, value)).await
+}
+
+/// applies a JSON merge patch, `{ "rpc": { "port": 1234 } }` only touches `rpc.port`
+#[tauri::command]
+async fn patch_settings<R>(handle: AppHandle<R>, patch: Value) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+{
+    update_value(&handle, |settings| {
+        patch::merge(settings, patch);
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+async fn list_backups<R>(handle: AppHandle<R>) -> Result<Vec<BackupInfo>, ConfigError>
+where
+    R: Runtime,
+{
+    let file_path = config_path(&handle);
+    tauri::async_runtime::spawn_blocking(move || backup::list(&file_path))
+        .await
+        .map_err(|e| ConfigError::Read(e.to_string()))?
+}
+
+/// swaps in a backup, the config it replaces is backed up first so this can be undone
+#[tauri::command]
+async fn restore_backup<R>(handle: AppHandle<R>, name: String) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+{
+    let file_path = config_path(&handle);
+    let contents = tauri::async_runtime::spawn_blocking(move || backup::read(&file_path, &name))
         .await
-        .map_err(|e| format!("Write Config Error: {}", e.to_string()))
+        .map_err(|e| ConfigError::Read(e.to_string()))??;
+
+    let (settings, _) = parse_str(&contents)?;
+    update(&handle, true, |_| Ok(settings)).await
+}
+
+#[tauri::command]
+async fn list_profiles<R>(handle: AppHandle<R>) -> Result<ProfileList, ConfigError>
+where
+    R: Runtime,
+{
+    let config_dir = config_dir(&handle);
+    tauri::async_runtime::spawn_blocking(move || profiles::list(&config_dir))
+        .await
+        .map_err(|e| ConfigError::Read(e.to_string()))?
+}
+
+/// saves the current config as `name`, overwriting a profile with the same name
+#[tauri::command]
+async fn save_profile<R>(handle: AppHandle<R>, name: String) -> Result<(), ConfigError>
+where
+    R: Runtime,
+{
+    let config_dir = config_dir(&handle);
+    let contents = to_string(&get(&handle).await)?;
+
+    tauri::async_runtime::spawn_blocking(move || profiles::save(&config_dir, &name, &contents))
+        .await
+        .map_err(|e| ConfigError::Write(e.to_string()))?
+}
+
+/// saves the current config under the active profile and swaps in `name`
+#[tauri::command]
+async fn switch_profile<R>(handle: AppHandle<R>, name: String) -> Result<Settings, ConfigError>
+where
+    R: Runtime,
+{
+    let config_dir = config_dir(&handle);
+    let current = to_string(&get(&handle).await)?;
+
+    let (dir, next) = (config_dir.clone(), name.clone());
+    let contents = tauri::async_runtime::spawn_blocking(move || {
+        // saved first, switching to the active profile would otherwise read back a stale copy
+        profiles::save(&dir, &profiles::active(&dir), &current)?;
+        profiles::read(&dir, &next)
+    })
+    .await
+    .map_err(|e| ConfigError::Read(e.to_string()))??;
+
+    let (settings, _) = parse_str(&contents)?;
+    let settings = update(&handle, true, |_| Ok(settings)).await?;
+
+    tauri::async_runtime::spawn_blocking(move || profiles::set_active(&config_dir, &name))
+        .await
+        .map_err(|e| ConfigError::Write(e.to_string()))??;
+
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn delete_profile<R>(handle: AppHandle<R>, name: String) -> Result<(), ConfigError>
+where
+    R: Runtime,
+{
+    let config_dir = config_dir(&handle);
+    tauri::async_runtime::spawn_blocking(move || profiles::delete(&config_dir, &name))
+        .await
+        .map_err(|e| ConfigError::Write(e.to_string()))?
+}
+
+/// writes the config, every profile and the plugin list to a zip at `dest`
+#[tauri::command]
+async fn export_settings<R>(handle: AppHandle<R>, dest: String) -> Result<(), ConfigError>
+where
+    R: Runtime,
+{
+    let config_dir = config_dir(&handle);
+    let config = to_string(&get(&handle).await)?;
+
+    let plugins: Vec<PluginEntry> = match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins
+            .list()
+            .await
+            .into_iter()
+            .map(|p| PluginEntry {
+                id: p.id,
+                name: p.name,
+                version: p.version,
+                enabled: p.enabled,
+            })
+            .collect(),
+        None => vec![],
+    };
+
+    let manifest = ArchiveManifest {
+        format: transfer::FORMAT_VERSION,
+        app_version: handle.package_info().version.to_string(),
+        exported_at: chrono::Local::now().to_rfc3339(),
+        profile: profiles::active(&config_dir),
+    };
+
+    tauri::async_runtime::spawn_blocking(move || {
+        transfer::export(&config_dir, Path::new(&dest), &manifest, &config, &plugins)
+    })
+    .await
+    .map_err(|e| ConfigError::Archive(e.to_string()))?
+}
+
+/// the counterpart to `export_settings`. the current config is backed up first, plugins
+/// that are installed here get enabled or disabled to match and the rest are reported
+#[tauri::command]
+async fn import_settings<R>(
+    handle: AppHandle<R>,
+    archive: String,
+) -> Result<ImportReport, ConfigError>
+where
+    R: Runtime,
+{
+    let config_dir = config_dir(&handle);
+    let path = PathBuf::from(&archive);
+    let contents = tauri::async_runtime::spawn_blocking(move || transfer::read(&path))
+        .await
+        .map_err(|e| ConfigError::Archive(e.to_string()))??;
+
+    // check everything before touching anything
+    let (settings, _) = parse_str(&contents.config)?;
+    for (name, profile) in &contents.profiles {
+        parse_str(profile)
+            .map_err(|e| ConfigError::Archive(format!("profile \"{}\" is invalid: {}", name, e)))?;
+    }
+    let profile = Some(contents.manifest.profile.clone())
+        .filter(|p| profiles::validate_name(p).is_ok())
+        .unwrap_or_else(|| profiles::DEFAULT_PROFILE.to_string());
+
+    update(&handle, true, |_| Ok(settings)).await?;
+
+    let (dir, saved, active) = (config_dir.clone(), contents.profiles, profile.clone());
+    let saved = tauri::async_runtime::spawn_blocking(move || {
+        for (name, profile) in &saved {
+            profiles::save(&dir, name, profile)?;
+        }
+        profiles::set_active(&dir, &active)?;
+
+        Ok::<_, ConfigError>(profiles::list(&dir)?.profiles)
+    })
+    .await
+    .map_err(|e| ConfigError::Archive(e.to_string()))??;
+
+    let mut missing_plugins = vec![];
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => {
+            let installed = plugins.list().await;
+            for entry in contents.plugins {
+                let local = match installed.iter().find(|p| p.id == entry.id) {
+                    Some(p) => p,
+                    None => {
+                        missing_plugins.push(entry);
+                        continue;
+                    }
+                };
+
+                let result = match (local.enabled, entry.enabled) {
+                    (false, true) => plugins.enable(&entry.id).await,
+                    (true, false) => plugins.disable(&entry.id).await,
+                    _ => Ok(()),
+                };
+
+                if let Err(e) = result {
+                    println!("Unable to match plugin {} to the import, {}", entry.id, e);
+                }
+            }
+        }
+        None => missing_plugins = contents.plugins,
+    }
+
+    Ok(ImportReport {
+        profiles: saved,
+        profile,
+        missing_plugins,
+    })
 }
 
 pub fn init<R>() -> TauriPlugin<R>
@@ -56,6 +571,28 @@ where
     R: Runtime,
 {
     PluginBuilder::new("config")
-        .invoke_handler(tauri::generate_handler![read, write])
+        .setup(|app| {
+            app.manage(ConfigState {
+                settings: RwLock::new(load(app)),
+            });
+
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            read,
+            write,
+            get_settings,
+            get_value,
+            set_value,
+            patch_settings,
+            list_backups,
+            restore_backup,
+            list_profiles,
+            save_profile,
+            switch_profile,
+            delete_profile,
+            export_settings,
+            import_settings
+        ])
         .build()
 }
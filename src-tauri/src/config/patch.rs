@@ -0,0 +1,98 @@
+use serde_json::{Map, Value};
+
+use super::error::ConfigError;
+
+/// accepts either a JSON pointer (`/discord/enabled`) or a key path (`discord.enabled`)
+pub fn to_pointer(path: &str) -> String {
+    if path.is_empty() || path.starts_with('/') {
+        path.to_string()
+    } else {
+        path.split('.')
+            .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+            .collect()
+    }
+}
+
+/// sets the value at `pointer`, creating any objects missing along the way
+pub fn set(root: &mut Value, pointer: &str, value: Value) -> Result<(), ConfigError> {
+    if pointer.is_empty() {
+        return Err(ConfigError::Invalid(
+            "can't replace the whole config by path".into(),
+        ));
+    }
+
+    let mut current = root;
+    let keys: Vec<String> = pointer[1..]
+        .split('/')
+        .map(|key| key.replace("~1", "/").replace("~0", "~"))
+        .collect();
+
+    for (i, key) in keys.iter().enumerate() {
+        let map = current
+            .as_object_mut()
+            .ok_or_else(|| ConfigError::Invalid(format!("{} is not inside an object", pointer)))?;
+
+        if i == keys.len() - 1 {
+            map.insert(key.clone(), value);
+            return Ok(());
+        }
+
+        current = map
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    Ok(())
+}
+
+/// RFC 7386 merge patch, a `null` in the patch removes the key
+pub fn merge(target: &mut Value, patch: Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        other => {
+            *target = other;
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    let map = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            map.remove(&key);
+        } else {
+            merge(map.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// the pointers of every leaf that differs between `old` and `new`
+pub fn diff(old: &Value, new: &Value) -> Vec<String> {
+    let mut changed = vec![];
+    diff_into(old, new, String::new(), &mut changed);
+    changed
+}
+
+fn diff_into(old: &Value, new: &Value, pointer: String, changed: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, value) in new {
+                let child = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                diff_into(old.get(key).unwrap_or(&Value::Null), value, child, changed);
+            }
+
+            for key in old.keys().filter(|k| !new.contains_key(*k)) {
+                changed.push(format!(
+                    "{}/{}",
+                    pointer,
+                    key.replace('~', "~0").replace('/', "~1")
+                ));
+            }
+        }
+        (old, new) if old != new => changed.push(pointer),
+        _ => {}
+    }
+}
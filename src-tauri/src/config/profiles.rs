@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{backup, error::ConfigError};
+
+// a profile is just a saved copy of `spa-config.json`, switching copies one over it
+const PROFILE_DIR: &str = "profiles";
+const ACTIVE_FILE: &str = "active";
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Clone, serde::Serialize)]
+pub struct ProfileList {
+    pub active: String,
+    pub profiles: Vec<String>,
+}
+
+pub fn validate_name(name: &str) -> Result<(), ConfigError> {
+    if name.is_empty() || name.len() > 64 {
+        return Err(ConfigError::Profile(
+            "profile names must be between 1 and 64 characters".into(),
+        ));
+    }
+
+    // names end up as file names
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ')
+    {
+        return Err(ConfigError::Profile(format!(
+            "\"{}\" may only contain letters, digits, spaces, '-' and '_'",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn active(config_dir: &Path) -> String {
+    fs::read_to_string(profile_dir(config_dir).join(ACTIVE_FILE))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| validate_name(s).is_ok())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+pub fn set_active(config_dir: &Path, name: &str) -> Result<(), ConfigError> {
+    let dir = profile_dir(config_dir);
+    fs::create_dir_all(&dir).map_err(|e| ConfigError::Write(e.to_string()))?;
+    fs::write(dir.join(ACTIVE_FILE), name).map_err(|e| ConfigError::Write(e.to_string()))
+}
+
+pub fn list(config_dir: &Path) -> Result<ProfileList, ConfigError> {
+    let active = active(config_dir);
+    let dir = profile_dir(config_dir);
+
+    let mut profiles: Vec<String> = if dir.exists() {
+        fs::read_dir(&dir)
+            .map_err(|e| ConfigError::Read(e.to_string()))?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .strip_suffix(".json")
+                    .map(|s| s.to_string())
+            })
+            .filter(|name| validate_name(name).is_ok())
+            .collect()
+    } else {
+        vec![]
+    };
+
+    // the active one hasn't necessarily been saved yet
+    if !profiles.contains(&active) {
+        profiles.push(active.clone());
+    }
+    profiles.sort();
+
+    Ok(ProfileList { active, profiles })
+}
+
+pub fn read(config_dir: &Path, name: &str) -> Result<String, ConfigError> {
+    validate_name(name)?;
+
+    let path = profile_path(config_dir, name);
+    if !path.exists() {
+        return Err(ConfigError::Profile(format!(
+            "no profile named \"{}\"",
+            name
+        )));
+    }
+
+    fs::read_to_string(path).map_err(|e| ConfigError::Read(e.to_string()))
+}
+
+pub fn save(config_dir: &Path, name: &str, contents: &str) -> Result<(), ConfigError> {
+    validate_name(name)?;
+    backup::write_atomic(&profile_path(config_dir, name), contents)
+}
+
+pub fn delete(config_dir: &Path, name: &str) -> Result<(), ConfigError> {
+    validate_name(name)?;
+
+    if name == active(config_dir) {
+        return Err(ConfigError::Profile(
+            "the active profile can't be deleted, switch to another one first".into(),
+        ));
+    }
+
+    let path = profile_path(config_dir, name);
+    if path.exists() {
+        fs::remove_file(path).map_err(|e| ConfigError::Write(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+pub fn profile_dir(config_dir: &Path) -> PathBuf {
+    config_dir.join(PROFILE_DIR)
+}
+
+fn profile_path(config_dir: &Path, name: &str) -> PathBuf {
+    profile_dir(config_dir).join(format!("{}.json", name))
+}
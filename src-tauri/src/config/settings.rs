@@ -0,0 +1,251 @@
+use std::net::IpAddr;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{error::ConfigError, migrations::CURRENT_VERSION};
+
+// the port the app itself is served from, see `main`
+const RESERVED_PORT: u16 = 10768;
+
+/// the parts of `spa-config.json` the backend reads itself, everything else is kept as-is
+/// for the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub discord: DiscordSettings,
+    pub server: ServerSettings,
+    pub rpc: RpcSettings,
+    pub websocket: WebSocketSettings,
+    pub lastfm: LastFmSettings,
+    pub tray: TraySettings,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscordSettings {
+    pub enabled: bool,
+    // one of "Cider-2", "AppleMusic" or "Cider", see `DiscordRPC::init`
+    pub client_id: String,
+    pub show_buttons: bool,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// the http server the RPC routes and the websocket share
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub port: u16,
+    pub bind_address: String,
+    // anything other than a loopback `bind_address` needs this turned on
+    pub allow_lan: bool,
+    // origins browsers may call the server from, `*` allows any
+    pub allowed_origins: Vec<String>,
+    // requests from other devices need a token from pairing, local ones never do
+    pub require_pairing: bool,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcSettings {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSocketSettings {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastFmSettings {
+    pub enabled: bool,
+    pub now_playing: bool,
+    // percentage of the song that has to be played before it gets scrobbled
+    pub scrobble_after: u8,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraySettings {
+    // show the current song at the top of the tray menu
+    pub show_now_playing: bool,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            discord: DiscordSettings::default(),
+            server: ServerSettings::default(),
+            rpc: RpcSettings::default(),
+            websocket: WebSocketSettings::default(),
+            lastfm: LastFmSettings::default(),
+            tray: TraySettings::default(),
+            other: Map::new(),
+        }
+    }
+}
+
+impl Default for DiscordSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            client_id: "Cider-2".into(),
+            show_buttons: true,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            port: 10769,
+            bind_address: "127.0.0.1".into(),
+            allow_lan: false,
+            allowed_origins: vec!["*".into()],
+            require_pairing: true,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Default for LastFmSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            now_playing: true,
+            scrobble_after: 50,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Default for TraySettings {
+    fn default() -> Self {
+        Self {
+            show_now_playing: true,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// for config files, which may have been edited by hand. a field with the wrong type is
+    /// dropped and gets its default, instead of the whole file failing to parse
+    pub fn from_value_lenient(value: Value) -> Result<Self, ConfigError> {
+        let mut map = match value {
+            Value::Object(map) => map,
+            other => {
+                return Err(ConfigError::Parse(format!(
+                    "expected an object, got {}",
+                    other
+                )))
+            }
+        };
+
+        lenient_section::<DiscordSettings>(&mut map, "discord");
+        lenient_section::<ServerSettings>(&mut map, "server");
+        lenient_section::<RpcSettings>(&mut map, "rpc");
+        lenient_section::<WebSocketSettings>(&mut map, "websocket");
+        lenient_section::<LastFmSettings>(&mut map, "lastfm");
+        lenient_section::<TraySettings>(&mut map, "tray");
+
+        if let Some(version) = map.get("version") {
+            if u32::deserialize(version).is_err() {
+                println!("Ignoring `version` {}, it isn't a number", version);
+                map.remove("version");
+            }
+        }
+
+        serde_json::from_value(Value::Object(map)).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.port == 0 {
+            return Err(ConfigError::Invalid("`server.port` can't be 0".into()));
+        }
+
+        if self.server.port == RESERVED_PORT {
+            return Err(ConfigError::Invalid(format!(
+                "`server.port` {} is used by Cider itself",
+                RESERVED_PORT
+            )));
+        }
+
+        let ip: IpAddr = self.server.bind_address.parse().map_err(|_| {
+            ConfigError::Invalid(format!(
+                "`server.bind_address` \"{}\" is not an IP address",
+                self.server.bind_address
+            ))
+        })?;
+
+        if !ip.is_loopback() && !self.server.allow_lan {
+            return Err(ConfigError::Invalid(
+                "`server.bind_address` can only be a non-loopback address with `server.allow_lan`"
+                    .into(),
+            ));
+        }
+
+        if !(1..=100).contains(&self.lastfm.scrobble_after) {
+            return Err(ConfigError::Invalid(
+                "`lastfm.scrobble_after` must be between 1 and 100".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// replaces `map[key]` with a copy of itself that only has the fields `T` accepts. each one is
+// tried on its own against what's been kept so far, so one bad field doesn't take the rest out
+fn lenient_section<T>(map: &mut Map<String, Value>, key: &str)
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    let fields = match map.remove(key) {
+        Some(Value::Object(fields)) => fields,
+        Some(Value::Null) | None => return,
+        Some(other) => {
+            println!("Ignoring `{}` {}, it isn't an object", key, other);
+            return;
+        }
+    };
+
+    let mut kept = match serde_json::to_value(T::default()) {
+        Ok(Value::Object(defaults)) => defaults,
+        _ => Map::new(),
+    };
+
+    for (field, value) in fields {
+        let mut candidate = kept.clone();
+        candidate.insert(field.clone(), value);
+
+        let candidate = Value::Object(candidate);
+        if serde_json::from_value::<T>(candidate.clone()).is_ok() {
+            if let Value::Object(candidate) = candidate {
+                kept = candidate;
+            }
+        } else {
+            println!("Ignoring `{}.{}`, it has the wrong type", key, field);
+        }
+    }
+
+    map.insert(key.to_string(), Value::Object(kept));
+}
@@ -0,0 +1,148 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+use super::{error::ConfigError, profiles};
+
+// NOTE(settings archive)
+//
+//   manifest.json      `ArchiveManifest`
+//   spa-config.json    the config as it was when exported
+//   profiles/*.json    every saved profile
+//   plugins.json       `[PluginEntry]`, which plugins were installed and enabled
+//
+// plugins themselves aren't included, they have to be installed (and their permissions
+// granted) on the machine that imports the archive. there's no themes folder to carry,
+// whatever theme settings the frontend has live in the config and come along with it.
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ArchiveManifest {
+    pub format: u32,
+    pub app_version: String,
+    pub exported_at: String,
+    pub profile: String,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PluginEntry {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub enabled: bool,
+}
+
+/// everything in an archive
+pub struct Archive {
+    pub manifest: ArchiveManifest,
+    pub config: String,
+    pub profiles: Vec<(String, String)>,
+    pub plugins: Vec<PluginEntry>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct ImportReport {
+    pub profile: String,
+    pub profiles: Vec<String>,
+    // listed in the archive but not installed here
+    pub missing_plugins: Vec<PluginEntry>,
+}
+
+pub fn export(
+    config_dir: &Path,
+    dest: &Path,
+    manifest: &ArchiveManifest,
+    config: &str,
+    plugins: &[PluginEntry],
+) -> Result<(), ConfigError> {
+    let file = File::create(dest).map_err(|e| ConfigError::Archive(e.to_string()))?;
+    let mut zip = ZipWriter::new(file);
+
+    let manifest =
+        serde_json::to_string(manifest).map_err(|e| ConfigError::Archive(e.to_string()))?;
+    let plugins =
+        serde_json::to_string(plugins).map_err(|e| ConfigError::Archive(e.to_string()))?;
+
+    add_file(&mut zip, "manifest.json", manifest.as_bytes())?;
+    add_file(&mut zip, "spa-config.json", config.as_bytes())?;
+    add_file(&mut zip, "plugins.json", plugins.as_bytes())?;
+
+    for name in profiles::list(config_dir)?.profiles {
+        // the active profile only exists as `spa-config.json` until it is saved
+        if let Ok(contents) = profiles::read(config_dir, &name) {
+            add_file(
+                &mut zip,
+                &format!("profiles/{}.json", name),
+                contents.as_bytes(),
+            )?;
+        }
+    }
+
+    zip.finish()
+        .map_err(|e| ConfigError::Archive(e.to_string()))?;
+
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Archive, ConfigError> {
+    let file = File::open(path).map_err(|e| ConfigError::Archive(e.to_string()))?;
+    let mut zip = ZipArchive::new(file).map_err(|e| ConfigError::Archive(e.to_string()))?;
+
+    let manifest: ArchiveManifest = serde_json::from_str(&read_file(&mut zip, "manifest.json")?)
+        .map_err(|e| ConfigError::Archive(format!("invalid manifest.json: {}", e)))?;
+
+    if manifest.format > FORMAT_VERSION {
+        return Err(ConfigError::Archive(format!(
+            "archive format {} is newer than this version of Cider understands",
+            manifest.format
+        )));
+    }
+
+    let config = read_file(&mut zip, "spa-config.json")?;
+    let plugins: Vec<PluginEntry> = serde_json::from_str(&read_file(&mut zip, "plugins.json")?)
+        .map_err(|e| ConfigError::Archive(format!("invalid plugins.json: {}", e)))?;
+
+    let names: Vec<String> = zip
+        .file_names()
+        .filter_map(|n| n.strip_prefix("profiles/")?.strip_suffix(".json"))
+        .filter(|n| profiles::validate_name(n).is_ok())
+        .map(|n| n.to_string())
+        .collect();
+
+    let mut saved = vec![];
+    for name in names {
+        let contents = read_file(&mut zip, &format!("profiles/{}.json", name))?;
+        saved.push((name, contents));
+    }
+
+    Ok(Archive {
+        manifest,
+        config,
+        profiles: saved,
+        plugins,
+    })
+}
+
+fn add_file(zip: &mut ZipWriter<File>, name: &str, contents: &[u8]) -> Result<(), ConfigError> {
+    zip.start_file(name, FileOptions::default())
+        .map_err(|e| ConfigError::Archive(e.to_string()))?;
+    zip.write_all(contents)
+        .map_err(|e| ConfigError::Archive(e.to_string()))
+}
+
+fn read_file(zip: &mut ZipArchive<File>, name: &str) -> Result<String, ConfigError> {
+    let mut file = zip
+        .by_name(name)
+        .map_err(|_| ConfigError::Archive(format!("{} is missing from the archive", name)))?;
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(|e| ConfigError::Archive(format!("unable to read {}: {}", name, e)))?;
+
+    Ok(contents)
+}
@@ -134,10 +134,15 @@ impl DiscordRPC {
 }
 
 #[tauri::command]
-pub async fn init_client<R>(handle: AppHandle<R>, client_id: String) -> Result<(), DiscordError>
+pub async fn init_client<R>(handle: AppHandle<R>, client_id: Option<String>) -> Result<(), DiscordError>
 where
     R: Runtime,
 {
+    let client_id = match client_id {
+        Some(id) => id,
+        None => crate::config::discord(&handle).await.client_id,
+    };
+
     tauri::async_runtime::spawn_blocking(move || {
         let client = handle.state::<DiscordRPC>();
         client.init(client_id)
@@ -187,7 +192,11 @@ where
     // huh? ok
     let timestamps = start.and_then(|s| end.map(|e| (s, e)));
 
-    let b = buttons.unwrap_or(vec![]);
+    let b = if crate::config::discord(&handle).await.show_buttons {
+        buttons.unwrap_or(vec![])
+    } else {
+        vec![]
+    };
 
     if let Err(_e) = client
         .set_rpc(
@@ -248,6 +257,27 @@ where
         .setup(|handle| {
             handle.manage(DiscordRPC::new());
 
+            // reconnect with the new client id, or disconnect when turned off
+            let h = handle.clone();
+            crate::config::on_change(handle, move |change| {
+                if !change.touches("/discord/enabled") && !change.touches("/discord/client_id") {
+                    return;
+                }
+
+                let h = h.clone();
+                tauri::async_runtime::spawn_blocking(move || {
+                    let client = h.state::<DiscordRPC>();
+                    let discord = change.settings.discord;
+
+                    client.remove();
+                    if discord.enabled {
+                        if let Err(e) = client.init(&discord.client_id) {
+                            println!("Unable to reconnect Discord RPC, {}", e);
+                        }
+                    }
+                });
+            });
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
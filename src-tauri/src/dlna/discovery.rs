@@ -0,0 +1,310 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use reqwest::Url;
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::{
+    error::DlnaError,
+    soap::{AV_TRANSPORT, RENDERING_CONTROL},
+};
+
+// NOTE(dlna discovery)
+//
+// there's no mDNS for UPnP, renderers answer an SSDP `M-SEARCH` sent to 239.255.255.250:1900
+// with a `LOCATION` header pointing at their device description. that's XML listing the
+// services and where to send SOAP to control them. we search every so often and forget
+// renderers that stop answering, they don't reliably say goodbye.
+
+pub const SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+pub const DEVICES_EVENT: &str = "dlna-devices";
+
+const SSDP_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+const SEARCH_INTERVAL: Duration = Duration::from_secs(30);
+// renderers missing from this many searches in a row are forgotten
+const MISSED_SEARCHES: u32 = 3;
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Renderer {
+    // the UDN, `uuid:...`
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub model: Option<String>,
+    pub location: String,
+    // where the SOAP goes, resolved against the description's url
+    #[serde(skip)]
+    pub av_transport: String,
+    // not every renderer lets us change its volume
+    #[serde(skip)]
+    pub rendering_control: Option<String>,
+}
+
+struct Found {
+    renderer: Renderer,
+    missed: u32,
+}
+
+pub struct Discovery {
+    stop: Arc<AtomicBool>,
+    renderers: Arc<RwLock<HashMap<String, Found>>>,
+}
+
+impl Discovery {
+    /// searches until dropped, telling the webview whenever the list changes
+    pub fn start<R>(handle: AppHandle<R>) -> Result<Self, DlnaError>
+    where
+        R: Runtime,
+    {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .map_err(|e| DlnaError::Discovery(e.to_string()))?;
+        // short, so dropping doesn't have to wait for a whole search
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .map_err(|e| DlnaError::Discovery(e.to_string()))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let renderers: Arc<RwLock<HashMap<String, Found>>> = Default::default();
+
+        let stopped = stop.clone();
+        let found = renderers.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            let http = reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(3))
+                .build()
+                .unwrap_or_default();
+
+            while !stopped.load(Ordering::SeqCst) {
+                if let Err(e) = search(&socket) {
+                    println!("{}", e);
+                }
+
+                let mut changed = false;
+                let mut seen = HashSet::new();
+                let deadline = Instant::now() + SEARCH_INTERVAL;
+
+                while Instant::now() < deadline && !stopped.load(Ordering::SeqCst) {
+                    let location = match receive(&socket) {
+                        Some(location) => location,
+                        None => continue,
+                    };
+
+                    // already described, no need to fetch it again
+                    let known = found
+                        .read()
+                        .unwrap()
+                        .values()
+                        .find(|f| f.renderer.location == location)
+                        .map(|f| f.renderer.id.clone());
+                    if let Some(id) = known {
+                        seen.insert(id);
+                        continue;
+                    }
+
+                    match describe(&http, &location) {
+                        Ok(renderer) => {
+                            seen.insert(renderer.id.clone());
+                            found.write().unwrap().insert(
+                                renderer.id.clone(),
+                                Found {
+                                    renderer,
+                                    missed: 0,
+                                },
+                            );
+                            changed = true;
+                        }
+                        Err(e) => println!("Ignoring {}, {}", location, e),
+                    }
+                }
+
+                {
+                    let mut found = found.write().unwrap();
+                    for (id, f) in found.iter_mut() {
+                        f.missed = if seen.contains(id) { 0 } else { f.missed + 1 };
+                    }
+
+                    let before = found.len();
+                    found.retain(|_, f| f.missed < MISSED_SEARCHES);
+                    changed |= found.len() != before;
+                }
+
+                if changed {
+                    handle.emit_all(DEVICES_EVENT, list(&found)).ok();
+                }
+            }
+        });
+
+        Ok(Self { stop, renderers })
+    }
+
+    pub fn renderers(&self) -> Vec<Renderer> {
+        list(&self.renderers)
+    }
+
+    pub fn renderer(&self, id: &str) -> Option<Renderer> {
+        self.renderers
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|f| f.renderer.clone())
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn list(renderers: &RwLock<HashMap<String, Found>>) -> Vec<Renderer> {
+    let mut renderers: Vec<Renderer> = renderers
+        .read()
+        .unwrap()
+        .values()
+        .map(|f| f.renderer.clone())
+        .collect();
+    renderers.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    renderers
+}
+
+fn search(socket: &UdpSocket) -> Result<(), DlnaError> {
+    let message = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {}:{}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 2\r\n\
+         ST: {}\r\n\r\n",
+        SSDP_ADDRESS, SSDP_PORT, SEARCH_TARGET
+    );
+
+    socket
+        .send_to(
+            message.as_bytes(),
+            SocketAddr::from((SSDP_ADDRESS, SSDP_PORT)),
+        )
+        .map_err(|e| DlnaError::Discovery(e.to_string()))?;
+
+    Ok(())
+}
+
+// the `LOCATION` of the next answer, `None` if nothing came in time
+fn receive(socket: &UdpSocket) -> Option<String> {
+    let mut buf = [0u8; 2048];
+    let (len, _) = socket.recv_from(&mut buf).ok()?;
+    let response = String::from_utf8_lossy(&buf[..len]);
+
+    if !response.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+
+    response.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("location") {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct Description {
+    #[serde(rename = "URLBase")]
+    url_base: Option<String>,
+    device: DeviceDescription,
+}
+
+#[derive(serde::Deserialize)]
+struct DeviceDescription {
+    #[serde(rename = "friendlyName")]
+    friendly_name: String,
+    #[serde(rename = "modelName")]
+    model_name: Option<String>,
+    #[serde(rename = "UDN")]
+    udn: String,
+    #[serde(rename = "serviceList", default)]
+    service_list: ServiceList,
+    // the renderer can be an embedded device, e.g. of a media server
+    #[serde(rename = "deviceList", default)]
+    device_list: DeviceList,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct ServiceList {
+    #[serde(default)]
+    service: Vec<Service>,
+}
+
+#[derive(serde::Deserialize)]
+struct Service {
+    #[serde(rename = "serviceType")]
+    service_type: String,
+    #[serde(rename = "controlURL")]
+    control_url: String,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct DeviceList {
+    #[serde(default)]
+    device: Vec<DeviceDescription>,
+}
+
+impl DeviceDescription {
+    fn control_url(&self, service: &str) -> Option<&str> {
+        self.service_list
+            .service
+            .iter()
+            .find(|s| s.service_type == service)
+            .map(|s| s.control_url.trim())
+    }
+
+    fn renderer(&self) -> Option<&DeviceDescription> {
+        if self.control_url(AV_TRANSPORT).is_some() {
+            return Some(self);
+        }
+
+        self.device_list.device.iter().find_map(|d| d.renderer())
+    }
+}
+
+fn describe(http: &reqwest::blocking::Client, location: &str) -> Result<Renderer, DlnaError> {
+    let xml = http
+        .get(location)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.text())
+        .map_err(|e| DlnaError::Renderer(e.to_string()))?;
+
+    let description: Description =
+        quick_xml::de::from_str(&xml).map_err(|e| DlnaError::Renderer(e.to_string()))?;
+
+    let base = description.url_base.as_deref().unwrap_or(location);
+    let base = Url::parse(base).map_err(|e| DlnaError::Renderer(e.to_string()))?;
+    let resolve = |url: &str| base.join(url).map(|u| u.to_string()).ok();
+
+    let device = description
+        .device
+        .renderer()
+        .ok_or_else(|| DlnaError::Renderer("no AVTransport service".into()))?;
+
+    Ok(Renderer {
+        id: device.udn.trim().to_string(),
+        name: device.friendly_name.trim().to_string(),
+        host: base.host_str().unwrap_or_default().to_string(),
+        model: device.model_name.as_ref().map(|m| m.trim().to_string()),
+        location: location.to_string(),
+        av_transport: device
+            .control_url(AV_TRANSPORT)
+            .and_then(resolve)
+            .ok_or_else(|| DlnaError::Renderer("invalid AVTransport url".into()))?,
+        rendering_control: device.control_url(RENDERING_CONTROL).and_then(resolve),
+    })
+}
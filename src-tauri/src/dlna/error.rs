@@ -0,0 +1,14 @@
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Serialize, Error)]
+pub enum DlnaError {
+    #[error("Discovery Error: {0}")]
+    Discovery(String),
+    #[error("Renderer Error: {0}")]
+    Renderer(String),
+    #[error("Control Error: {0}")]
+    Control(String),
+    #[error("Stream Error: {0}")]
+    Stream(String),
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use tauri::{
+    async_runtime::{Mutex, RwLock},
+    AppHandle, Runtime,
+};
+
+mod discovery;
+pub mod error;
+mod soap;
+mod stream;
+use discovery::Discovery;
+pub use discovery::Renderer;
+use error::DlnaError;
+use stream::Stream;
+
+// NOTE(dlna)
+//
+// DLNA/UPnP renderers pull audio rather than being sent it, so they're pointed at a WAV
+// stream we serve on the LAN, fed the same 44.1 kHz PCM the AirPlay receivers get. play,
+// pause and volume go to the renderer over SOAP.
+//
+// this has no device commands of its own, the AirPlay ones pick whichever kind an id belongs
+// to. renderers buffer a few seconds before they start, so they won't be in step with AirPlay
+// receivers, and a paused one picks up at whatever is playing when it resumes.
+
+#[derive(Clone, serde::Serialize)]
+pub struct Playing {
+    pub renderer: Renderer,
+    pub volume: f32,
+    pub muted: bool,
+    pub paused: bool,
+}
+
+pub struct DlnaClient {
+    discovery: Mutex<Option<Discovery>>,
+    outputs: RwLock<HashMap<String, Playing>>,
+    // only while something is playing
+    stream: Mutex<Option<Stream>>,
+}
+
+impl DlnaClient {
+    pub fn new() -> Self {
+        Self {
+            discovery: Mutex::new(None),
+            outputs: RwLock::new(HashMap::new()),
+            stream: Mutex::new(None),
+        }
+    }
+
+    /// starts searching the first time it's asked, returns what's been found so far
+    pub async fn renderers<R>(&self, handle: AppHandle<R>) -> Result<Vec<Renderer>, DlnaError>
+    where
+        R: Runtime,
+    {
+        let mut discovery = self.discovery.lock().await;
+        if discovery.is_none() {
+            *discovery = Some(Discovery::start(handle)?);
+        }
+
+        Ok(discovery
+            .as_ref()
+            .map(|d| d.renderers())
+            .unwrap_or_default())
+    }
+
+    pub async fn renderer(&self, id: &str) -> Option<Renderer> {
+        self.discovery
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|d| d.renderer(id))
+    }
+
+    /// whether `id` is a renderer we know about, playing or not
+    pub async fn owns(&self, id: &str) -> bool {
+        self.outputs.read().await.contains_key(id) || self.renderer(id).await.is_some()
+    }
+
+    pub async fn is_playing(&self) -> bool {
+        !self.outputs.read().await.is_empty()
+    }
+
+    pub async fn outputs(&self) -> Vec<(String, Playing)> {
+        self.outputs
+            .read()
+            .await
+            .iter()
+            .map(|(id, p)| (id.clone(), p.clone()))
+            .collect()
+    }
+
+    /// points the renderer at the stream and starts it, already playing ones are left alone
+    pub async fn start(&self, id: &str) -> Result<(), DlnaError> {
+        if self.outputs.read().await.contains_key(id) {
+            return Ok(());
+        }
+
+        let renderer = self
+            .renderer(id)
+            .await
+            .ok_or_else(|| DlnaError::Renderer(format!("no renderer named {}", id)))?;
+
+        let url = {
+            let mut stream = self.stream.lock().await;
+            if stream.is_none() {
+                *stream = Some(Stream::serve()?);
+            }
+            stream
+                .as_ref()
+                .expect("stream was just started")
+                .url(&renderer.host)?
+        };
+
+        let started = async {
+            soap::set_uri(&renderer.av_transport, &url, &soap::stream_metadata(&url)).await?;
+            soap::play(&renderer.av_transport).await
+        }
+        .await;
+
+        if let Err(e) = started {
+            self.stop_if_idle().await;
+            return Err(e);
+        }
+
+        self.outputs.write().await.insert(
+            id.to_string(),
+            Playing {
+                renderer,
+                volume: 1.0,
+                muted: false,
+                paused: false,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub async fn stop(&self, id: &str) {
+        let playing = self.outputs.write().await.remove(id);
+        if let Some(playing) = playing {
+            if let Err(e) = soap::stop(&playing.renderer.av_transport).await {
+                println!("{}", e);
+            }
+        }
+
+        self.stop_if_idle().await;
+    }
+
+    pub async fn stop_all(&self) {
+        let outputs = std::mem::take(&mut *self.outputs.write().await);
+        for (_, playing) in outputs {
+            if let Err(e) = soap::stop(&playing.renderer.av_transport).await {
+                println!("{}", e);
+            }
+        }
+
+        self.stop_if_idle().await;
+    }
+
+    pub async fn set_paused(&self, id: &str, paused: bool) -> Result<(), DlnaError> {
+        let control = self.playing(id).await?.renderer.av_transport;
+        if paused {
+            soap::pause(&control).await?;
+        } else {
+            soap::play(&control).await?;
+        }
+
+        self.update(id, |p| p.paused = paused).await;
+        Ok(())
+    }
+
+    /// `volume` is 0 to 1, renderers take whole percents
+    pub async fn set_volume(&self, id: &str, volume: f32) -> Result<(), DlnaError> {
+        let volume = if volume.is_finite() {
+            volume.clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        let control = self.rendering_control(id).await?;
+        soap::set_volume(&control, (volume * 100.0).round() as u8).await?;
+
+        self.update(id, |p| p.volume = volume).await;
+        Ok(())
+    }
+
+    pub async fn set_muted(&self, id: &str, muted: bool) -> Result<(), DlnaError> {
+        let control = self.rendering_control(id).await?;
+        soap::set_mute(&control, muted).await?;
+
+        self.update(id, |p| p.muted = muted).await;
+        Ok(())
+    }
+
+    /// `pcm` is 44.1 kHz stereo i16
+    pub async fn write(&self, pcm: &[u8]) {
+        if let Some(stream) = self.stream.lock().await.as_ref() {
+            stream.write(pcm);
+        }
+    }
+
+    // the SOAP calls are made without holding the lock, renderers can be slow to answer
+    async fn playing(&self, id: &str) -> Result<Playing, DlnaError> {
+        self.outputs
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| DlnaError::Renderer(format!("{} isn't playing", id)))
+    }
+
+    async fn rendering_control(&self, id: &str) -> Result<String, DlnaError> {
+        let playing = self.playing(id).await?;
+        playing.renderer.rendering_control.ok_or_else(|| {
+            DlnaError::Renderer(format!(
+                "{} doesn't let us change its volume",
+                playing.renderer.name
+            ))
+        })
+    }
+
+    async fn update(&self, id: &str, f: impl FnOnce(&mut Playing)) {
+        if let Some(playing) = self.outputs.write().await.get_mut(id) {
+            f(playing);
+        }
+    }
+
+    async fn stop_if_idle(&self) {
+        if self.outputs.read().await.is_empty() {
+            *self.stream.lock().await = None;
+        }
+    }
+}
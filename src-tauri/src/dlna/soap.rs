@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use super::error::DlnaError;
+
+pub const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+pub const RENDERING_CONTROL: &str = "urn:schemas-upnp-org:service:RenderingControl:1";
+
+lazy_static::lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap_or_default();
+}
+
+/// points the renderer at `url` without starting it, `metadata` is DIDL-Lite
+pub async fn set_uri(control: &str, url: &str, metadata: &str) -> Result<(), DlnaError> {
+    call(
+        control,
+        AV_TRANSPORT,
+        "SetAVTransportURI",
+        &[
+            ("InstanceID", "0"),
+            ("CurrentURI", url),
+            ("CurrentURIMetaData", metadata),
+        ],
+    )
+    .await
+}
+
+pub async fn play(control: &str) -> Result<(), DlnaError> {
+    call(
+        control,
+        AV_TRANSPORT,
+        "Play",
+        &[("InstanceID", "0"), ("Speed", "1")],
+    )
+    .await
+}
+
+pub async fn pause(control: &str) -> Result<(), DlnaError> {
+    call(control, AV_TRANSPORT, "Pause", &[("InstanceID", "0")]).await
+}
+
+pub async fn stop(control: &str) -> Result<(), DlnaError> {
+    call(control, AV_TRANSPORT, "Stop", &[("InstanceID", "0")]).await
+}
+
+/// `volume` is 0 to 100
+pub async fn set_volume(control: &str, volume: u8) -> Result<(), DlnaError> {
+    call(
+        control,
+        RENDERING_CONTROL,
+        "SetVolume",
+        &[
+            ("InstanceID", "0"),
+            ("Channel", "Master"),
+            ("DesiredVolume", &volume.to_string()),
+        ],
+    )
+    .await
+}
+
+pub async fn set_mute(control: &str, muted: bool) -> Result<(), DlnaError> {
+    call(
+        control,
+        RENDERING_CONTROL,
+        "SetMute",
+        &[
+            ("InstanceID", "0"),
+            ("Channel", "Master"),
+            ("DesiredMute", if muted { "1" } else { "0" }),
+        ],
+    )
+    .await
+}
+
+/// DIDL-Lite for the live stream, it's the same item whatever is playing
+pub fn stream_metadata(url: &str) -> String {
+    format!(
+        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/"><item id="0" parentID="-1" restricted="1"><dc:title>Cider</dc:title><upnp:class>object.item.audioItem.musicTrack</upnp:class><res protocolInfo="http-get:*:audio/wav:*">{}</res></item></DIDL-Lite>"#,
+        escape(url)
+    )
+}
+
+async fn call(
+    control: &str,
+    service: &str,
+    action: &str,
+    args: &[(&str, &str)],
+) -> Result<(), DlnaError> {
+    let args: String = args
+        .iter()
+        .map(|(name, value)| format!("<{0}>{1}</{0}>", name, escape(value)))
+        .collect();
+
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:{action} xmlns:u="{service}">{args}</u:{action}></s:Body></s:Envelope>"#,
+    );
+
+    let response = CLIENT
+        .post(control)
+        .header("Content-Type", r#"text/xml; charset="utf-8""#)
+        .header("SOAPAction", format!("\"{}#{}\"", service, action))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| DlnaError::Control(e.to_string()))?;
+
+    if response.status().is_success() {
+        return Ok(());
+    }
+
+    // faults come back as a 500 with the reason somewhere in the body
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    let reason = between(&text, "<errorDescription>", "</errorDescription>")
+        .or_else(|| between(&text, "<faultstring>", "</faultstring>"))
+        .unwrap_or_else(|| status.to_string());
+
+    Err(DlnaError::Control(format!("{} failed, {}", action, reason)))
+}
+
+fn between(text: &str, start: &str, end: &str) -> Option<String> {
+    let from = text.find(start)? + start.len();
+    let to = text[from..].find(end)? + from;
+    Some(text[from..to].to_string())
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
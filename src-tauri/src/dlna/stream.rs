@@ -0,0 +1,168 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+};
+
+use bytes::Bytes;
+use futures::{channel::oneshot, stream, StreamExt};
+use tauri::async_runtime::JoinHandle;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::{
+    http::{Method, Response, StatusCode},
+    hyper::Body,
+    Filter, Reply,
+};
+
+use super::error::DlnaError;
+
+// about two seconds of the chunks the writer hands us, a renderer further behind than that
+// skips ahead rather than holding everyone else up
+const CAPACITY_CHUNKS: usize = 256;
+const SAMPLE_RATE: u32 = 44_100;
+
+/// the live stream renderers pull from, stops when dropped
+pub struct Stream {
+    port: u16,
+    token: String,
+    sender: broadcast::Sender<Bytes>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        self.task.abort();
+    }
+}
+
+impl Stream {
+    /// listens on every interface, renderers are somewhere else on the LAN
+    pub fn serve() -> Result<Self, DlnaError> {
+        // the url is handed to anything on the network, so it shouldn't be guessable
+        let token: String = rand::random::<[u8; 16]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let (sender, _) = broadcast::channel::<Bytes>(CAPACITY_CHUNKS);
+
+        let expected = format!("{}.wav", token);
+        let subscriber = sender.clone();
+        let route = warp::path!("dlna" / String)
+            .and(warp::method())
+            .map(move |file: String, method: Method| {
+                if file != expected {
+                    return StatusCode::NOT_FOUND.into_response();
+                }
+
+                let head = match method {
+                    Method::GET => false,
+                    // some renderers check what they're getting before they play it
+                    Method::HEAD => true,
+                    _ => return StatusCode::METHOD_NOT_ALLOWED.into_response(),
+                };
+
+                let body = if head {
+                    Body::empty()
+                } else {
+                    Body::wrap_stream(listen(subscriber.subscribe()))
+                };
+
+                Response::builder()
+                    .header("Content-Type", "audio/wav")
+                    .header("transferMode.dlna.org", "Streaming")
+                    .header(
+                        "contentFeatures.dlna.org",
+                        "DLNA.ORG_OP=00;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000",
+                    )
+                    .body(body)
+                    .into_response()
+            });
+
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let (address, server) = warp::serve(route)
+            .try_bind_with_graceful_shutdown(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)), async {
+                signal.await.ok();
+            })
+            .map_err(|e| DlnaError::Stream(format!("unable to start stream: {}", e)))?;
+
+        Ok(Self {
+            port: address.port(),
+            token,
+            sender,
+            shutdown: Some(shutdown),
+            task: tauri::async_runtime::spawn(server),
+        })
+    }
+
+    /// the url `host` can reach us on
+    pub fn url(&self, host: &str) -> Result<String, DlnaError> {
+        Ok(format!(
+            "http://{}:{}/dlna/{}.wav",
+            local_address(host)?,
+            self.port,
+            self.token
+        ))
+    }
+
+    /// `pcm` is 44.1 kHz stereo i16, dropped if nobody is listening
+    pub fn write(&self, pcm: &[u8]) {
+        self.sender.send(Bytes::copy_from_slice(pcm)).ok();
+    }
+}
+
+fn listen(
+    receiver: broadcast::Receiver<Bytes>,
+) -> impl futures::Stream<Item = Result<Bytes, Infallible>> {
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(chunk) => return Some((Ok(chunk), receiver)),
+                // fell behind, carry on from whatever is playing now
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    stream::once(async { Ok(Bytes::from(wav_header())) }).chain(chunks)
+}
+
+// a stream has no length, the sizes are as big as they go and renderers read until it ends
+fn wav_header() -> Vec<u8> {
+    let channels: u16 = 2;
+    let bits: u16 = 16;
+    let block_align = channels * bits / 8;
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend(u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend(16u32.to_le_bytes());
+    // PCM
+    header.extend(1u16.to_le_bytes());
+    header.extend(channels.to_le_bytes());
+    header.extend(SAMPLE_RATE.to_le_bytes());
+    header.extend((SAMPLE_RATE * block_align as u32).to_le_bytes());
+    header.extend(block_align.to_le_bytes());
+    header.extend(bits.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend((u32::MAX - 36).to_le_bytes());
+    header
+}
+
+// the address of whichever interface routes to `host`, connecting a UDP socket doesn't send
+// anything
+fn local_address(host: &str) -> Result<IpAddr, DlnaError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|s| s.connect((host, 9)).map(|_| s))
+        .map_err(|e| DlnaError::Stream(e.to_string()))?;
+
+    socket
+        .local_addr()
+        .map(|a| a.ip())
+        .map_err(|e| DlnaError::Stream(e.to_string()))
+}
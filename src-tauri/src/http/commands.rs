@@ -0,0 +1,96 @@
+use std::net::IpAddr;
+
+use qrcode::{render::svg, QrCode};
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::{
+    error::HttpError, exposed_on_lan, mdns::API_VERSION, pairing::PairedClient, HttpServer,
+};
+
+#[derive(Clone, serde::Serialize)]
+pub struct PairingCode {
+    // what the QR code encodes, for showing alongside it
+    pub payload: String,
+    pub svg: String,
+    pub host: String,
+    pub port: u16,
+    pub expires_in_secs: u64,
+}
+
+#[derive(serde::Serialize)]
+struct PairingPayload<'a> {
+    api: &'a str,
+    host: &'a str,
+    port: u16,
+    token: &'a str,
+}
+
+/// a QR code a phone can scan to pair itself, only while the server is reachable on the LAN
+#[tauri::command]
+pub async fn create_pairing_code<R>(app_handle: AppHandle<R>) -> Result<PairingCode, HttpError>
+where
+    R: Runtime,
+{
+    let state = app_handle.state::<HttpServer>();
+    let guard = state.running.lock().await;
+
+    let running = guard
+        .as_ref()
+        .filter(|r| exposed_on_lan(&r.settings))
+        .ok_or_else(|| {
+            HttpError::Pairing("the server isn't running or isn't exposed on the LAN".into())
+        })?;
+
+    let ip: IpAddr = running.settings.bind_address.parse().map_err(|_| {
+        HttpError::Pairing(format!("invalid address {}", running.settings.bind_address))
+    })?;
+
+    // bound to every interface, the phone needs one it can actually reach
+    let host = if ip.is_unspecified() {
+        local_ip_address::local_ip()
+            .map_err(|e| HttpError::Pairing(format!("unable to find a LAN address: {}", e)))?
+    } else {
+        ip
+    }
+    .to_string();
+    let port = running.address.port();
+
+    let (token, ttl) = state.pairing.new_pairing_token();
+    let payload = serde_json::to_string(&PairingPayload {
+        api: API_VERSION,
+        host: &host,
+        port,
+        token: &token,
+    })
+    .map_err(|e| HttpError::Pairing(e.to_string()))?;
+
+    let svg = QrCode::new(payload.as_bytes())
+        .map_err(|e| HttpError::Pairing(e.to_string()))?
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .build();
+
+    Ok(PairingCode {
+        payload,
+        svg,
+        host,
+        port,
+        expires_in_secs: ttl.as_secs(),
+    })
+}
+
+#[tauri::command]
+pub fn list_paired_clients<R>(app_handle: AppHandle<R>) -> Vec<PairedClient>
+where
+    R: Runtime,
+{
+    app_handle.state::<HttpServer>().pairing.clients()
+}
+
+#[tauri::command]
+pub fn revoke_paired_client<R>(id: String, app_handle: AppHandle<R>) -> Result<(), HttpError>
+where
+    R: Runtime,
+{
+    app_handle.state::<HttpServer>().pairing.revoke(&id)
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Serialize, Error)]
+pub enum HttpError {
+    #[error("Unable to Start Server: {0}")]
+    Bind(String),
+    #[error("Unable to Stop Server: {0}")]
+    Stop(String),
+    #[error("Unable to Advertise Server: {0}")]
+    Mdns(String),
+    #[error("Unable to Pair: {0}")]
+    Pairing(String),
+}
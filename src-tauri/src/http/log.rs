@@ -0,0 +1,59 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+// the last few requests the http server handled, for working out what an integration is doing
+const MAX_ENTRIES: usize = 200;
+
+lazy_static::lazy_static! {
+    static ref REQUEST_LOG: Mutex<VecDeque<RequestLogEntry>> = Mutex::new(VecDeque::with_capacity(MAX_ENTRIES));
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct RequestLogEntry {
+    // RFC 3339
+    pub time: String,
+    pub method: String,
+    pub url: String,
+    pub origin: Option<String>,
+    pub status: u16,
+    pub duration_ms: f64,
+}
+
+pub fn record(method: &str, url: &str, origin: Option<&str>, status: u16, duration: Duration) {
+    let entry = RequestLogEntry {
+        time: chrono::Local::now().to_rfc3339(),
+        method: method.to_string(),
+        url: url.to_string(),
+        origin: origin.map(|o| o.to_string()),
+        status,
+        duration_ms: duration.as_secs_f64() * 1000.0,
+    };
+
+    if crate::IS_DEV {
+        println!(
+            "[http] {} {} -> {} ({:.1}ms)",
+            entry.method, entry.url, entry.status, entry.duration_ms
+        );
+    }
+
+    // a poisoned log isn't worth failing a request over
+    if let Ok(mut log) = REQUEST_LOG.lock() {
+        if log.len() == MAX_ENTRIES {
+            log.pop_front();
+        }
+        log.push_back(entry);
+    }
+}
+
+/// oldest first
+pub fn entries() -> Vec<RequestLogEntry> {
+    REQUEST_LOG
+        .lock()
+        .map(|log| log.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn clear() {
+    if let Ok(mut log) = REQUEST_LOG.lock() {
+        log.clear();
+    }
+}
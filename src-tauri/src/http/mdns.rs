@@ -0,0 +1,107 @@
+use std::net::IpAddr;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+use super::error::HttpError;
+use crate::config::ServerSettings;
+
+// NOTE(mdns)
+//
+// the remote apps browse for `_cider-remote._tcp` instead of asking for an IP. TXT records:
+//
+//   api    version of the RPC/websocket API, bumped on breaking changes
+//   auth   what a client needs before it can call the API, `pairing` or `none`
+//   ws     path of the websocket, if it is enabled
+
+pub const SERVICE_TYPE: &str = "_cider-remote._tcp.local.";
+pub const API_VERSION: &str = "1";
+
+/// unregisters itself when dropped, so it can't outlive the server it points at
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // the goodbye packet is queued before the daemon is told to shut down
+        self.daemon.unregister(&self.fullname).ok();
+        self.daemon.shutdown().ok();
+    }
+}
+
+pub fn advertise(
+    settings: &ServerSettings,
+    port: u16,
+    websocket: bool,
+) -> Result<Advertisement, HttpError> {
+    let daemon = ServiceDaemon::new().map_err(|e| HttpError::Mdns(e.to_string()))?;
+
+    let host = hostname();
+    let instance = format!("Cider on {}", host);
+    let host_name = format!("{}.local.", host);
+
+    let auth = if settings.require_pairing {
+        "pairing"
+    } else {
+        "none"
+    };
+    let mut properties = vec![("api", API_VERSION), ("auth", auth)];
+    if websocket {
+        properties.push(("ws", "/ws"));
+    }
+
+    // bound to every interface, let the daemon work out which addresses to announce
+    let ip: IpAddr = settings
+        .bind_address
+        .parse()
+        .map_err(|_| HttpError::Mdns(format!("invalid address {}", settings.bind_address)))?;
+    let addresses = if ip.is_unspecified() {
+        String::new()
+    } else {
+        ip.to_string()
+    };
+
+    let mut service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance,
+        &host_name,
+        addresses.as_str(),
+        port,
+        &properties[..],
+    )
+    .map_err(|e| HttpError::Mdns(e.to_string()))?;
+
+    if ip.is_unspecified() {
+        service = service.enable_addr_auto();
+    }
+
+    let fullname = service.get_fullname().to_string();
+    daemon
+        .register(service)
+        .map_err(|e| HttpError::Mdns(e.to_string()))?;
+
+    Ok(Advertisement { daemon, fullname })
+}
+
+// mDNS names are a single label, no dots
+fn hostname() -> String {
+    let name = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_default();
+
+    let label: String = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    if label.is_empty() {
+        "cider".into()
+    } else {
+        label
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use futures::channel::oneshot;
+use tauri::{
+    async_runtime::{JoinHandle, Mutex},
+    plugin::{Builder as PluginBuilder, TauriPlugin},
+    AppHandle, Manager, Runtime,
+};
+use warp::{
+    http::{header, HeaderValue, StatusCode},
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+use crate::config::{ConfigChanged, ServerSettings};
+
+mod commands;
+pub mod error;
+pub mod log;
+mod mdns;
+mod pairing;
+
+use error::HttpError;
+use pairing::Pairing;
+
+// NOTE(http server)
+//
+// the RPC routes and the websocket are served by one warp server on `server.port`. it runs
+// while either of them is enabled, and everything on it goes through the same origin check,
+// CORS headers, request log and JSON error responses. other devices on the LAN also have
+// to be paired first, unless `server.require_pairing` is off.
+
+#[derive(Clone, Copy)]
+pub enum Feature {
+    Rpc,
+    WebSocket,
+}
+
+pub struct HttpServer {
+    running: Mutex<Option<Running>>,
+    rpc: Arc<AtomicBool>,
+    websocket: Arc<AtomicBool>,
+    pairing: Arc<Pairing>,
+}
+
+struct Running {
+    address: SocketAddr,
+    settings: ServerSettings,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+    advertisement: Option<mdns::Advertisement>,
+}
+
+impl HttpServer {
+    fn flag(&self, feature: Feature) -> &Arc<AtomicBool> {
+        match feature {
+            Feature::Rpc => &self.rpc,
+            Feature::WebSocket => &self.websocket,
+        }
+    }
+
+    /// advertises the server on the LAN while RPC is on, with TXT records matching what's enabled
+    fn advertise(&self, running: &mut Running) {
+        // the old record has to go first, it may point at another port
+        running.advertisement = None;
+
+        if !self.rpc.load(Ordering::SeqCst) || !exposed_on_lan(&running.settings) {
+            return;
+        }
+
+        let websocket = self.websocket.load(Ordering::SeqCst);
+        match mdns::advertise(&running.settings, running.address.port(), websocket) {
+            Ok(advertisement) => running.advertisement = Some(advertisement),
+            Err(e) => println!("Unable to advertise remote control service, {}", e),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ApiErrorResult {
+    detail: String,
+}
+
+#[derive(Debug)]
+struct OriginNotAllowed;
+
+impl warp::reject::Reject for OriginNotAllowed {}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(serde::Deserialize)]
+struct PairRequest {
+    token: String,
+    name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct PairResponse {
+    client: pairing::PairedClient,
+    token: String,
+}
+
+pub fn error_response(code: StatusCode, detail: impl Into<String>) -> Response {
+    let json = warp::reply::json(&ApiErrorResult {
+        detail: detail.into(),
+    });
+    warp::reply::with_status(json, code).into_response()
+}
+
+/// turns `feature` on, starting the server if nothing else has. returns the port it is on
+pub async fn enable<R>(
+    handle: &AppHandle<R>,
+    feature: Feature,
+    port: Option<u16>,
+) -> Result<u16, HttpError>
+where
+    R: Runtime,
+{
+    let state = handle.state::<HttpServer>();
+    let mut running = state.running.lock().await;
+
+    let mut settings = crate::config::server(handle).await;
+    if let Some(port) = port {
+        settings.port = port;
+    }
+
+    // there's only the one port, asking for another moves everything over
+    if running.as_ref().map(|r| r.address.port()) != Some(settings.port) {
+        stop(running.take()).await?;
+        *running = Some(start(handle, &state, &settings)?);
+    }
+
+    state.flag(feature).store(true, Ordering::SeqCst);
+    if let Some(running) = running.as_mut() {
+        state.advertise(running);
+    }
+
+    Ok(settings.port)
+}
+
+/// turns `feature` off, stopping the server once nothing is using it
+pub async fn disable<R>(handle: &AppHandle<R>, feature: Feature) -> Result<(), HttpError>
+where
+    R: Runtime,
+{
+    let state = handle.state::<HttpServer>();
+    let mut running = state.running.lock().await;
+
+    state.flag(feature).store(false, Ordering::SeqCst);
+    if let Feature::WebSocket = feature {
+        crate::ws::close_clients().await;
+    }
+
+    if !state.rpc.load(Ordering::SeqCst) && !state.websocket.load(Ordering::SeqCst) {
+        stop(running.take()).await?;
+    } else if let Some(running) = running.as_mut() {
+        state.advertise(running);
+    }
+
+    Ok(())
+}
+
+pub async fn is_enabled<R>(handle: &AppHandle<R>, feature: Feature) -> bool
+where
+    R: Runtime,
+{
+    let state = handle.state::<HttpServer>();
+    let running = state.running.lock().await.is_some();
+
+    running && state.flag(feature).load(Ordering::SeqCst)
+}
+
+/// picks up new `server` settings, if the server is running
+pub async fn restart<R>(handle: &AppHandle<R>) -> Result<(), HttpError>
+where
+    R: Runtime,
+{
+    let state = handle.state::<HttpServer>();
+    let mut running = state.running.lock().await;
+
+    if running.is_some() {
+        stop(running.take()).await?;
+        let settings = crate::config::server(handle).await;
+        let mut restarted = start(handle, &state, &settings)?;
+        state.advertise(&mut restarted);
+        *running = Some(restarted);
+    }
+
+    Ok(())
+}
+
+fn start<R>(
+    handle: &AppHandle<R>,
+    state: &HttpServer,
+    settings: &ServerSettings,
+) -> Result<Running, HttpError>
+where
+    R: Runtime,
+{
+    let ip: IpAddr = settings
+        .bind_address
+        .parse()
+        .map_err(|_| HttpError::Bind(format!("invalid address {}", settings.bind_address)))?;
+
+    let routes = routes(
+        handle.clone(),
+        state.rpc.clone(),
+        state.websocket.clone(),
+        state.pairing.clone(),
+        settings,
+    );
+
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let (address, server) = warp::serve(routes)
+        .try_bind_with_graceful_shutdown(SocketAddr::new(ip, settings.port), async {
+            signal.await.ok();
+        })
+        .map_err(|e| HttpError::Bind(format!("{}:{}: {}", ip, settings.port, e)))?;
+
+    Ok(Running {
+        address,
+        settings: settings.clone(),
+        shutdown,
+        task: tauri::async_runtime::spawn(server),
+        advertisement: None,
+    })
+}
+
+/// lets in-flight requests finish, then waits for the server to let go of the port
+async fn stop(running: Option<Running>) -> Result<(), HttpError> {
+    if let Some(mut running) = running {
+        // stop advertising before the port goes away, not after
+        running.advertisement = None;
+        running.shutdown.send(()).ok();
+        running
+            .task
+            .await
+            .map_err(|e| HttpError::Stop(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// whether another device could reach the server at all
+pub fn exposed_on_lan(settings: &ServerSettings) -> bool {
+    let loopback = settings
+        .bind_address
+        .parse::<IpAddr>()
+        .map(|ip| ip.is_loopback())
+        .unwrap_or(true);
+
+    settings.allow_lan && !loopback
+}
+
+/// rejects with a 404 unless `flag` is set, for routes that belong to a feature
+pub fn enabled(flag: Arc<AtomicBool>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let enabled = flag.load(Ordering::SeqCst);
+            async move {
+                if enabled {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn routes<R>(
+    handle: AppHandle<R>,
+    rpc: Arc<AtomicBool>,
+    websocket: Arc<AtomicBool>,
+    pairing: Arc<Pairing>,
+    settings: &ServerSettings,
+) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone + Send + Sync + 'static
+where
+    R: Runtime,
+{
+    let allowed_origins = Arc::new(settings.allowed_origins.clone());
+
+    let health_check = warp::path("health-check")
+        .and(warp::path::end())
+        .map(|| "OK".into_response());
+
+    let preflight = warp::options().map(|| {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, POST, PUT, OPTIONS"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("Authorization, Content-Type"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static("600"),
+        );
+        response
+    });
+
+    let pairing_store = pairing.clone();
+    let pair = warp::path("pair")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .map(move |body: PairRequest| {
+            match pairing_store.pair(&body.token, body.name.as_deref().unwrap_or_default()) {
+                Ok((client, token)) => {
+                    warp::reply::json(&PairResponse { client, token }).into_response()
+                }
+                Err(e) => error_response(StatusCode::UNAUTHORIZED, e.to_string()),
+            }
+        });
+
+    let features = crate::ws::routes(websocket)
+        .or(crate::rpc::server::routes(handle, rpc))
+        .unify();
+
+    let app = preflight
+        .or(health_check)
+        .unify()
+        .or(pair)
+        .unify()
+        .or(authorized(pairing, settings.require_pairing).and(features))
+        .unify()
+        .recover(handle_rejection)
+        .unify();
+
+    // requests without an origin aren't from a browser, so CORS doesn't apply to them
+    let origins = allowed_origins.clone();
+    let origin_check =
+        warp::header::optional::<String>("origin").and_then(move |origin: Option<String>| {
+            let allowed = match &origin {
+                Some(o) => origin_allowed(o, &origins),
+                None => true,
+            };
+
+            async move {
+                if allowed {
+                    Ok(origin)
+                } else {
+                    Err(warp::reject::custom(OriginNotAllowed))
+                }
+            }
+        });
+
+    origin_check
+        .and(app)
+        .map(move |origin: Option<String>, response: Response| {
+            with_cors(response, origin.as_deref(), &allowed_origins)
+        })
+        .recover(handle_rejection)
+        .unify()
+        .with(warp::log::custom(|info| {
+            let origin = info
+                .request_headers()
+                .get(header::ORIGIN)
+                .and_then(|o| o.to_str().ok());
+            log::record(
+                info.method().as_str(),
+                info.path(),
+                origin,
+                info.status().as_u16(),
+                info.elapsed(),
+            );
+        }))
+}
+
+/// lets through anything local, and anything else carrying a paired client's token
+fn authorized(
+    pairing: Arc<Pairing>,
+    required: bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |remote: Option<SocketAddr>,
+                  authorization: Option<String>,
+                  query: HashMap<String, String>| {
+                let local = remote.map(|a| a.ip().is_loopback()).unwrap_or(false);
+
+                // browsers can't set headers on a websocket, so the token can be in the query too
+                let token = authorization
+                    .as_deref()
+                    .and_then(|a| a.strip_prefix("Bearer "))
+                    .or_else(|| query.get("token").map(|t| t.as_str()));
+                let allowed =
+                    !required || local || token.map(|t| pairing.is_authorized(t)).unwrap_or(false);
+
+                async move {
+                    if allowed {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::custom(Unauthorized))
+                    }
+                }
+            },
+        )
+        .untuple_one()
+}
+
+fn origin_allowed(origin: &str, allowed_origins: &[String]) -> bool {
+    allowed_origins
+        .iter()
+        .any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+}
+
+fn with_cors(mut response: Response, origin: Option<&str>, allowed_origins: &[String]) -> Response {
+    let headers = response.headers_mut();
+
+    if allowed_origins.iter().any(|o| o == "*") {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+    } else if let Some(value) = origin
+        .filter(|o| origin_allowed(o, allowed_origins))
+        .and_then(|o| HeaderValue::from_str(o).ok())
+    {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    }
+
+    response
+}
+
+async fn handle_rejection(err: Rejection) -> Result<Response, Infallible> {
+    let (code, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not Found")
+    } else if err.find::<Unauthorized>().is_some() {
+        (StatusCode::UNAUTHORIZED, "Unauthorized")
+    } else if err.find::<OriginNotAllowed>().is_some() {
+        (StatusCode::FORBIDDEN, "Origin Not Allowed")
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        (StatusCode::BAD_REQUEST, "Invalid Query")
+    } else if err
+        .find::<warp::filters::body::BodyDeserializeError>()
+        .is_some()
+    {
+        (StatusCode::BAD_REQUEST, "Invalid Body")
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "Method not Allowed")
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+    };
+
+    Ok(error_response(code, message))
+}
+
+async fn apply<R>(handle: &AppHandle<R>, change: &ConfigChanged) -> Result<(), HttpError>
+where
+    R: Runtime,
+{
+    if change.touches("/server") {
+        restart(handle).await?;
+    }
+
+    let features = [
+        ("/rpc/enabled", Feature::Rpc, change.settings.rpc.enabled),
+        (
+            "/websocket/enabled",
+            Feature::WebSocket,
+            change.settings.websocket.enabled,
+        ),
+    ];
+
+    for (pointer, feature, on) in features {
+        if !change.touches(pointer) {
+            continue;
+        }
+
+        if on {
+            enable(handle, feature, None).await?;
+        } else {
+            disable(handle, feature).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init<R>() -> TauriPlugin<R>
+where
+    R: Runtime,
+{
+    PluginBuilder::new("http")
+        .invoke_handler(tauri::generate_handler![
+            commands::create_pairing_code,
+            commands::list_paired_clients,
+            commands::revoke_paired_client
+        ])
+        .setup(|app| {
+            let pairing_path = app
+                .path_resolver()
+                .app_config_dir()
+                .expect("Unknown Application Config Dir")
+                .join("paired-clients.json");
+
+            app.manage(HttpServer {
+                running: Mutex::new(None),
+                rpc: Arc::new(AtomicBool::new(false)),
+                websocket: Arc::new(AtomicBool::new(false)),
+                pairing: Arc::new(Pairing::load(pairing_path)),
+            });
+
+            // settings apply live, the server moves or features come and go without a restart
+            let handle = app.clone();
+            crate::config::on_change(app, move |change| {
+                let handle = handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = apply(&handle, &change).await {
+                        println!("Unable to apply server settings, {}", e);
+                    }
+                });
+            });
+
+            Ok(())
+        })
+        .build()
+}
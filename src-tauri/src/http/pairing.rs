@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::error::HttpError;
+
+// NOTE(pairing)
+//
+// `create_pairing_code` hands out a one-time token (inside a QR code) that's good for a few
+// minutes. the phone trades it at `POST /pair` for a client token of its own, which it sends
+// as `Authorization: Bearer <token>` (or `?token=` for the websocket) from then on. only a
+// hash of each client token is kept on disk.
+
+const PAIRING_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PairedClient {
+    pub id: String,
+    pub name: String,
+    // RFC 3339
+    pub paired_at: String,
+}
+
+// what's kept on disk, the token hash never leaves this module
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredClient {
+    #[serde(flatten)]
+    client: PairedClient,
+    token_hash: String,
+}
+
+struct Pending {
+    token: String,
+    created: Instant,
+}
+
+pub struct Pairing {
+    path: PathBuf,
+    pending: Mutex<Option<Pending>>,
+    clients: RwLock<Vec<StoredClient>>,
+}
+
+impl Pairing {
+    pub fn load(path: PathBuf) -> Self {
+        let clients = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            pending: Mutex::new(None),
+            clients: RwLock::new(clients),
+        }
+    }
+
+    /// replaces any token handed out before, only the newest QR code works
+    pub fn new_pairing_token(&self) -> (String, Duration) {
+        let token = new_token();
+        *self.pending.lock().unwrap() = Some(Pending {
+            token: token.clone(),
+            created: Instant::now(),
+        });
+
+        (token, PAIRING_TTL)
+    }
+
+    /// trades a pairing token for a client token, the pairing token can't be used again
+    pub fn pair(&self, token: &str, name: &str) -> Result<(PairedClient, String), HttpError> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.as_ref() {
+                Some(p) if p.token == token && p.created.elapsed() < PAIRING_TTL => {
+                    pending.take();
+                }
+                _ => {
+                    return Err(HttpError::Pairing(
+                        "invalid or expired pairing token".into(),
+                    ))
+                }
+            }
+        }
+
+        let client_token = new_token();
+        let client = PairedClient {
+            id: new_token()[..16].to_string(),
+            name: if name.trim().is_empty() {
+                "Remote".into()
+            } else {
+                name.trim().chars().take(64).collect()
+            },
+            paired_at: chrono::Local::now().to_rfc3339(),
+        };
+
+        let stored = StoredClient {
+            client: client.clone(),
+            token_hash: hash(&client_token),
+        };
+        self.update(|clients| clients.push(stored))?;
+
+        Ok((client, client_token))
+    }
+
+    pub fn is_authorized(&self, client_token: &str) -> bool {
+        let hashed = hash(client_token);
+        self.clients
+            .read()
+            .unwrap()
+            .iter()
+            .any(|c| c.token_hash == hashed)
+    }
+
+    pub fn clients(&self) -> Vec<PairedClient> {
+        self.clients
+            .read()
+            .unwrap()
+            .iter()
+            .map(|c| c.client.clone())
+            .collect()
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<(), HttpError> {
+        self.update(|clients| clients.retain(|c| c.client.id != id))
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Vec<StoredClient>)) -> Result<(), HttpError> {
+        let mut clients = self.clients.write().unwrap();
+        f(&mut clients);
+
+        let contents = serde_json::to_string_pretty(&*clients)
+            .map_err(|e| HttpError::Pairing(e.to_string()))?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| HttpError::Pairing(e.to_string()))?;
+        }
+        fs::write(&self.path, contents).map_err(|e| HttpError::Pairing(e.to_string()))
+    }
+}
+
+fn new_token() -> String {
+    rand::random::<[u8; 32]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
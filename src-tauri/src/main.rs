@@ -38,6 +38,8 @@ mod airplay;
 mod bridge;
 mod config;
 mod discord;
+mod dlna;
+mod http;
 mod lastfm;
 mod musickit;
 mod plugin;
@@ -79,6 +81,13 @@ async fn init_plugins() {
         let pg = pg.await;
         let pg = pg.as_ref().unwrap();
         pg.load().await;
+
+        // pick up edits to plugins without restarting while working on them
+        if IS_DEV {
+            if let Err(e) = pg.watch().await {
+                println!("Unable to watch plugins, {}", e);
+            }
+        }
     });
 }
 
@@ -104,11 +113,13 @@ async fn main() {
         win.show().expect("UNABLE TO SHOW WINDOW");
         win.set_focus().expect("UNABLE TO SET FOCUS");
     }))
+    // first, the others read their settings from it
+    .plugin(config::init())
+    .plugin(http::init())
     .plugin(discord::init())
     .plugin(lastfm::init())
     .plugin(airplay::init())
     .plugin(rpc::init())
-    .plugin(config::init())
     .plugin(vibrancy::init())
     .plugin(ws::init())
     .plugin(steam::init())
@@ -121,6 +132,19 @@ async fn main() {
 
       let runtime_env = if IS_DEV { "development" } else { "production" };
 
+      // tray settings apply live
+      let tray_handle = app.handle();
+      tauri::async_runtime::spawn(async move {
+        let settings = config::get(&tray_handle).await;
+        systemtray::apply_settings(&tray_handle, &settings.tray);
+      });
+      let tray_handle = app.handle();
+      config::on_change(&app.handle(), move |change| {
+        if change.touches("/tray") {
+          systemtray::apply_settings(&tray_handle, &change.settings.tray);
+        }
+      });
+
       // Setup deep link
       let h = app.handle();
 
@@ -232,7 +256,7 @@ async fn main() {
     })
     .system_tray(systemtray::init())
     .on_system_tray_event(systemtray::system_tray_event_handle)
-    .invoke_handler(tauri::generate_handler![set_itspod, init_plugins, systemtray::play, systemtray::pause, systemtray::change_song, additional::set_miniplayer_mode, updater::get_zip_update]);
+    .invoke_handler(tauri::generate_handler![set_itspod, init_plugins, systemtray::play, systemtray::pause, systemtray::change_song, additional::set_miniplayer_mode, updater::get_zip_update, plugin::commands::plugin_load_report, plugin::commands::list_plugins, plugin::commands::enable_plugin, plugin::commands::disable_plugin, plugin::commands::reload_plugin, plugin::commands::inspect_plugin, plugin::commands::install_plugin, plugin::commands::uninstall_plugin, plugin::commands::set_plugin_permissions, plugin::commands::plugin_call, plugin::commands::plugin_storage_get, plugin::commands::plugin_storage_set, plugin::commands::plugin_storage_delete, plugin::commands::plugin_storage_list]);
 
     let mut context: Context<EmbeddedAssets> = tauri::generate_context!();
     let mut should_zip = false;
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{api::notification::Notification, AppHandle, Runtime};
+
+use super::{error::PluginError, permissions::Permissions, storage};
+use crate::bridge::Bridge;
+
+// NOTE(plugin api)
+//
+// every call a plugin makes into Cider, from either half, ends up in `call` and is
+// checked against the permissions the user granted it.
+//
+//   `bridge.<action>`      playback     see `call_bridge`
+//   `network.fetch`        network      { "url", "method"?, "headers"?, "body"? }
+//   `notifications.show`   notifications { "title", "body" }
+//   `storage.get`          storage      { "key" }
+//   `storage.set`          storage      { "key", "value" }
+//   `storage.delete`       storage      { "key" }
+//   `storage.list`         storage      {}
+
+// standard JSON-RPC error codes, plus one of our own
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const PERMISSION_DENIED: i64 = -32001;
+pub const QUOTA_EXCEEDED: i64 = -32002;
+
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<PluginError> for ApiError {
+    fn from(e: PluginError) -> Self {
+        let code = match e {
+            PluginError::Quota(_) => QUOTA_EXCEEDED,
+            PluginError::PermissionDenied(_) => PERMISSION_DENIED,
+            _ => INTERNAL_ERROR,
+        };
+
+        ApiError::new(code, e.to_string())
+    }
+}
+
+impl From<ApiError> for PluginError {
+    fn from(e: ApiError) -> Self {
+        PluginError::Api {
+            code: e.code,
+            message: e.message,
+        }
+    }
+}
+
+pub async fn call<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+    permissions: &Permissions,
+    method: &str,
+    params: Value,
+) -> Result<Value, ApiError> {
+    match method.split_once('.') {
+        Some(("bridge", action)) => {
+            require(permissions.playback, "playback")?;
+
+            let handle = handle.clone();
+            let action = action.to_string();
+            tauri::async_runtime::spawn_blocking(move || {
+                call_bridge(Bridge::new(handle), &action, &params)
+            })
+            .await
+            .unwrap_or_else(|e| Err(ApiError::new(INTERNAL_ERROR, e.to_string())))
+        }
+        Some(("network", "fetch")) => fetch(permissions, params).await,
+        Some(("notifications", "show")) => {
+            require(permissions.notifications, "notifications")?;
+
+            Notification::new(&handle.config().tauri.bundle.identifier)
+                .title(param::<String>(&params, "title")?)
+                .body(param::<String>(&params, "body")?)
+                .show()
+                .map_err(|e| ApiError::new(INTERNAL_ERROR, e.to_string()))?;
+
+            Ok(Value::Null)
+        }
+        Some(("storage", action)) => {
+            require(permissions.storage, "storage")?;
+            call_storage(handle, id, action, &params).await
+        }
+        _ => Err(ApiError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {}", method),
+        )),
+    }
+}
+
+pub fn require(granted: bool, permission: &str) -> Result<(), ApiError> {
+    if granted {
+        Ok(())
+    } else {
+        Err(ApiError::new(
+            PERMISSION_DENIED,
+            format!("the `{}` permission was not granted", permission),
+        ))
+    }
+}
+
+pub fn param<T: DeserializeOwned>(params: &Value, key: &str) -> Result<T, ApiError> {
+    let value = params
+        .get(key)
+        .ok_or_else(|| ApiError::new(INVALID_PARAMS, format!("missing param `{}`", key)))?;
+
+    serde_json::from_value(value.clone())
+        .map_err(|e| ApiError::new(INVALID_PARAMS, format!("invalid param `{}`: {}", key, e)))
+}
+
+#[derive(Deserialize)]
+struct FetchRequest {
+    url: String,
+    method: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    body: Option<String>,
+}
+
+async fn fetch(permissions: &Permissions, params: Value) -> Result<Value, ApiError> {
+    let request: FetchRequest =
+        serde_json::from_value(params).map_err(|e| ApiError::new(INVALID_PARAMS, e.to_string()))?;
+
+    let url = reqwest::Url::parse(&request.url)
+        .map_err(|e| ApiError::new(INVALID_PARAMS, format!("invalid url: {}", e)))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ApiError::new(
+            INVALID_PARAMS,
+            "only http(s) urls are allowed",
+        ));
+    }
+
+    let host = url.host_str().unwrap_or_default().to_string();
+    if !permissions.allows_host(&host) {
+        return Err(ApiError::new(
+            PERMISSION_DENIED,
+            format!("network access to {} was not granted", host),
+        ));
+    }
+
+    let method = reqwest::Method::from_bytes(
+        request
+            .method
+            .unwrap_or_else(|| "GET".into())
+            .to_ascii_uppercase()
+            .as_bytes(),
+    )
+    .map_err(|e| ApiError::new(INVALID_PARAMS, e.to_string()))?;
+
+    // a redirect must not be a way around the host list
+    let allowed = permissions.clone();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::custom(
+            move |attempt| match attempt.url().host_str() {
+                Some(h) if allowed.allows_host(h) => attempt.follow(),
+                _ => attempt.stop(),
+            },
+        ))
+        .build()
+        .map_err(|e| ApiError::new(INTERNAL_ERROR, e.to_string()))?;
+
+    let mut builder = client.request(method, url);
+    for (k, v) in request.headers {
+        builder = builder.header(k, v);
+    }
+    if let Some(body) = request.body {
+        builder = builder.body(body);
+    }
+
+    let response = builder
+        .send()
+        .await
+        .map_err(|e| ApiError::new(INTERNAL_ERROR, e.to_string()))?;
+
+    let status = response.status().as_u16();
+    let headers: HashMap<String, String> = response
+        .headers()
+        .iter()
+        .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+        .collect();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| ApiError::new(INTERNAL_ERROR, e.to_string()))?;
+
+    Ok(json!({ "status": status, "headers": headers, "body": body }))
+}
+
+async fn call_storage<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+    action: &str,
+    params: &Value,
+) -> Result<Value, ApiError> {
+    let value = match action {
+        "get" => storage::get(handle, id, &param::<String>(params, "key")?)
+            .await?
+            .unwrap_or(Value::Null),
+        "set" => {
+            storage::set(
+                handle,
+                id,
+                &param::<String>(params, "key")?,
+                param(params, "value")?,
+            )
+            .await?;
+            Value::Null
+        }
+        "delete" => json!(storage::delete(handle, id, &param::<String>(params, "key")?).await?),
+        "list" => json!(storage::list(handle, id).await?),
+        _ => {
+            return Err(ApiError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown storage action {}", action),
+            ))
+        }
+    };
+
+    Ok(value)
+}
+
+fn call_bridge<R: Runtime>(
+    bridge: Bridge<R>,
+    action: &str,
+    params: &Value,
+) -> Result<Value, ApiError> {
+    let value = match action {
+        "play_pause" => {
+            bridge.play_pause();
+            Value::Null
+        }
+        "play" => {
+            let kind: Option<String> = param(params, "kind").ok();
+            let ids: Option<Vec<String>> = param(params, "ids").ok();
+            match (kind, ids) {
+                (Some(kind), Some(ids)) => {
+                    let ids: Vec<&str> = ids.iter().map(|i| i.as_str()).collect();
+                    bridge.play(Some(kind), Some(&ids));
+                }
+                _ => bridge.play(None, None),
+            }
+            Value::Null
+        }
+        "pause" => {
+            bridge.pause();
+            Value::Null
+        }
+        "stop" => {
+            bridge.stop();
+            Value::Null
+        }
+        "next" => {
+            bridge.next();
+            Value::Null
+        }
+        "previous" => {
+            bridge.previous();
+            Value::Null
+        }
+        "seekto" => {
+            bridge.seekto(param(params, "time")?);
+            Value::Null
+        }
+        "add_to_library" => {
+            bridge.add_to_library();
+            Value::Null
+        }
+        "get_playing_song" => bridge.get_playing_song(),
+        "is_playing" => json!(bridge.is_playing()),
+        "toggle_autoplay" => json!(bridge.toggle_autoplay()),
+        "toggle_shuffle" => json!(bridge.toggle_shuffle()),
+        "toggle_repeat" => json!(bridge.toggle_repeat()),
+        "album" => json!(bridge.album(&param::<String>(params, "id")?)),
+        "song" => json!(bridge.song(&param::<String>(params, "id")?)),
+        "get_volume" => json!(bridge.get_audio_volume()),
+        "set_volume" => {
+            bridge.set_audio_volume(param(params, "volume")?);
+            Value::Null
+        }
+        "set_rating" => {
+            bridge.set_rating(param(params, "rating")?);
+            Value::Null
+        }
+        _ => {
+            return Err(ApiError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown bridge action {}", action),
+            ))
+        }
+    };
+
+    Ok(value)
+}
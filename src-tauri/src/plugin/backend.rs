@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{
+    api::process::{Command, CommandChild, CommandEvent},
+    async_runtime::{JoinHandle, Mutex, Receiver, RwLock},
+    AppHandle, EventHandler, Manager, Runtime,
+};
+
+use super::{
+    api::{self, ApiError, INVALID_PARAMS, PARSE_ERROR},
+    error::PluginError,
+    permissions::Permissions,
+    PluginStatus, Registry,
+};
+
+// NOTE(backend plugins)
+//
+// `backend_main_script` is run by the `plugin-host` sidecar (plugin-host/, built by build.rs),
+// one process per plugin, with a cleared environment and the plugin's own folder as the
+// working directory. the script runs in an embedded QuickJS with no file, network, process
+// or environment bindings, see NOTE(plugin host). the only way back into Cider is JSON-RPC 2.0
+// over stdio, one message per line, so everything it does goes through `api::call` and the
+// permissions it was granted.
+//
+// in the script that's `CiderPlugin.call(method, params)`, `CiderPlugin.on(event, handler)`,
+// `CiderPlugin.off(event, handler)` and `CiderPlugin.log(message)`, plus timers and `console`.
+//
+// a backend that exits by itself marks its plugin as failed and emits `plugin-backend-exited`
+// with `{ "id", "reason" }`, it's not restarted.
+//
+// plugin -> host requests:
+//   `events.subscribe`   { "events": [..] }   -> the events now subscribed to
+//   `events.unsubscribe` { "events": [..] }   -> the events now subscribed to
+//   `log`                { "message": ".." }  -> null
+//   anything else is handed to `api::call`
+//
+// host -> plugin notifications:
+//   `initialize` { "id", "app_version", "api_version" } once on startup
+//   `event`      { "name", "payload" } for every subscribed playback event
+
+pub const API_VERSION: u32 = 1;
+pub const EXITED_EVENT: &str = "plugin-backend-exited";
+
+// emitted by the frontend whenever MusicKit reports a change
+pub const PLAYBACK_EVENTS: [&str; 4] = [
+    "playback-state-changed",
+    "now-playing-changed",
+    "playback-time-changed",
+    "playback-volume-changed",
+];
+
+type ChildState = Arc<Mutex<Option<CommandChild>>>;
+type Subscriptions = Arc<RwLock<HashSet<String>>>;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiError>,
+}
+
+#[derive(Serialize)]
+struct RpcNotification<'a> {
+    jsonrpc: &'static str,
+    method: &'a str,
+    params: Value,
+}
+
+pub struct Backend {
+    child: ChildState,
+    reader: JoinHandle<()>,
+    listeners: Vec<EventHandler>,
+}
+
+impl Backend {
+    /// `token` is the one the plugin is loaded with, an exit is only recorded while it's current
+    pub async fn spawn<R: Runtime>(
+        handle: AppHandle<R>,
+        registry: Registry,
+        id: &str,
+        token: &str,
+        dir: &Path,
+        script: &str,
+        permissions: Permissions,
+    ) -> Result<Self, PluginError> {
+        let script_path = dir.join(script);
+        if !script_path.is_file() {
+            return Err(PluginError::Backend(format!(
+                "{} not found",
+                script_path.display()
+            )));
+        }
+        let script_arg = script_path.to_str().ok_or_else(|| {
+            PluginError::Backend(format!("{} isn't valid UTF-8", script_path.display()))
+        })?;
+
+        let (rx, child) = Command::new_sidecar("plugin-host")
+            .map_err(|e| PluginError::Backend(e.to_string()))?
+            .args([script_arg])
+            .env_clear()
+            .envs(HashMap::from([(
+                "CIDER_PLUGIN_ID".to_string(),
+                id.to_string(),
+            )]))
+            .current_dir(dir.to_path_buf())
+            .spawn()
+            .map_err(|e| PluginError::Backend(format!("unable to start plugin-host, {}", e)))?;
+
+        let child: ChildState = Arc::new(Mutex::new(Some(child)));
+        let subscriptions: Subscriptions = Arc::new(RwLock::new(HashSet::new()));
+
+        let listeners = PLAYBACK_EVENTS
+            .iter()
+            .map(|&name| {
+                let child = child.clone();
+                let subscriptions = subscriptions.clone();
+                handle.listen_global(name, move |event| {
+                    let child = child.clone();
+                    let subscriptions = subscriptions.clone();
+                    let payload = event
+                        .payload()
+                        .and_then(|p| serde_json::from_str::<Value>(p).ok())
+                        .unwrap_or(Value::Null);
+
+                    tauri::async_runtime::spawn(async move {
+                        if subscriptions.read().await.contains(name) {
+                            notify(&child, "event", json!({ "name": name, "payload": payload }))
+                                .await;
+                        }
+                    });
+                })
+            })
+            .collect();
+
+        notify(
+            &child,
+            "initialize",
+            json!({
+                "id": id,
+                "app_version": handle.package_info().version.to_string(),
+                "api_version": API_VERSION,
+            }),
+        )
+        .await;
+
+        let reader = tauri::async_runtime::spawn(read_loop(
+            handle,
+            registry,
+            id.to_string(),
+            token.to_string(),
+            rx,
+            child.clone(),
+            subscriptions,
+            permissions,
+        ));
+
+        Ok(Self {
+            child,
+            reader,
+            listeners,
+        })
+    }
+
+    pub async fn stop<R: Runtime>(self, handle: &AppHandle<R>) {
+        for listener in self.listeners {
+            handle.unlisten(listener);
+        }
+
+        if let Some(child) = self.child.lock().await.take() {
+            child.kill().ok();
+        }
+
+        self.reader.abort();
+    }
+
+    // for when the process is already gone and this is being called from its own reader
+    fn detach<R: Runtime>(self, handle: &AppHandle<R>) {
+        for listener in self.listeners {
+            handle.unlisten(listener);
+        }
+    }
+}
+
+async fn read_loop<R: Runtime>(
+    handle: AppHandle<R>,
+    registry: Registry,
+    id: String,
+    token: String,
+    mut rx: Receiver<CommandEvent>,
+    child: ChildState,
+    subscriptions: Subscriptions,
+    permissions: Permissions,
+) {
+    while let Some(event) = rx.recv().await {
+        match event {
+            CommandEvent::Stdout(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                if let Some(response) =
+                    handle_message(&handle, &id, &subscriptions, &permissions, &line).await
+                {
+                    write(&child, &response).await;
+                }
+            }
+            CommandEvent::Stderr(line) => eprintln!("[{}] {}", id, line),
+            CommandEvent::Error(e) => eprintln!("[{}] {}", id, e),
+            CommandEvent::Terminated(payload) => {
+                println!("Backend for {} exited with {:?}", id, payload.code);
+                child.lock().await.take();
+                exited(&handle, &registry, &id, &token, payload.code).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn exited<R: Runtime>(
+    handle: &AppHandle<R>,
+    registry: &Registry,
+    id: &str,
+    token: &str,
+    code: Option<i32>,
+) {
+    let reason = match code {
+        Some(code) => format!("backend exited with code {}", code),
+        None => "backend was killed".to_string(),
+    };
+
+    {
+        let mut registry = registry.write().await;
+        let plugin = match registry.get_mut(id) {
+            // stopped or restarted in the meantime
+            Some(p) if p.token.as_deref() == Some(token) => p,
+            _ => return,
+        };
+
+        if let Some(backend) = plugin.backend.take() {
+            backend.detach(handle);
+        }
+        plugin.status = PluginStatus::Failed(reason.clone());
+    }
+
+    handle
+        .emit_all(EXITED_EVENT, json!({ "id": id, "reason": reason }))
+        .ok();
+}
+
+async fn handle_message<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+    subscriptions: &Subscriptions,
+    permissions: &Permissions,
+    line: &str,
+) -> Option<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_str(line) {
+        Ok(r) => r,
+        Err(e) => {
+            return Some(respond(
+                Value::Null,
+                Err(ApiError::new(PARSE_ERROR, e.to_string())),
+            ));
+        }
+    };
+
+    let result = match request.method.as_str() {
+        "events.subscribe" | "events.unsubscribe" => {
+            match api::require(permissions.playback, "playback") {
+                Ok(_) => {
+                    update_subscriptions(subscriptions, &request.method, &request.params).await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        "log" => api::param::<String>(&request.params, "message").map(|m| {
+            println!("[{}] {}", id, m);
+            Value::Null
+        }),
+        method => api::call(handle, id, permissions, method, request.params).await,
+    };
+
+    // no id means it was a notification, which never gets a response
+    request.id.map(|id| respond(id, result))
+}
+
+async fn update_subscriptions(
+    subscriptions: &Subscriptions,
+    method: &str,
+    params: &Value,
+) -> Result<Value, ApiError> {
+    let events: Vec<String> = api::param(params, "events")?;
+
+    if let Some(unknown) = events
+        .iter()
+        .find(|e| !PLAYBACK_EVENTS.contains(&e.as_str()))
+    {
+        return Err(ApiError::new(
+            INVALID_PARAMS,
+            format!("unknown event {}", unknown),
+        ));
+    }
+
+    let mut lock = subscriptions.write().await;
+    for event in events {
+        if method == "events.subscribe" {
+            lock.insert(event);
+        } else {
+            lock.remove(&event);
+        }
+    }
+
+    Ok(json!(lock.iter().collect::<Vec<_>>()))
+}
+
+fn respond(id: Value, result: Result<Value, ApiError>) -> RpcResponse {
+    match result {
+        Ok(result) => RpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result: Some(result),
+            error: None,
+        },
+        Err(error) => RpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result: None,
+            error: Some(error),
+        },
+    }
+}
+
+async fn notify(child: &ChildState, method: &str, params: Value) {
+    write(
+        child,
+        &RpcNotification {
+            jsonrpc: "2.0",
+            method,
+            params,
+        },
+    )
+    .await;
+}
+
+async fn write(child: &ChildState, message: &impl Serialize) {
+    let mut line = match serde_json::to_string(message) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    line.push('\n');
+
+    if let Some(child) = child.lock().await.as_mut() {
+        child.write(line.as_bytes()).ok();
+    }
+}
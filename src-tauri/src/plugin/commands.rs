@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use super::{error::PluginError, LoadReport, PackageInfo, Permissions, PluginInfo, StorageUsage};
+
+#[tauri::command]
+pub async fn plugin_load_report() -> Result<LoadReport, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => Ok(plugins.report().await),
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn list_plugins() -> Result<Vec<PluginInfo>, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => Ok(plugins.list().await),
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn enable_plugin(id: String) -> Result<(), PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.enable(&id).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn disable_plugin(id: String) -> Result<(), PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.disable(&id).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn reload_plugin(id: String) -> Result<(), PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.reload(&id).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn inspect_plugin(archive: String) -> Result<PackageInfo, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.inspect(&PathBuf::from(archive)),
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+/// `index` is a url or a local path to the package index the archive's sha256 is checked
+/// against, a signature on its entry is checked too if the app has signing keys. `approved` is
+/// what the user agreed to after seeing `inspect_plugin`
+#[tauri::command]
+pub async fn install_plugin(
+    archive: String,
+    index: String,
+    approved: Permissions,
+) -> Result<PluginInfo, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => {
+            plugins
+                .install(&PathBuf::from(archive), &index, approved)
+                .await
+        }
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn uninstall_plugin(id: String) -> Result<(), PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.uninstall(&id).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn set_plugin_permissions(id: String, granted: Permissions) -> Result<(), PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.set_permissions(&id, granted).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+/// the only way a plugin's frontend half is meant to reach the backend, see `scope.js`
+#[tauri::command]
+pub async fn plugin_call(
+    plugin: String,
+    token: String,
+    method: String,
+    params: Value,
+) -> Result<Value, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.call(&plugin, &token, &method, params).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+// the storage commands take the same token as `plugin_call`, a plugin only gets at its own
+#[tauri::command]
+pub async fn plugin_storage_get(
+    id: String,
+    token: String,
+    key: String,
+) -> Result<Option<Value>, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.storage_get(&id, &token, &key).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn plugin_storage_set(
+    id: String,
+    token: String,
+    key: String,
+    value: Value,
+) -> Result<(), PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.storage_set(&id, &token, &key, value).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn plugin_storage_delete(
+    id: String,
+    token: String,
+    key: String,
+) -> Result<bool, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.storage_delete(&id, &token, &key).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
+
+#[tauri::command]
+pub async fn plugin_storage_list(id: String, token: String) -> Result<StorageUsage, PluginError> {
+    match crate::PLUGINS.read().await.as_ref() {
+        Some(plugins) => plugins.storage_list(&id, &token).await,
+        None => Err(PluginError::NotInitialised),
+    }
+}
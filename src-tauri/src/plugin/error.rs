@@ -0,0 +1,34 @@
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Serialize, Error)]
+pub enum PluginError {
+    #[error("Metadata Error: {0}")]
+    Metadata(String),
+    #[error("Script Error: {0}")]
+    Script(String),
+    #[error("Backend Error: {0}")]
+    Backend(String),
+    #[error("Plugin Not Found: {0}")]
+    NotFound(String),
+    #[error("Plugin Disabled: {0}")]
+    Disabled(String),
+    #[error("Dependency Error: {0}")]
+    Dependency(String),
+    #[error("Package Error: {0}")]
+    Package(String),
+    #[error("Integrity Error: {0}")]
+    Integrity(String),
+    #[error("Permission Denied: {0}")]
+    PermissionDenied(String),
+    #[error("API Error {code}: {message}")]
+    Api { code: i64, message: String },
+    #[error("Storage Error: {0}")]
+    Storage(String),
+    #[error("Quota Exceeded: {0}")]
+    Quota(String),
+    #[error("IO Error: {0}")]
+    Io(String),
+    #[error("Plugins Not Initialised")]
+    NotInitialised,
+}
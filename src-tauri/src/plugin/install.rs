@@ -0,0 +1,199 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use zip::ZipArchive;
+
+use super::{error::PluginError, manifest::Metadata};
+
+// NOTE(plugin signing)
+//
+// an archive is installed when its sha256 matches the index entry. signatures are optional,
+// an entry that has one is also checked against the keys built into the app,
+// `PLUGIN_SIGNING_KEYS` at build time, comma separated base64 ed25519 public keys. a build
+// without keys can't check them and goes by the sha256 alone.
+const TRUSTED_KEYS: Option<&str> = option_env!("PLUGIN_SIGNING_KEYS");
+
+// well past anything a plugin needs, keeps a zip bomb from filling the disk
+const MAX_ENTRIES: usize = 4096;
+const MAX_UNPACKED_SIZE: u64 = 256 * 1024 * 1024;
+
+// anything with the symlink bit set in its unix mode
+const S_IFLNK: u32 = 0o120000;
+const S_IFMT: u32 = 0o170000;
+
+/// the list of published plugin packages and what their archives should hash to
+#[derive(serde::Deserialize)]
+pub struct PackageIndex {
+    pub packages: Vec<IndexEntry>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct IndexEntry {
+    pub id: String,
+    pub version: String,
+    pub sha256: String,
+    // base64 ed25519 signature over the raw sha256 digest
+    pub signature: Option<String>,
+}
+
+impl PackageIndex {
+    pub fn find(&self, id: &str, version: &str) -> Result<&IndexEntry, PluginError> {
+        self.packages
+            .iter()
+            .find(|e| e.id == id && e.version == version)
+            .ok_or_else(|| {
+                PluginError::Integrity(format!("{} {} is not listed in the index", id, version))
+            })
+    }
+}
+
+/// reads the index from a url or a local file
+pub async fn fetch_index(index: &str) -> Result<PackageIndex, PluginError> {
+    if index.starts_with("https://") || index.starts_with("http://") {
+        reqwest::get(index)
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| PluginError::Package(format!("unable to fetch index: {}", e)))?
+            .json::<PackageIndex>()
+            .await
+            .map_err(|e| PluginError::Package(format!("unable to parse index: {}", e)))
+    } else {
+        let contents = tokio::fs::read_to_string(index)
+            .await
+            .map_err(|e| PluginError::Package(format!("unable to read index: {}", e)))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| PluginError::Package(format!("unable to parse index: {}", e)))
+    }
+}
+
+pub fn verify(entry: &IndexEntry, digest: &[u8]) -> Result<(), PluginError> {
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    if !entry.sha256.eq_ignore_ascii_case(&hex) {
+        return Err(PluginError::Integrity(format!(
+            "sha256 mismatch, index says {} but the archive is {}",
+            entry.sha256, hex
+        )));
+    }
+
+    let keys: Vec<&str> = TRUSTED_KEYS
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .collect();
+
+    let signature = match &entry.signature {
+        Some(signature) if !keys.is_empty() => signature,
+        // unsigned, or nothing to check it against, the sha256 matching is enough
+        _ => return Ok(()),
+    };
+    let signature = general_purpose::STANDARD
+        .decode(signature)
+        .ok()
+        .and_then(|s| Signature::from_slice(&s).ok())
+        .ok_or_else(|| PluginError::Integrity("malformed signature".into()))?;
+
+    let trusted = keys.iter().any(|key| {
+        general_purpose::STANDARD
+            .decode(key)
+            .ok()
+            .and_then(|k| <[u8; 32]>::try_from(k.as_slice()).ok())
+            .and_then(|k| VerifyingKey::from_bytes(&k).ok())
+            .map(|k| k.verify(digest, &signature).is_ok())
+            .unwrap_or(false)
+    });
+
+    if !trusted {
+        return Err(PluginError::Integrity(
+            "signature does not match any trusted key".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn read_metadata<T: Read + Seek>(zip: &mut ZipArchive<T>) -> Result<Metadata, PluginError> {
+    let file = zip.by_name("metadata.json").map_err(|_| {
+        PluginError::Package("metadata.json must be at the root of the archive".into())
+    })?;
+
+    let metadata: Metadata = serde_json::from_reader(file)
+        .map_err(|e| PluginError::Metadata(format!("unable to parse metadata: {}", e)))?;
+
+    metadata.validate()?;
+
+    Ok(metadata)
+}
+
+/// unpacks everything into `dest`, refusing entries that would land outside of it
+pub fn extract<T: Read + Seek>(zip: &mut ZipArchive<T>, dest: &Path) -> Result<(), PluginError> {
+    if zip.len() > MAX_ENTRIES {
+        return Err(PluginError::Package(format!(
+            "archive has {} entries, at most {} are allowed",
+            zip.len(),
+            MAX_ENTRIES
+        )));
+    }
+
+    if dest.exists() {
+        fs::remove_dir_all(dest).map_err(|e| PluginError::Io(e.to_string()))?;
+    }
+    fs::create_dir_all(dest).map_err(|e| PluginError::Io(e.to_string()))?;
+
+    let mut unpacked: u64 = 0;
+    for i in 0..zip.len() {
+        let mut file = zip
+            .by_index(i)
+            .map_err(|e| PluginError::Package(e.to_string()))?;
+
+        let relative = match file.enclosed_name() {
+            Some(p) => p.to_owned(),
+            None => {
+                return Err(PluginError::Package(format!(
+                    "{} escapes the plugin folder",
+                    file.name()
+                )))
+            }
+        };
+
+        if file
+            .unix_mode()
+            .map(|m| m & S_IFMT == S_IFLNK)
+            .unwrap_or(false)
+        {
+            return Err(PluginError::Package(format!(
+                "{} is a symlink, which is not allowed",
+                file.name()
+            )));
+        }
+
+        let out_path = dest.join(relative);
+
+        if file.is_dir() {
+            fs::create_dir_all(&out_path).map_err(|e| PluginError::Io(e.to_string()))?;
+            continue;
+        }
+
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| PluginError::Io(e.to_string()))?;
+        }
+
+        // counted as it's written, the sizes in the archive can lie
+        let remaining = MAX_UNPACKED_SIZE - unpacked;
+        let mut out = File::create(&out_path).map_err(|e| PluginError::Io(e.to_string()))?;
+        unpacked += io::copy(&mut (&mut file).take(remaining + 1), &mut out)
+            .map_err(|e| PluginError::Io(e.to_string()))?;
+
+        if unpacked > MAX_UNPACKED_SIZE {
+            return Err(PluginError::Package(format!(
+                "archive unpacks to more than {} MiB",
+                MAX_UNPACKED_SIZE / 1024 / 1024
+            )));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use semver::{Version, VersionReq};
+
+use super::{error::PluginError, permissions::Permissions};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Metadata {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub authors: Vec<String>,
+    pub min_app_version: Option<String>,
+    pub max_app_version: Option<String>,
+    // plugin id -> semver requirement, e.g. `{ "lyrics-core": "^1.2" }`
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    #[serde(default)]
+    pub permissions: Permissions,
+    pub frontend_main_script: Option<String>,
+    pub backend_main_script: Option<String>,
+}
+
+impl Metadata {
+    /// checks everything that can be checked without knowing about the other plugins
+    pub fn validate(&self) -> Result<(), PluginError> {
+        if self.id.is_empty() {
+            return Err(PluginError::Metadata("`id` is empty".into()));
+        }
+
+        // hidden folders are reserved for installs in progress, and `..` would escape `plugins`
+        if self.id.starts_with('.') {
+            return Err(PluginError::Metadata("`id` may not start with '.'".into()));
+        }
+
+        // ids end up as directory names and event names, keep them boring
+        if !self.id.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_' || c == '.'
+        }) {
+            return Err(PluginError::Metadata(format!(
+                "`id` \"{}\" may only contain lowercase letters, digits, '-', '_' and '.'",
+                self.id
+            )));
+        }
+
+        self.parsed_version()?;
+        self.min_app_version()?;
+        self.max_app_version()?;
+
+        for (dep, req) in &self.dependencies {
+            if dep == &self.id {
+                return Err(PluginError::Metadata("plugin depends on itself".into()));
+            }
+
+            VersionReq::parse(req).map_err(|e| {
+                PluginError::Metadata(format!(
+                    "invalid requirement \"{}\" for {}: {}",
+                    req, dep, e
+                ))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn parsed_version(&self) -> Result<Version, PluginError> {
+        Version::parse(&self.version)
+            .map_err(|e| PluginError::Metadata(format!("invalid `version`: {}", e)))
+    }
+
+    fn min_app_version(&self) -> Result<Option<Version>, PluginError> {
+        parse_optional(&self.min_app_version, "min_app_version")
+    }
+
+    fn max_app_version(&self) -> Result<Option<Version>, PluginError> {
+        parse_optional(&self.max_app_version, "max_app_version")
+    }
+
+    /// returns the reason the plugin can't run on this version of the app, if any
+    pub fn check_app_version(&self, app_version: &Version) -> Option<String> {
+        if let Ok(Some(min)) = self.min_app_version() {
+            if app_version < &min {
+                return Some(format!(
+                    "requires Cider {} or newer (running {})",
+                    min, app_version
+                ));
+            }
+        }
+
+        if let Ok(Some(max)) = self.max_app_version() {
+            if app_version > &max {
+                return Some(format!(
+                    "requires Cider {} or older (running {})",
+                    max, app_version
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// returns the reason `provider` doesn't satisfy the dependency on `dep`, if any
+    pub fn check_dependency(&self, dep: &str, provider: Option<&Metadata>) -> Option<String> {
+        let provider = match provider {
+            Some(p) => p,
+            None => return Some(format!("missing dependency {}", dep)),
+        };
+
+        let req = match self.dependencies.get(dep).map(|r| VersionReq::parse(r)) {
+            Some(Ok(r)) => r,
+            _ => return Some(format!("invalid requirement for {}", dep)),
+        };
+
+        match provider.parsed_version() {
+            Ok(v) if req.matches(&v) => None,
+            Ok(v) => Some(format!("requires {} {} but {} is installed", dep, req, v)),
+            Err(e) => Some(format!("dependency {} is invalid: {}", dep, e)),
+        }
+    }
+}
+
+fn parse_optional(value: &Option<String>, field: &str) -> Result<Option<Version>, PluginError> {
+    match value {
+        None => Ok(None),
+        Some(v) => Version::parse(v)
+            .map(Some)
+            .map_err(|e| PluginError::Metadata(format!("invalid `{}`: {}", field, e))),
+    }
+}
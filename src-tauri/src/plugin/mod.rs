@@ -1,66 +1,236 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Read;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::{fs::File, io::BufReader};
 
-use tauri::{AppHandle, Manager, Runtime};
+use sha2::{Digest, Sha256};
+use tauri::{
+    async_runtime::{Mutex, RwLock},
+    AppHandle, Manager, Runtime,
+};
+use zip::ZipArchive;
 
-pub struct Plugin {}
+mod api;
+mod backend;
+pub mod commands;
+pub mod error;
+mod install;
+mod manifest;
+mod permissions;
+mod storage;
+mod watcher;
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Metadata {
-    name: String,
-    version: String,
-    description: String,
-    authors: Vec<String>,
-    frontend_main_script: Option<String>,
-    backend_main_script: Option<String>,
+use backend::Backend;
+use error::PluginError;
+use manifest::Metadata;
+pub use permissions::Permissions;
+pub use storage::StorageUsage;
+
+const SCOPE_SCRIPT: &str = include_str!("scope.js");
+
+// shared with the backends, so one exiting can mark its plugin as failed
+type Registry = Arc<RwLock<HashMap<String, Plugin>>>;
+
+pub struct Plugin {
+    metadata: Metadata,
+    path: PathBuf,
+    enabled: bool,
+    granted: Permissions,
+    status: PluginStatus,
+    backend: Option<Backend>,
+    // kept by the frontend half's bridge on load, `plugin_call` checks it so plugins can't act as each other
+    token: Option<String>,
+}
+
+impl Plugin {
+    fn new(
+        metadata: Metadata,
+        path: PathBuf,
+        state: &PersistedState,
+        status: PluginStatus,
+    ) -> Self {
+        Self {
+            enabled: !state.disabled.contains(&metadata.id),
+            granted: state.granted.get(&metadata.id).cloned().unwrap_or_default(),
+            metadata,
+            path,
+            status,
+            backend: None,
+            token: None,
+        }
+    }
+
+    /// what the plugin asked for and the user agreed to
+    fn permissions(&self) -> Permissions {
+        self.metadata.permissions.intersect(&self.granted)
+    }
+
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            id: self.metadata.id.clone(),
+            name: self.metadata.name.clone(),
+            version: self.metadata.version.clone(),
+            description: self.metadata.description.clone(),
+            authors: self.metadata.authors.clone(),
+            path: self.path.to_string_lossy().to_string(),
+            enabled: self.enabled,
+            permissions: self.metadata.permissions.clone(),
+            granted: self.granted.clone(),
+            status: self.status.clone(),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "snake_case")]
+pub enum PluginStatus {
+    Loaded,
+    Disabled,
+    Skipped(String),
+    Failed(String),
+}
+
+/// what the frontend gets when listing plugins
+#[derive(Clone, serde::Serialize)]
+pub struct PluginInfo {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub authors: Vec<String>,
+    pub path: String,
+    pub enabled: bool,
+    pub permissions: Permissions,
+    pub granted: Permissions,
+    #[serde(flatten)]
+    pub status: PluginStatus,
+}
+
+/// what happened to every plugin found during the last `load`, handed to the frontend as is
+#[derive(Clone, Default, serde::Serialize)]
+pub struct LoadReport {
+    pub loaded: Vec<LoadedPlugin>,
+    pub skipped: Vec<SkippedPlugin>,
+    pub failed: Vec<FailedPlugin>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct LoadedPlugin {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct SkippedPlugin {
+    pub id: String,
+    pub reason: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct FailedPlugin {
+    pub path: String,
+    pub error: String,
+}
+
+/// a plugin archive's manifest, for showing what it wants before installing it
+#[derive(Clone, serde::Serialize)]
+pub struct PackageInfo {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub authors: Vec<String>,
+    pub permissions: Permissions,
+}
+
+// persisted next to `spa-config.json`. only disabled ids are kept so new plugins start enabled,
+// `granted` is what the user agreed to for each plugin
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct PersistedState {
+    #[serde(default)]
+    disabled: HashSet<String>,
+    #[serde(default)]
+    granted: HashMap<String, Permissions>,
 }
 
-// TODO(freehelpdesk) - remove the _ when this hashmap is actually used
 pub struct Plugins<R: Runtime> {
     handle: AppHandle<R>,
     path: String,
-    _hashmap: HashMap<String, Box<Plugin>>,
+    registry: Registry,
+    report: RwLock<LoadReport>,
+    // held by whatever is loading, enabling or removing plugins. the registry itself is only
+    // locked for moments, starting a backend can take a while
+    lifecycle: Mutex<()>,
 }
 
 pub fn new<R: Runtime>(path: &str, handle: AppHandle<R>) -> Plugins<R> {
-    // Create our hash map
-    let map: HashMap<String, Box<Plugin>> = HashMap::new();
     Plugins {
         handle,
         path: path.to_string(),
-        _hashmap: map,
+        registry: Arc::new(RwLock::new(HashMap::new())),
+        report: RwLock::new(LoadReport::default()),
+        lifecycle: Mutex::new(()),
     }
 }
 
 impl<R: Runtime> Plugins<R> {
-    pub async fn load(&self) {
-        fs::create_dir_all(&self.path).unwrap();
-        for entry in fs::read_dir(&self.path).unwrap() {
-            let path = entry.unwrap().path();
-            println!("scanning {}", &path.to_str().unwrap());
-
-            // Try and find the metadata in the folder
-            let metadata_path = path.join("metadata.json");
-            let file = match File::open(&metadata_path) {
-                Ok(f) => f,
-                Err(_) => {
-                    println!(
-                        "Unable to load plugin, metadata.json not found at {}",
-                        metadata_path.clone().to_str().unwrap()
-                    );
+    pub async fn load(&self) -> LoadReport {
+        let _lifecycle = self.lifecycle.lock().await;
+        let mut report = LoadReport::default();
+        let app_version = self.handle.package_info().version.clone();
+        let state = self.read_state();
+
+        // a reload of the main window calls this again, don't leave the old processes behind
+        self.stop_backends().await;
+
+        let mut registry = self.registry.write().await;
+        registry.clear();
+
+        let mut candidates: HashMap<String, (PathBuf, Metadata)> = HashMap::new();
+
+        let entries = match fs::create_dir_all(&self.path).and_then(|_| fs::read_dir(&self.path)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("Unable to read plugins from {}, {}", &self.path, e);
+                report.failed.push(FailedPlugin {
+                    path: self.path.clone(),
+                    error: PluginError::Io(e.to_string()).to_string(),
+                });
+                *self.report.write().await = report.clone();
+                return report;
+            }
+        };
+
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    println!("Skipping unreadable plugin folder entry, {}", e);
                     continue;
                 }
             };
-            let reader = BufReader::new(&file);
-            let metadata: Metadata = match serde_json::from_reader(reader) {
+
+            // hidden folders are installs in progress
+            let hidden = path
+                .file_name()
+                .map(|n| n.to_string_lossy().starts_with('.'))
+                .unwrap_or(true);
+            if !path.is_dir() || hidden {
+                continue;
+            }
+            println!("scanning {}", path.display());
+
+            let metadata = match read_metadata(&path) {
                 Ok(m) => m,
-                Err(_) => {
-                    println!(
-                        "Unable to load plugin, was unable to parse metadata for {}",
-                        metadata_path.to_str().unwrap()
-                    );
+                Err(e) => {
+                    println!("Unable to load plugin at {}, {}", path.display(), e);
+                    report.failed.push(FailedPlugin {
+                        path: path.to_string_lossy().to_string(),
+                        error: e.to_string(),
+                    });
                     continue;
                 }
             };
@@ -71,31 +241,767 @@ impl<R: Runtime> Plugins<R> {
                 metadata.authors.join(", ")
             );
 
-            if let Some(frontend_path) = metadata.frontend_main_script {
-                let frontend_path = path.join(frontend_path);
-                let mut frontend = match File::open(&frontend_path) {
-                    Ok(f) => f,
-                    Err(_) => {
-                        println!(
-                            "Unable to load {}, {} not found",
-                            &metadata.name,
-                            &frontend_path.to_str().unwrap()
-                        );
-                        continue;
-                    }
-                };
-                let mut reader = BufReader::new(&mut frontend);
-                let mut contents = String::new();
-                reader
-                    .read_to_string(&mut contents)
-                    .expect("Unable to read config buffer");
-
-                self.handle
-                    .get_window("cider_main")
-                    .unwrap()
-                    .eval(contents.as_str())
-                    .unwrap();
+            if let Some((other, _)) = candidates.get(&metadata.id) {
+                report.skipped.push(SkippedPlugin {
+                    id: metadata.id.clone(),
+                    reason: format!(
+                        "duplicate id, already provided by {}",
+                        other.to_string_lossy()
+                    ),
+                });
+                continue;
             }
+
+            if let Some(reason) = metadata.check_app_version(&app_version) {
+                report.skipped.push(SkippedPlugin {
+                    id: metadata.id.clone(),
+                    reason: reason.clone(),
+                });
+                registry.insert(
+                    metadata.id.clone(),
+                    Plugin::new(metadata, path, &state, PluginStatus::Skipped(reason)),
+                );
+                continue;
+            }
+
+            candidates.insert(metadata.id.clone(), (path, metadata));
+        }
+
+        let (order, skipped) = resolve_order(&candidates);
+
+        for SkippedPlugin { id, reason } in skipped {
+            let (path, metadata) = candidates[&id].clone();
+            registry.insert(
+                id.clone(),
+                Plugin::new(
+                    metadata,
+                    path,
+                    &state,
+                    PluginStatus::Skipped(reason.clone()),
+                ),
+            );
+            report.skipped.push(SkippedPlugin { id, reason });
         }
+
+        // a backend exiting early needs the registry to mark its plugin, so it's let go
+        // of while they start
+        drop(registry);
+
+        // anything whose dependencies didn't load can't be loaded either
+        let mut unavailable: HashSet<String> = HashSet::new();
+
+        for id in order {
+            let (path, metadata) = candidates[&id].clone();
+            let mut plugin = Plugin::new(metadata, path, &state, PluginStatus::Disabled);
+
+            let mut start = plugin.enabled;
+            if let Some(dep) = plugin
+                .metadata
+                .dependencies
+                .keys()
+                .find(|d| unavailable.contains(*d))
+            {
+                if start {
+                    plugin.status =
+                        PluginStatus::Skipped(format!("dependency {} is not loaded", dep));
+                    start = false;
+                }
+            }
+
+            let name = plugin.metadata.name.clone();
+            self.registry.write().await.insert(id.clone(), plugin);
+
+            if start {
+                if let Err(e) = self.start(&id).await {
+                    println!("Unable to load {}, {}", name, e);
+                }
+            }
+
+            let registry = self.registry.read().await;
+            let plugin = match registry.get(&id) {
+                Some(plugin) => plugin,
+                None => continue,
+            };
+
+            match &plugin.status {
+                PluginStatus::Loaded => report.loaded.push(LoadedPlugin {
+                    id: id.clone(),
+                    name: plugin.metadata.name.clone(),
+                    version: plugin.metadata.version.clone(),
+                }),
+                PluginStatus::Disabled => report.skipped.push(SkippedPlugin {
+                    id: id.clone(),
+                    reason: "disabled".into(),
+                }),
+                PluginStatus::Skipped(reason) => report.skipped.push(SkippedPlugin {
+                    id: id.clone(),
+                    reason: reason.clone(),
+                }),
+                PluginStatus::Failed(error) => report.failed.push(FailedPlugin {
+                    path: plugin.path.to_string_lossy().to_string(),
+                    error: error.clone(),
+                }),
+            }
+
+            if !matches!(plugin.status, PluginStatus::Loaded) {
+                unavailable.insert(id.clone());
+            }
+        }
+
+        *self.report.write().await = report.clone();
+
+        report
+    }
+
+    pub async fn report(&self) -> LoadReport {
+        self.report.read().await.clone()
     }
+
+    pub async fn list(&self) -> Vec<PluginInfo> {
+        let mut plugins: Vec<PluginInfo> = self
+            .registry
+            .read()
+            .await
+            .values()
+            .map(|p| p.info())
+            .collect();
+
+        plugins.sort_by(|a, b| a.id.cmp(&b.id));
+        plugins
+    }
+
+    pub async fn enable(&self, id: &str) -> Result<(), PluginError> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let mut registry = self.registry.write().await;
+        let plugin = registry
+            .get(id)
+            .ok_or_else(|| PluginError::NotFound(id.to_string()))?;
+
+        let loaded = matches!(plugin.status, PluginStatus::Loaded);
+        if !loaded {
+            if let Some(reason) = self.unmet_requirement(&registry, &plugin.metadata) {
+                return Err(PluginError::Dependency(reason));
+            }
+        }
+
+        registry
+            .get_mut(id)
+            .ok_or_else(|| PluginError::NotFound(id.to_string()))?
+            .enabled = true;
+        drop(registry);
+
+        let result = if loaded { Ok(()) } else { self.start(id).await };
+
+        self.update_state(|state| {
+            state.disabled.remove(id);
+        })?;
+
+        result
+    }
+
+    pub async fn disable(&self, id: &str) -> Result<(), PluginError> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let mut registry = self.registry.write().await;
+
+        if !registry.contains_key(id) {
+            return Err(PluginError::NotFound(id.to_string()));
+        }
+
+        if let Some(dependent) = registry.values().find(|p| {
+            matches!(p.status, PluginStatus::Loaded) && p.metadata.dependencies.contains_key(id)
+        }) {
+            return Err(PluginError::Dependency(format!(
+                "{} depends on {}",
+                dependent.metadata.id, id
+            )));
+        }
+
+        let plugin = registry
+            .get_mut(id)
+            .ok_or_else(|| PluginError::NotFound(id.to_string()))?;
+        plugin.enabled = false;
+        self.stop(plugin, "plugin-disabled").await;
+        plugin.status = PluginStatus::Disabled;
+
+        drop(registry);
+        self.update_state(|state| {
+            state.disabled.insert(id.to_string());
+        })
+    }
+
+    pub async fn reload(&self, id: &str) -> Result<(), PluginError> {
+        let _lifecycle = self.lifecycle.lock().await;
+        {
+            let mut registry = self.registry.write().await;
+            let plugin = registry
+                .get_mut(id)
+                .ok_or_else(|| PluginError::NotFound(id.to_string()))?;
+
+            if !plugin.enabled {
+                return Err(PluginError::Disabled(id.to_string()));
+            }
+
+            let metadata = read_metadata(&plugin.path)?;
+            if metadata.id != id {
+                return Err(PluginError::Metadata(format!(
+                    "id changed from {} to {}, reload the window to pick it up",
+                    id, metadata.id
+                )));
+            }
+
+            self.stop(plugin, "plugin-reloading").await;
+            plugin.metadata = metadata;
+        }
+
+        self.start(id).await
+    }
+
+    /// lets the user agree to (a subset of) what a plugin asks for, restarting it if it's running
+    pub async fn set_permissions(&self, id: &str, granted: Permissions) -> Result<(), PluginError> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let mut registry = self.registry.write().await;
+        let plugin = registry
+            .get_mut(id)
+            .ok_or_else(|| PluginError::NotFound(id.to_string()))?;
+
+        // never store more than was asked for, a later update has to ask again
+        plugin.granted = plugin.metadata.permissions.intersect(&granted);
+        let stored = plugin.granted.clone();
+
+        let restart = matches!(plugin.status, PluginStatus::Loaded);
+        if restart {
+            self.stop(plugin, "plugin-reloading").await;
+        }
+        drop(registry);
+
+        let result = if restart {
+            self.start(id).await
+        } else {
+            Ok(())
+        };
+
+        self.update_state(|state| {
+            state.granted.insert(id.to_string(), stored);
+        })?;
+
+        result
+    }
+
+    /// reads the manifest out of a plugin archive without installing anything
+    pub fn inspect(&self, archive: &Path) -> Result<PackageInfo, PluginError> {
+        let file = File::open(archive).map_err(|e| PluginError::Io(e.to_string()))?;
+        let mut zip = ZipArchive::new(file).map_err(|e| PluginError::Package(e.to_string()))?;
+        let metadata = install::read_metadata(&mut zip)?;
+
+        Ok(PackageInfo {
+            id: metadata.id,
+            name: metadata.name,
+            version: metadata.version,
+            description: metadata.description,
+            authors: metadata.authors,
+            permissions: metadata.permissions,
+        })
+    }
+
+    /// runs a call from a plugin's frontend half through the scoped api
+    pub async fn call(
+        &self,
+        id: &str,
+        token: &str,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, PluginError> {
+        let permissions = self.permissions_for(id, token).await?;
+
+        api::call(&self.handle, id, &permissions, method, params)
+            .await
+            .map_err(PluginError::from)
+    }
+
+    /// what the plugin holding `token` may do, the same as `NotFound` if it isn't `id`'s
+    async fn permissions_for(&self, id: &str, token: &str) -> Result<Permissions, PluginError> {
+        let registry = self.registry.read().await;
+        match registry.get(id) {
+            Some(p) if p.token.as_deref() == Some(token) => match &p.status {
+                PluginStatus::Failed(reason) => Err(PluginError::Backend(reason.clone())),
+                _ => Ok(p.permissions()),
+            },
+            _ => Err(PluginError::NotFound(id.to_string())),
+        }
+    }
+
+    /// verifies `archive` against `index`, unpacks it into `plugins/<id>` and loads it,
+    /// replacing whatever version was installed before. `approved` is what the user agreed
+    /// to after seeing `inspect`, the install is refused if the plugin wants anything more
+    pub async fn install(
+        &self,
+        archive: &Path,
+        index: &str,
+        approved: Permissions,
+    ) -> Result<PluginInfo, PluginError> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let bytes = fs::read(archive).map_err(|e| PluginError::Io(e.to_string()))?;
+        let digest = Sha256::digest(&bytes);
+
+        let mut zip =
+            ZipArchive::new(Cursor::new(bytes)).map_err(|e| PluginError::Package(e.to_string()))?;
+        let metadata = install::read_metadata(&mut zip)?;
+
+        let index = install::fetch_index(index).await?;
+        install::verify(index.find(&metadata.id, &metadata.version)?, &digest)?;
+
+        if let Some(reason) = metadata.check_app_version(&self.handle.package_info().version) {
+            return Err(PluginError::Dependency(reason));
+        }
+
+        if !metadata.permissions.is_covered_by(&approved) {
+            return Err(PluginError::PermissionDenied(format!(
+                "{} asks for more than was approved",
+                metadata.id
+            )));
+        }
+
+        // unpack next to the real folder first so a bad archive can't leave half a plugin behind
+        let dest = PathBuf::from(&self.path).join(&metadata.id);
+        let staging = PathBuf::from(&self.path).join(format!(".{}.installing", metadata.id));
+        if let Err(e) = install::extract(&mut zip, &staging) {
+            fs::remove_dir_all(&staging).ok();
+            return Err(e);
+        }
+
+        // the guard has to be gone before stopping, an exiting backend needs the registry
+        let old = { self.registry.write().await.remove(&metadata.id) };
+        if let Some(mut old) = old {
+            self.stop(&mut old, "plugin-reloading").await;
+        }
+
+        if dest.exists() {
+            fs::remove_dir_all(&dest).map_err(|e| PluginError::Io(e.to_string()))?;
+        }
+        fs::rename(&staging, &dest).map_err(|e| PluginError::Io(e.to_string()))?;
+
+        println!("Installed plugin {} {}", metadata.id, metadata.version);
+
+        let granted = metadata.permissions.clone();
+        self.update_state(|state| {
+            state.granted.insert(metadata.id.clone(), granted);
+        })?;
+
+        self.register(&dest).await
+    }
+
+    pub async fn uninstall(&self, id: &str) -> Result<(), PluginError> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let mut registry = self.registry.write().await;
+
+        if let Some(dependent) = registry.values().find(|p| {
+            matches!(p.status, PluginStatus::Loaded) && p.metadata.dependencies.contains_key(id)
+        }) {
+            return Err(PluginError::Dependency(format!(
+                "{} depends on {}",
+                dependent.metadata.id, id
+            )));
+        }
+
+        let mut plugin = registry
+            .remove(id)
+            .ok_or_else(|| PluginError::NotFound(id.to_string()))?;
+        drop(registry);
+
+        self.stop(&mut plugin, "plugin-disabled").await;
+
+        fs::remove_dir_all(&plugin.path).map_err(|e| PluginError::Io(e.to_string()))?;
+        storage::clear(&self.handle, id).await?;
+
+        // forget everything else too, a reinstall starts fresh
+        self.update_state(|state| {
+            state.disabled.remove(id);
+            state.granted.remove(id);
+        })
+    }
+
+    /// adds the plugin in `path` to the registry and loads it if it's enabled
+    async fn register(&self, path: &Path) -> Result<PluginInfo, PluginError> {
+        let metadata = read_metadata(path)?;
+        let mut plugin = Plugin::new(
+            metadata,
+            path.to_path_buf(),
+            &self.read_state(),
+            PluginStatus::Disabled,
+        );
+
+        let id = plugin.metadata.id.clone();
+
+        let mut registry = self.registry.write().await;
+        let mut start = plugin.enabled;
+        if start {
+            if let Some(reason) = self.unmet_requirement(&registry, &plugin.metadata) {
+                plugin.status = PluginStatus::Skipped(reason);
+                start = false;
+            }
+        }
+        registry.insert(id.clone(), plugin);
+        drop(registry);
+
+        if start {
+            // the outcome ends up in `status`, which is what gets returned
+            self.start(&id).await.ok();
+        }
+
+        self.registry
+            .read()
+            .await
+            .get(&id)
+            .map(|p| p.info())
+            .ok_or(PluginError::NotFound(id))
+    }
+
+    /// why `metadata` can't be loaded right now given what else is loaded, if anything
+    fn unmet_requirement(
+        &self,
+        registry: &HashMap<String, Plugin>,
+        metadata: &Metadata,
+    ) -> Option<String> {
+        if let Some(reason) = metadata.check_app_version(&self.handle.package_info().version) {
+            return Some(reason);
+        }
+
+        // everything we depend on has to be up already
+        metadata.dependencies.keys().find_map(|dep| {
+            let provider = registry
+                .get(dep)
+                .filter(|p| matches!(p.status, PluginStatus::Loaded))
+                .map(|p| &p.metadata);
+            metadata.check_dependency(dep, provider)
+        })
+    }
+
+    pub async fn storage_get(
+        &self,
+        id: &str,
+        token: &str,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>, PluginError> {
+        self.ensure_storage(id, token).await?;
+        storage::get(&self.handle, id, key).await
+    }
+
+    pub async fn storage_set(
+        &self,
+        id: &str,
+        token: &str,
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<(), PluginError> {
+        self.ensure_storage(id, token).await?;
+        storage::set(&self.handle, id, key, value).await
+    }
+
+    pub async fn storage_delete(
+        &self,
+        id: &str,
+        token: &str,
+        key: &str,
+    ) -> Result<bool, PluginError> {
+        self.ensure_storage(id, token).await?;
+        storage::delete(&self.handle, id, key).await
+    }
+
+    pub async fn storage_list(&self, id: &str, token: &str) -> Result<StorageUsage, PluginError> {
+        self.ensure_storage(id, token).await?;
+        storage::list(&self.handle, id).await
+    }
+
+    // the same checks `storage.*` gets through `call`
+    async fn ensure_storage(&self, id: &str, token: &str) -> Result<(), PluginError> {
+        let permissions = self.permissions_for(id, token).await?;
+        api::require(permissions.storage, "storage")?;
+        Ok(())
+    }
+
+    /// the id of the plugin whose folder contains `path`, if any
+    pub async fn id_for_path(&self, path: &Path) -> Option<String> {
+        self.registry
+            .read()
+            .await
+            .values()
+            .find(|p| path.starts_with(&p.path))
+            .map(|p| p.metadata.id.clone())
+    }
+
+    pub async fn watch(&self) -> Result<(), PluginError> {
+        watcher::start(PathBuf::from(&self.path)).await
+    }
+
+    pub async fn stop_backends(&self) {
+        let backends: Vec<Backend> = {
+            let mut registry = self.registry.write().await;
+            registry
+                .values_mut()
+                .filter_map(|plugin| {
+                    plugin.token = None;
+                    plugin.backend.take()
+                })
+                .collect()
+        };
+
+        for backend in backends {
+            backend.stop(&self.handle).await;
+        }
+    }
+
+    /// loads both halves of the plugin `id` and records how that went on it. the registry
+    /// isn't held meanwhile, callers hold `lifecycle` so nothing else starts or removes it
+    async fn start(&self, id: &str) -> Result<(), PluginError> {
+        let (path, metadata, permissions) = {
+            let registry = self.registry.read().await;
+            let plugin = registry
+                .get(id)
+                .ok_or_else(|| PluginError::NotFound(id.to_string()))?;
+            (
+                plugin.path.clone(),
+                plugin.metadata.clone(),
+                plugin.permissions(),
+            )
+        };
+
+        let token = new_token();
+        let result = self.load_one(&path, &metadata, &permissions, &token).await;
+
+        let mut registry = self.registry.write().await;
+        let plugin = match registry.get_mut(id) {
+            Some(plugin) => plugin,
+            None => {
+                drop(registry);
+                if let Ok(Some(backend)) = result {
+                    backend.stop(&self.handle).await;
+                }
+                return Err(PluginError::NotFound(id.to_string()));
+            }
+        };
+
+        match result {
+            Ok(backend) => {
+                plugin.backend = backend;
+                plugin.token = Some(token);
+                plugin.status = PluginStatus::Loaded;
+                Ok(())
+            }
+            Err(e) => {
+                plugin.status = PluginStatus::Failed(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// stops the backend half and sends `event`, which `scope.js` takes as its cue to remove
+    /// the frontend half's frame
+    async fn stop(&self, plugin: &mut Plugin, event: &str) {
+        if let Some(backend) = plugin.backend.take() {
+            backend.stop(&self.handle).await;
+        }
+
+        if plugin.token.take().is_some() {
+            self.handle.emit_all(event, &plugin.metadata.id).ok();
+        }
+    }
+
+    /// starts the backend half then evaluates the frontend half, undoing the former if the latter fails
+    async fn load_one(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        permissions: &Permissions,
+        token: &str,
+    ) -> Result<Option<Backend>, PluginError> {
+        let backend = match &metadata.backend_main_script {
+            Some(script) => Some(
+                Backend::spawn(
+                    self.handle.clone(),
+                    self.registry.clone(),
+                    &metadata.id,
+                    token,
+                    path,
+                    script,
+                    permissions.clone(),
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        if let Err(e) = self.load_frontend(path, metadata, permissions, token) {
+            if let Some(backend) = backend {
+                backend.stop(&self.handle).await;
+            }
+            return Err(e);
+        }
+
+        Ok(backend)
+    }
+
+    fn load_frontend(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        permissions: &Permissions,
+        token: &str,
+    ) -> Result<(), PluginError> {
+        if let Some(frontend_path) = &metadata.frontend_main_script {
+            let frontend_path = path.join(frontend_path);
+            let mut frontend = File::open(&frontend_path).map_err(|_| {
+                PluginError::Script(format!("{} not found", frontend_path.display()))
+            })?;
+            let mut reader = BufReader::new(&mut frontend);
+            let mut contents = String::new();
+            reader
+                .read_to_string(&mut contents)
+                .map_err(|e| PluginError::Script(e.to_string()))?;
+
+            // the source goes in last so nothing in it gets mistaken for a placeholder
+            let script = SCOPE_SCRIPT
+                .replace("__PLUGIN_ID__", &json_string(&metadata.id))
+                .replace("__PLUGIN_TOKEN__", &json_string(token))
+                .replace(
+                    "__PLUGIN_PERMISSIONS__",
+                    &serde_json::to_string(permissions).unwrap(),
+                )
+                .replace("__PLUGIN_SOURCE__", &json_string(&contents));
+
+            self.handle
+                .get_window("cider_main")
+                .ok_or_else(|| PluginError::Script("main window not found".into()))?
+                .eval(script.as_str())
+                .map_err(|e| PluginError::Script(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.handle
+            .path_resolver()
+            .app_config_dir()
+            .expect("Unknown Application Config Dir")
+            .join("plugin-state.json")
+    }
+
+    fn read_state(&self) -> PersistedState {
+        File::open(self.state_path())
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default()
+    }
+
+    fn update_state(&self, f: impl FnOnce(&mut PersistedState)) -> Result<(), PluginError> {
+        let mut state = self.read_state();
+        f(&mut state);
+
+        let contents =
+            serde_json::to_string_pretty(&state).map_err(|e| PluginError::Io(e.to_string()))?;
+        fs::write(self.state_path(), contents).map_err(|e| PluginError::Io(e.to_string()))
+    }
+}
+
+fn new_token() -> String {
+    rand::random::<[u8; 32]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+fn read_metadata(path: &Path) -> Result<Metadata, PluginError> {
+    // Try and find the metadata in the folder
+    let metadata_path = path.join("metadata.json");
+    let file = File::open(&metadata_path).map_err(|_| {
+        PluginError::Metadata(format!(
+            "metadata.json not found at {}",
+            metadata_path.display()
+        ))
+    })?;
+    let reader = BufReader::new(&file);
+    let metadata: Metadata = serde_json::from_reader(reader)
+        .map_err(|e| PluginError::Metadata(format!("unable to parse metadata: {}", e)))?;
+
+    metadata.validate()?;
+
+    Ok(metadata)
+}
+
+/// works out which candidates have their dependencies met (recording why the rest don't)
+/// and returns them in an order where each plugin comes after everything it depends on
+fn resolve_order(
+    candidates: &HashMap<String, (PathBuf, Metadata)>,
+) -> (Vec<String>, Vec<SkippedPlugin>) {
+    let mut skipped = vec![];
+    let mut available: HashSet<&String> = candidates.keys().collect();
+
+    // dropping a plugin can break its dependents, so keep going until nothing changes
+    loop {
+        let mut unmet = vec![];
+        for id in available.iter() {
+            let metadata = &candidates[*id].1;
+            for dep in metadata.dependencies.keys() {
+                let provider = candidates
+                    .get(dep)
+                    .filter(|_| available.contains(dep))
+                    .map(|(_, m)| m);
+                if let Some(reason) = metadata.check_dependency(dep, provider) {
+                    unmet.push((*id, reason));
+                    break;
+                }
+            }
+        }
+
+        if unmet.is_empty() {
+            break;
+        }
+
+        for (id, reason) in unmet {
+            available.remove(id);
+            skipped.push(SkippedPlugin {
+                id: id.clone(),
+                reason,
+            });
+        }
+    }
+
+    let mut remaining: Vec<String> = available.into_iter().cloned().collect();
+    remaining.sort();
+
+    let mut order: Vec<String> = vec![];
+    loop {
+        let ready: Vec<String> = remaining
+            .iter()
+            .filter(|id| {
+                candidates[*id]
+                    .1
+                    .dependencies
+                    .keys()
+                    .all(|d| order.contains(d))
+            })
+            .cloned()
+            .collect();
+
+        if ready.is_empty() {
+            break;
+        }
+
+        remaining.retain(|id| !ready.contains(id));
+        order.extend(ready);
+    }
+
+    // whatever is left is part of (or depends on) a cycle
+    for id in remaining {
+        skipped.push(SkippedPlugin {
+            id,
+            reason: "dependency cycle".into(),
+        });
+    }
+
+    (order, skipped)
 }
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// what a plugin asks for in its manifest, and what the user has agreed to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    // hosts `network.fetch` may talk to, `*.example.com` covers every subdomain
+    pub network: Vec<String>,
+    pub storage: bool,
+    pub playback: bool,
+    pub notifications: bool,
+}
+
+impl Permissions {
+    pub fn allows_host(&self, host: &str) -> bool {
+        self.network
+            .iter()
+            .any(|pattern| host_matches(pattern, host))
+    }
+
+    /// true if everything in `self` is also in `other`
+    pub fn is_covered_by(&self, other: &Permissions) -> bool {
+        (!self.storage || other.storage)
+            && (!self.playback || other.playback)
+            && (!self.notifications || other.notifications)
+            && self.network.iter().all(|h| other.network.contains(h))
+    }
+
+    /// only what is in both, used to work out what a plugin can actually do
+    pub fn intersect(&self, other: &Permissions) -> Permissions {
+        Permissions {
+            network: self
+                .network
+                .iter()
+                .filter(|h| other.network.contains(h))
+                .cloned()
+                .collect(),
+            storage: self.storage && other.storage,
+            playback: self.playback && other.playback,
+            notifications: self.notifications && other.notifications,
+        }
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let host = host.to_ascii_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => pattern == host,
+    }
+}
@@ -0,0 +1,88 @@
+// runs a plugin's frontend script in a sandboxed iframe. it gets its own opaque origin, so
+// nothing in it can reach this window, `__TAURI__` or the IPC, the only way out is
+// `CiderPlugin.call`, which is posted back here and forwarded to `plugin_call` with the
+// plugin's token. the token never enters the frame. disabling or reloading the plugin
+// removes the frame, and everything the script was doing with it.
+(function () {
+  const id = __PLUGIN_ID__;
+  const token = __PLUGIN_TOKEN__;
+  const { invoke, event } = window.__TAURI__;
+
+  // set up inside the frame before the plugin's source is handed over
+  const loader = `<script>
+    (function () {
+      let next = 0;
+      const pending = new Map();
+
+      window.addEventListener("message", (e) => {
+        if (e.source !== window.parent) return;
+        const data = e.data || {};
+
+        if ("source" in data) {
+          Object.defineProperty(window, "CiderPlugin", {
+            value: Object.freeze({
+              id: data.id,
+              permissions: Object.freeze(data.permissions),
+              call: (method, params) =>
+                new Promise((resolve, reject) => {
+                  const call = next++;
+                  pending.set(call, { resolve, reject });
+                  window.parent.postMessage({ call, method, params: params || {} }, "*");
+                }),
+            }),
+          });
+          // indirect, so the plugin runs as a plain top level script
+          (0, eval)(data.source);
+          return;
+        }
+
+        const waiting = pending.get(data.call);
+        if (!waiting) return;
+        pending.delete(data.call);
+        "error" in data ? waiting.reject(data.error) : waiting.resolve(data.result);
+      });
+    })();
+  <\/script>`;
+
+  const frame = document.createElement("iframe");
+  // scripts only, without allow-same-origin it can't touch anything of ours
+  frame.setAttribute("sandbox", "allow-scripts");
+  frame.setAttribute("data-plugin", id);
+  frame.style.display = "none";
+  frame.srcdoc = loader;
+
+  const onMessage = (e) => {
+    if (e.source !== frame.contentWindow) return;
+    const { call, method, params } = e.data || {};
+    if (typeof call !== "number" || typeof method !== "string") return;
+
+    invoke("plugin_call", { plugin: id, token, method, params: params || {} }).then(
+      (result) => frame.contentWindow.postMessage({ call, result }, "*"),
+      (error) => frame.contentWindow.postMessage({ call, error }, "*")
+    );
+  };
+  window.addEventListener("message", onMessage);
+
+  frame.addEventListener(
+    "load",
+    () =>
+      frame.contentWindow.postMessage(
+        { id, permissions: __PLUGIN_PERMISSIONS__, source: __PLUGIN_SOURCE__ },
+        "*"
+      ),
+    { once: true }
+  );
+
+  const unlisten = [];
+  const unload = (e) => {
+    if (e.payload !== id) return;
+    window.removeEventListener("message", onMessage);
+    frame.remove();
+    unlisten.forEach((f) => f());
+  };
+  for (const name of ["plugin-disabled", "plugin-reloading"]) {
+    event.listen(name, unload).then((f) => unlisten.push(f));
+  }
+
+  document.body.appendChild(frame);
+})();
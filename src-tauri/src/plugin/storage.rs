@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+use tauri::{async_runtime::Mutex, AppHandle, Runtime};
+use tokio::fs::{create_dir_all, read_to_string, remove_file, rename, write as write_str};
+
+use super::error::PluginError;
+
+// every plugin gets its own json file under `plugin-data`, completely separate from `spa-config.json`
+
+// per plugin, measured on the serialised store
+pub const QUOTA_BYTES: usize = 1024 * 1024;
+pub const MAX_KEY_LENGTH: usize = 256;
+
+lazy_static::lazy_static! {
+    // read-modify-write has to happen one at a time or two sets can lose each other
+    static ref STORE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct StorageUsage {
+    pub keys: Vec<String>,
+    pub bytes: usize,
+    pub quota: usize,
+}
+
+pub async fn get<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+    key: &str,
+) -> Result<Option<Value>, PluginError> {
+    let _lock = STORE_LOCK.lock().await;
+    Ok(read_store(&store_path(handle, id)).await?.remove(key))
+}
+
+pub async fn set<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+    key: &str,
+    value: Value,
+) -> Result<(), PluginError> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(PluginError::Storage(format!(
+            "keys must be between 1 and {} bytes",
+            MAX_KEY_LENGTH
+        )));
+    }
+
+    let _lock = STORE_LOCK.lock().await;
+    let path = store_path(handle, id);
+
+    let mut store = read_store(&path).await?;
+    store.insert(key.to_string(), value);
+    write_store(&path, &store).await
+}
+
+/// returns whether there was anything to delete
+pub async fn delete<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+    key: &str,
+) -> Result<bool, PluginError> {
+    let _lock = STORE_LOCK.lock().await;
+    let path = store_path(handle, id);
+
+    let mut store = read_store(&path).await?;
+    if store.remove(key).is_none() {
+        return Ok(false);
+    }
+
+    write_store(&path, &store).await?;
+    Ok(true)
+}
+
+pub async fn list<R: Runtime>(
+    handle: &AppHandle<R>,
+    id: &str,
+) -> Result<StorageUsage, PluginError> {
+    let _lock = STORE_LOCK.lock().await;
+    let store = read_store(&store_path(handle, id)).await?;
+
+    Ok(StorageUsage {
+        bytes: serialised_size(&store)?,
+        keys: store.keys().cloned().collect(),
+        quota: QUOTA_BYTES,
+    })
+}
+
+/// drops everything a plugin has stored, used when it gets uninstalled
+pub async fn clear<R: Runtime>(handle: &AppHandle<R>, id: &str) -> Result<(), PluginError> {
+    let _lock = STORE_LOCK.lock().await;
+    let path = store_path(handle, id);
+
+    if path.exists() {
+        remove_file(path)
+            .await
+            .map_err(|e| PluginError::Storage(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+fn store_path<R: Runtime>(handle: &AppHandle<R>, id: &str) -> PathBuf {
+    handle
+        .path_resolver()
+        .app_config_dir()
+        .expect("Unknown Application Config Dir")
+        .join("plugin-data")
+        .join(format!("{}.json", id))
+}
+
+async fn read_store(path: &Path) -> Result<Map<String, Value>, PluginError> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+
+    let contents = read_to_string(path)
+        .await
+        .map_err(|e| PluginError::Storage(e.to_string()))?;
+
+    serde_json::from_str(&contents).map_err(|e| PluginError::Storage(e.to_string()))
+}
+
+async fn write_store(path: &Path, store: &Map<String, Value>) -> Result<(), PluginError> {
+    let contents = serde_json::to_string(store).map_err(|e| PluginError::Storage(e.to_string()))?;
+
+    if contents.len() > QUOTA_BYTES {
+        return Err(PluginError::Quota(format!(
+            "storing this would use {} of {} bytes",
+            contents.len(),
+            QUOTA_BYTES
+        )));
+    }
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)
+            .await
+            .map_err(|e| PluginError::Storage(e.to_string()))?;
+    }
+
+    // write next to it and swap, a crash mid-write shouldn't cost a plugin all of its data
+    let tmp = path.with_extension("json.tmp");
+    write_str(&tmp, contents)
+        .await
+        .map_err(|e| PluginError::Storage(e.to_string()))?;
+    rename(&tmp, path)
+        .await
+        .map_err(|e| PluginError::Storage(e.to_string()))
+}
+
+fn serialised_size(store: &Map<String, Value>) -> Result<usize, PluginError> {
+    serde_json::to_string(store)
+        .map(|s| s.len())
+        .map_err(|e| PluginError::Storage(e.to_string()))
+}
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tauri::async_runtime::{channel, Mutex, Receiver};
+
+use super::error::PluginError;
+
+lazy_static::lazy_static! {
+    static ref WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+}
+
+// editors tend to write a file a few times per save, wait for things to go quiet first
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub async fn start(path: PathBuf) -> Result<(), PluginError> {
+    let mut lock = WATCHER.lock().await;
+    if lock.is_some() {
+        return Ok(());
+    }
+
+    let (tx, rx) = channel::<PathBuf>(128);
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        if let Ok(event) = res {
+            for path in event.paths {
+                // dropping events is fine when the queue is full, we only need one per plugin
+                tx.try_send(path).ok();
+            }
+        }
+    })
+    .map_err(|e| PluginError::Io(e.to_string()))?;
+
+    watcher
+        .watch(&path, RecursiveMode::Recursive)
+        .map_err(|e| PluginError::Io(e.to_string()))?;
+
+    tauri::async_runtime::spawn(reload_loop(rx));
+
+    *lock = Some(watcher);
+    println!("Watching {} for plugin changes", path.display());
+
+    Ok(())
+}
+
+async fn reload_loop(mut rx: Receiver<PathBuf>) {
+    while let Some(first) = rx.recv().await {
+        let mut changed = vec![first];
+        while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+            changed.push(path);
+        }
+
+        let lock = crate::PLUGINS.read().await;
+        let plugins = match lock.as_ref() {
+            Some(p) => p,
+            None => continue,
+        };
+
+        let mut ids = HashSet::new();
+        for path in changed {
+            if let Some(id) = plugins.id_for_path(&path).await {
+                ids.insert(id);
+            }
+        }
+
+        for id in ids {
+            match plugins.reload(&id).await {
+                Ok(_) => println!("Reloaded plugin {}", id),
+                Err(e) => println!("Unable to reload {}, {}", id, e),
+            }
+        }
+    }
+}
@@ -1,59 +1,52 @@
-use super::{server::create_rpc_server, RPCServerThreadState};
-use tauri::{AppHandle, Runtime, State};
+use super::error::RpcError;
+use crate::http::{
+    self,
+    log::{self, RequestLogEntry},
+    Feature,
+};
+use tauri::{AppHandle, Runtime};
 
 #[tauri::command]
-pub fn handle_js_return(input: serde_json::Value) {
+pub fn handle_js_return(input: serde_json::Value) -> Result<(), RpcError> {
     super::server::CHANNELS
         .0
         .lock()
-        .unwrap()
+        .map_err(|e| RpcError::Channel(e.to_string()))?
         .send(input)
-        .unwrap();
+        .map_err(|e| RpcError::Channel(e.to_string()))
 }
 
 #[tauri::command]
-pub async fn start_rpc_server<R>(
-    port: Option<u16>,
-    app_handle: AppHandle<R>,
-    server_thread: State<'_, RPCServerThreadState>,
-) -> Result<(), String>
+pub async fn start_rpc_server<R>(port: Option<u16>, app_handle: AppHandle<R>) -> Result<(), RpcError>
 where
     R: Runtime,
 {
-    let mut lock = server_thread.lock().await;
-
-    if lock.is_some() {
-        return Err("Server Already Started".into());
-    }
-
-    let server = create_rpc_server(app_handle, port.unwrap_or_else(|| 10769u16));
-    let join_handle_combo = server.stoppable();
-
-    tauri::async_runtime::spawn_blocking(|| join_handle_combo.0.join());
-
-    *lock = Some(join_handle_combo.1);
-
+    http::enable(&app_handle, Feature::Rpc, port).await?;
     Ok(())
 }
 
 #[tauri::command]
-pub async fn stop_rpc_server(server_thread: State<'_, RPCServerThreadState>) -> Result<(), String> {
-    let mut lock = server_thread.lock().await;
-
-    match lock.as_ref() {
-      None => Ok(()),
-
-      Some(sender) => {
-        sender.send(()).map_err(|e| e.to_string())?;
+pub async fn stop_rpc_server<R>(app_handle: AppHandle<R>) -> Result<(), RpcError>
+where
+    R: Runtime,
+{
+    Ok(http::disable(&app_handle, Feature::Rpc).await?)
+}
 
-        *lock = None;
+#[tauri::command]
+pub async fn is_rpc_server_running<R>(app_handle: AppHandle<R>) -> Result<bool, RpcError>
+where
+    R: Runtime,
+{
+    Ok(http::is_enabled(&app_handle, Feature::Rpc).await)
+}
 
-        Ok(())
-      }
-    }
+#[tauri::command]
+pub fn rpc_request_log() -> Vec<RequestLogEntry> {
+  log::entries()
 }
 
 #[tauri::command]
-pub async fn is_rpc_server_running(server_thread: State<'_, RPCServerThreadState>) -> Result<bool, String> {
-  Ok(server_thread.lock().await.is_some())
+pub fn clear_rpc_request_log() {
+  log::clear()
 }
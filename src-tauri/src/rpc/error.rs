@@ -0,0 +1,12 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::http::error::HttpError;
+
+#[derive(Debug, Serialize, Error)]
+pub enum RpcError {
+    #[error(transparent)]
+    Server(#[from] HttpError),
+    #[error("Channel Error: {0}")]
+    Channel(String),
+}
@@ -5,44 +5,41 @@ use tauri::{
   plugin::{
     Builder as PluginBuilder,
     TauriPlugin
-  }, Manager, Runtime, Window
+  }, Runtime, Window
 };
-use tokio::sync::Mutex;
 
 pub mod server;
 mod commands;
-
-type RPCServerThreadState = Mutex<Option<std::sync::mpsc::Sender<()>>>;
+pub mod error;
 
 pub fn execute_and_receive_js<R>(window: &Window<R>, script: &str) -> serde_json::Value
 where
   R: Runtime,
 {
-  window
-      .eval(
-          format!(
-              "window.__TAURI__.invoke('plugin:rpc|handle_js_return', {{input: {}}})",
-              script
-          )
-          .as_str(),
+  let result = window.eval(
+      format!(
+          "window.__TAURI__.invoke('plugin:rpc|handle_js_return', {{input: {}}})",
+          script
       )
-      .unwrap();
+      .as_str(),
+  );
+
+  // the callers treat null as "no answer", which is what this is
+  if let Err(e) = result {
+    println!("Unable to run script for RPC, {}", e);
+    return Value::Null;
+  }
 
   server::CHANNELS
       .1
       .lock()
-      .unwrap()
-      .recv_timeout(Duration::from_millis(250))
+      .ok()
+      .and_then(|rx| rx.recv_timeout(Duration::from_millis(250)).ok())
       .unwrap_or(Value::Null)
 }
 
 pub fn init<R>() -> TauriPlugin<R> where R: Runtime {
   PluginBuilder::new("rpc")
-    .invoke_handler(tauri::generate_handler![commands::handle_js_return, commands::start_rpc_server, commands::stop_rpc_server, commands::is_rpc_server_running])
-    .setup(|app| {
-      app.manage::<RPCServerThreadState>(Mutex::new(None));
-
-      Ok(())
-    })
+    .invoke_handler(tauri::generate_handler![commands::handle_js_return, commands::start_rpc_server, commands::stop_rpc_server, commands::is_rpc_server_running, commands::rpc_request_log, commands::clear_rpc_request_log])
     .build()
 }
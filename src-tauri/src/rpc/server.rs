@@ -1,18 +1,31 @@
-use std::sync::{
-    mpsc::{channel, Receiver, Sender},
-    Arc, Mutex,
+use std::{
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    str::FromStr,
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
 };
 
 use crate::{
     bridge::Bridge,
+    http::{self, error_response},
     lastfm::{AuthState, LastFm},
 };
 
-use serde_json::Value;
+use futures::FutureExt;
+use serde_json::{json, Value};
 
 use tauri::{AppHandle, Manager, Runtime};
 
-use rouille::{Response, Server};
+use warp::{
+    http::{Method, StatusCode},
+    path::FullPath,
+    reply::Response,
+    Filter, Rejection, Reply,
+};
 
 type ChannelChan = (Arc<Mutex<Sender<Value>>>, Arc<Mutex<Receiver<Value>>>);
 
@@ -23,224 +36,279 @@ lazy_static::lazy_static! {
   };
 }
 
-pub fn create_rpc_server<R>(
+/// every RPC route, mounted on the shared http server while RPC is enabled
+pub fn routes<R>(
+    handle: AppHandle<R>,
+    enabled: Arc<AtomicBool>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone + Send + Sync + 'static
+where
+    R: Runtime,
+{
+    http::enabled(enabled)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |method: Method, path: FullPath, query: HashMap<String, String>| {
+                let handle = handle.clone();
+                async move {
+                    // a handler panicking shouldn't look any different to the client than any other failure
+                    let response = AssertUnwindSafe(route(handle, method, path, query))
+                        .catch_unwind()
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(error_response(
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Internal Server Error",
+                            ))
+                        });
+
+                    Ok::<_, Rejection>(response.unwrap_or_else(|e| e))
+                }
+            },
+        )
+}
+
+async fn route<R>(
     handle: AppHandle<R>,
-    port: u16,
-) -> Server<impl Fn(&rouille::Request) -> Response>
+    method: Method,
+    path: FullPath,
+    query: HashMap<String, String>,
+) -> Result<Response, Response>
 where
     R: Runtime,
 {
-    let bridge = Bridge::new(handle.clone());
-
-    let server = Server::new(format!("localhost:{port}"), move |req| {
-      rouille::router!(req,
-
-          (GET) (/handleCallbackUrl) => {
-              Response::empty_204()
-          },
-
-          (GET) (/last_fm_auth_callback) => {
-              let state = handle.state::<LastFm>();
-
-              let mut url = req.raw_url().to_string();
-
-              let offset = url.rfind("?token=").expect("INVALID URL");
-
-              url.replace_range(..offset+7, "");
-
-              let s = state
-              .inner_client
-              .blocking_write()
-              .authenticate_with_token(&url);
-
-              match s {
-                  Ok(_) => {
-                      state.set_auth_state(AuthState::Authorised);
-                  }
-                  Err(e) => {
-                      eprintln!("{:?}", e);
-                      return Response::empty_400();
-                  }
-              }
-
-              Response::empty_204()
-          },
-
-          (GET) (/active) => {
-              Response::empty_204()
-          },
-
-          (GET) (/currentPlayingSong) => {
-              #[derive(serde::Deserialize, serde::Serialize)]
-              struct Info {
-                  info: serde_json::Value
-              }
-
-              Response::json(&Info {
-                  info: bridge.get_playing_song(),
-              }).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (GET) (/addToLibrary) => {
-              bridge.add_to_library();
-              Response::empty_204()
-          },
-
-          (GET) (/isPlaying) => {
-              #[derive(serde::Deserialize, serde::Serialize)]
-              struct IsPlaying {
-                  is_playing: Option<bool>
-              }
-
-              Response::json(&IsPlaying {
-                  is_playing: bridge.is_playing(),
-              }).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (GET) (/toggleAutoplay) => {
-              #[derive(serde::Deserialize, serde::Serialize)]
-              struct IsAutoplay {
-                  autoplay: Option<bool>
-              }
-
-              Response::json(&IsAutoplay {
-                  autoplay: bridge.toggle_autoplay(),
-              }).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (POST) (/toggleShuffle) => {
-            Response::json(&bridge.toggle_shuffle()).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (POST) (/toggleRepeat) => {
-            Response::json(&bridge.toggle_repeat()).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (GET) (/playPause) => {
-              bridge.play_pause();
-              Response::empty_204()
-          },
-
-          (GET) (/play/{kind: String}/{ids: String}) => {
-              let ids: Vec<&str> = ids.split(',').collect();
-              bridge.play(Some(kind), Some(&ids));
-              Response::empty_204()
-          },
-
-          (GET) (/play) => {
-              bridge.play(None, None);
-              Response::empty_204()
-          },
-
-          (GET) (/pause) => {
-              bridge.pause();
-              Response::empty_204()
-          },
-
-          (GET) (/stop) => {
-              bridge.stop();
-              Response::empty_204()
-          },
-
-          (GET) (/next) => {
-              bridge.next();
-              Response::empty_204()
-          },
-
-          (GET) (/previous) => {
-              bridge.previous();
-              Response::empty_204()
-          },
-
-          (GET) (/seekto/{t: u32}) => {
-              bridge.seekto(t);
-              Response::empty_204()
-          },
-
-          (GET) (/show) => {
-              bridge.show();
-              Response::empty_204()
-          },
-
-          (GET) (/hide) => {
-              bridge.hide();
-              Response::empty_204()
-          },
-
-          (GET) (/album/{id: String}) => {
-              Response::json(&bridge.album(&id)).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (GET) (/song/{id: String}) => {
-              Response::json(&bridge.song(&id)).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (GET) (/audio/{volume: f32}) => {
-              bridge.set_audio_volume(volume);
-              Response::empty_204()
-          },
-
-          (GET) (/audio) => {
-              Response::json(&bridge.get_audio_volume()).with_additional_header("Access-Control-Allow-Origin", "*")
-          },
-
-          (PUT) (/setRating/{rating: i8}) => {
-              bridge.set_rating(rating);
-              Response::empty_204()
-          },
-
-          (PUT) (/rating/{content_type: String}/{id: u64}/{rating: i8}) => {
-              let a = bridge.set_rating_api(content_type, id.to_string(), rating);
-
-              if let Some(value) = a {
-                  let mut status = 200u16;
-
-                  if let Some(code) = value.get("code") {
-                      if let Some(actual_code) = code.as_u64() {
-                          status = actual_code as u16
-                      }
-                  }
-
-                  Response::json(&value).with_additional_header("Access-Control-Allow-Origin", "*").with_status_code(status)
-              } else {
-                  // me when the delete request has no return body
-                  // kill me
-                  if rating != 0 {
-                      Response::text("Unable to Set Rating").with_status_code(500)
-                  } else {
-                      Response::empty_204()
-                  }
-              }
-          },
-
-          (GET) (/rating/{content_type: String}/{id: u64}) => {
-              let a = bridge.get_rating(content_type, id.to_string());
-
-              if let Some(value) = a {
-                  let mut status = 200u16;
-
-                  if let Some(code) = value.get("code") {
-                      if let Some(actual_code) = code.as_u64() {
-                          status = actual_code as u16
-                      }
-                  }
-
-                  Response::json(&value).with_additional_header("Access-Control-Allow-Origin", "*").with_status_code(status)
-              } else {
-                  Response::text("Unable to Get Rating").with_status_code(500)
-              }
-          },
-
-          // Add a song ID to the current queue
-          // (GET) (/queue/{id: String}) => {
-          //     bridge.add_trackid_to_queue(&id);
-          //     Response::empty_404()
-          // },
-
-          _ => Response::empty_404()
-      )
-  })
-  .expect("RPC Server FAILED to Start");
-
-    server
+    let segments: Vec<&str> = path.as_str().trim_matches('/').split('/').collect();
+
+    let response = match (method.as_str(), segments.as_slice()) {
+        ("GET", ["handleCallbackUrl"]) | ("GET", ["active"]) => no_content(),
+
+        ("GET", ["last_fm_auth_callback"]) => {
+            let token = query
+                .get("token")
+                .cloned()
+                .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "Missing token"))?;
+
+            let h = handle.clone();
+            let result = tauri::async_runtime::spawn_blocking(move || {
+                let state = h.state::<LastFm>();
+                let result = state
+                    .inner_client
+                    .blocking_write()
+                    .authenticate_with_token(&token);
+
+                if result.is_ok() {
+                    state.set_auth_state(AuthState::Authorised);
+                }
+
+                result.map_err(|e| format!("{:?}", e))
+            })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+            match result {
+                Ok(_) => no_content(),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("Last.fm authentication failed: {}", e),
+                    ));
+                }
+            }
+        }
+
+        ("GET", ["currentPlayingSong"]) => {
+            let info = call(&handle, |b| b.get_playing_song()).await?;
+            warp::reply::json(&json!({ "info": info })).into_response()
+        }
+
+        ("GET", ["addToLibrary"]) => {
+            call(&handle, |b| b.add_to_library()).await?;
+            no_content()
+        }
+
+        ("GET", ["isPlaying"]) => {
+            let is_playing = call(&handle, |b| b.is_playing()).await?;
+            warp::reply::json(&json!({ "is_playing": is_playing })).into_response()
+        }
+
+        ("GET", ["toggleAutoplay"]) => {
+            let autoplay = call(&handle, |b| b.toggle_autoplay()).await?;
+            warp::reply::json(&json!({ "autoplay": autoplay })).into_response()
+        }
+
+        ("POST", ["toggleShuffle"]) => {
+            warp::reply::json(&call(&handle, |b| b.toggle_shuffle()).await?).into_response()
+        }
+
+        ("POST", ["toggleRepeat"]) => {
+            warp::reply::json(&call(&handle, |b| b.toggle_repeat()).await?).into_response()
+        }
+
+        ("GET", ["playPause"]) => {
+            call(&handle, |b| b.play_pause()).await?;
+            no_content()
+        }
+
+        ("GET", ["play", kind, ids]) => {
+            let kind = kind.to_string();
+            let ids: Vec<String> = ids.split(',').map(|i| i.to_string()).collect();
+            call(&handle, move |b| {
+                let ids: Vec<&str> = ids.iter().map(|i| i.as_str()).collect();
+                b.play(Some(kind), Some(&ids))
+            })
+            .await?;
+            no_content()
+        }
+
+        ("GET", ["play"]) => {
+            call(&handle, |b| b.play(None, None)).await?;
+            no_content()
+        }
+
+        ("GET", ["pause"]) => {
+            call(&handle, |b| b.pause()).await?;
+            no_content()
+        }
+
+        ("GET", ["stop"]) => {
+            call(&handle, |b| b.stop()).await?;
+            no_content()
+        }
+
+        ("GET", ["next"]) => {
+            call(&handle, |b| b.next()).await?;
+            no_content()
+        }
+
+        ("GET", ["previous"]) => {
+            call(&handle, |b| b.previous()).await?;
+            no_content()
+        }
+
+        ("GET", ["seekto", t]) => {
+            let t: u32 = param(t, "time")?;
+            call(&handle, move |b| b.seekto(t)).await?;
+            no_content()
+        }
+
+        ("GET", ["show"]) => {
+            call(&handle, |b| b.show()).await?;
+            no_content()
+        }
+
+        ("GET", ["hide"]) => {
+            call(&handle, |b| b.hide()).await?;
+            no_content()
+        }
+
+        ("GET", ["album", id]) => {
+            let id = id.to_string();
+            warp::reply::json(&call(&handle, move |b| b.album(&id)).await?).into_response()
+        }
+
+        ("GET", ["song", id]) => {
+            let id = id.to_string();
+            warp::reply::json(&call(&handle, move |b| b.song(&id)).await?).into_response()
+        }
+
+        ("GET", ["audio", volume]) => {
+            let volume: f32 = param(volume, "volume")?;
+            call(&handle, move |b| b.set_audio_volume(volume)).await?;
+            no_content()
+        }
+
+        ("GET", ["audio"]) => {
+            warp::reply::json(&call(&handle, |b| b.get_audio_volume()).await?).into_response()
+        }
+
+        ("PUT", ["setRating", rating]) => {
+            let rating: i8 = param(rating, "rating")?;
+            call(&handle, move |b| b.set_rating(rating)).await?;
+            no_content()
+        }
+
+        ("PUT", ["rating", content_type, id, rating]) => {
+            let content_type = content_type.to_string();
+            let id: u64 = param(id, "id")?;
+            let rating: i8 = param(rating, "rating")?;
+
+            let a = call(&handle, move |b| {
+                b.set_rating_api(content_type, id.to_string(), rating)
+            })
+            .await?;
+
+            match a {
+                Some(value) => json_with_code(&value),
+                // me when the delete request has no return body
+                // kill me
+                None if rating == 0 => no_content(),
+                None => {
+                    return Err(error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Unable to Set Rating",
+                    ))
+                }
+            }
+        }
+
+        ("GET", ["rating", content_type, id]) => {
+            let content_type = content_type.to_string();
+            let id: u64 = param(id, "id")?;
+
+            match call(&handle, move |b| b.get_rating(content_type, id.to_string())).await? {
+                Some(value) => json_with_code(&value),
+                None => {
+                    return Err(error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Unable to Get Rating",
+                    ))
+                }
+            }
+        }
+
+        // Add a song ID to the current queue
+        // ("GET", ["queue", id]) => {
+        //     bridge.add_trackid_to_queue(&id);
+        //     Response::empty_404()
+        // },
+        _ => return Err(error_response(StatusCode::NOT_FOUND, "Not Found")),
+    };
+
+    Ok(response)
+}
+
+/// bridge calls wait on the webview, so they get a blocking thread instead of holding up the server
+async fn call<R, T, F>(handle: &AppHandle<R>, f: F) -> Result<T, Response>
+where
+    R: Runtime,
+    T: Send + 'static,
+    F: FnOnce(Bridge<R>) -> T + Send + 'static,
+{
+    let handle = handle.clone();
+    tauri::async_runtime::spawn_blocking(move || f(Bridge::new(handle)))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn param<T: FromStr>(value: &str, name: &str) -> Result<T, Response> {
+    value
+        .parse()
+        .map_err(|_| error_response(StatusCode::BAD_REQUEST, format!("Invalid {}", name)))
+}
+
+fn no_content() -> Response {
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// the API passes its own status code through in the body
+fn json_with_code(value: &Value) -> Response {
+    let code = value
+        .get("code")
+        .and_then(|c| c.as_u64())
+        .and_then(|c| StatusCode::from_u16(c as u16).ok())
+        .unwrap_or(StatusCode::OK);
+
+    warp::reply::with_status(warp::reply::json(value), code).into_response()
 }
@@ -0,0 +1,492 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use super::stats::StatsBackend;
+
+// NOTE(steam achievements)
+//
+// achievements are declared here against the stat they follow, and unlocked once the stat
+// reaches the threshold. the names have to match what's set up on steamworks. the frontend
+// only reports what was listened to (`ListeningEvent`), the bookkeeping all happens here.
+//
+// steam only sends the current stats a little while after we connect, anything recorded
+// before that is held on to and added once they're in. which genres have been heard is kept
+// in `steam-genres.json`, steam only has room for the count. that count isn't added to, it's
+// raised to the size of the saved set whenever steam has less, so an update that never made
+// it is caught up on later and a second install can't count the same genres again.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stat {
+    TracksPlayed,
+    MinutesListened,
+    GenresExplored,
+}
+
+impl Stat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stat::TracksPlayed => "tracks_played",
+            Stat::MinutesListened => "minutes_listened",
+            Stat::GenresExplored => "genres_explored",
+        }
+    }
+}
+
+const STATS: [Stat; 3] = [
+    Stat::TracksPlayed,
+    Stat::MinutesListened,
+    Stat::GenresExplored,
+];
+
+pub struct Achievement {
+    pub name: &'static str,
+    pub stat: Stat,
+    pub threshold: i32,
+}
+
+pub const CATALOG: &[Achievement] = &[
+    Achievement {
+        name: "FIRST_TRACK",
+        stat: Stat::TracksPlayed,
+        threshold: 1,
+    },
+    Achievement {
+        name: "TRACKS_100",
+        stat: Stat::TracksPlayed,
+        threshold: 100,
+    },
+    Achievement {
+        name: "TRACKS_1000",
+        stat: Stat::TracksPlayed,
+        threshold: 1000,
+    },
+    Achievement {
+        name: "HOURS_10",
+        stat: Stat::MinutesListened,
+        threshold: 10 * 60,
+    },
+    Achievement {
+        name: "HOURS_100",
+        stat: Stat::MinutesListened,
+        threshold: 100 * 60,
+    },
+    Achievement {
+        name: "GENRES_5",
+        stat: Stat::GenresExplored,
+        threshold: 5,
+    },
+    Achievement {
+        name: "GENRES_20",
+        stat: Stat::GenresExplored,
+        threshold: 20,
+    },
+];
+
+// steam pops up how far along an achievement is as it passes each of these
+const MILESTONES: [f32; 3] = [0.25, 0.5, 0.75];
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ListeningEvent {
+    // the frontend decides when a track counts as played
+    TrackPlayed {
+        #[serde(default)]
+        genres: Vec<String>,
+    },
+    Listened {
+        seconds: u32,
+    },
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct AchievementProgress {
+    pub name: &'static str,
+    pub stat: Stat,
+    pub current: i32,
+    pub threshold: i32,
+    pub achieved: bool,
+}
+
+pub struct Tracker {
+    backend: Box<dyn StatsBackend + Send>,
+    // where the genres are kept, nowhere for the mock
+    path: Option<PathBuf>,
+    genres: HashSet<String>,
+    // under a minute, carried over to the next `Listened`
+    seconds: u32,
+    // recorded but not on steam yet, never `GenresExplored`
+    pending: HashMap<Stat, i32>,
+}
+
+impl Tracker {
+    pub fn new(backend: Box<dyn StatsBackend + Send>, path: Option<PathBuf>) -> Self {
+        let genres = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+
+        Self {
+            backend,
+            path,
+            genres,
+            seconds: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// returns the achievements this unlocked
+    pub fn record(&mut self, event: ListeningEvent) -> Vec<&'static str> {
+        match event {
+            ListeningEvent::TrackPlayed { genres } => {
+                self.add(Stat::TracksPlayed, 1);
+
+                let before = self.genres.len();
+                self.genres.extend(
+                    genres
+                        .iter()
+                        .map(|g| g.trim().to_lowercase())
+                        // apple puts everything under "music" as well
+                        .filter(|g| !g.is_empty() && g != "music"),
+                );
+
+                if self.genres.len() > before {
+                    self.save_genres();
+                }
+            }
+            ListeningEvent::Listened { seconds } => {
+                self.seconds += seconds;
+                let minutes = self.seconds / 60;
+                self.seconds %= 60;
+
+                if minutes > 0 {
+                    self.add(Stat::MinutesListened, minutes as i32);
+                }
+            }
+        }
+
+        self.flush()
+    }
+
+    /// for anything not in the catalog
+    pub fn unlock(&mut self, name: &str) -> bool {
+        self.backend.unlock(name) && self.backend.store()
+    }
+
+    /// empty until steam has sent the current stats
+    pub fn progress(&mut self) -> Vec<AchievementProgress> {
+        let current = match self.current() {
+            Some(current) => current,
+            None => return vec![],
+        };
+
+        let targets: HashMap<Stat, i32> = STATS
+            .iter()
+            .map(|s| (*s, self.target(*s, current[s])))
+            .collect();
+
+        CATALOG
+            .iter()
+            .map(|a| {
+                let value = targets[&a.stat];
+                AchievementProgress {
+                    name: a.name,
+                    stat: a.stat,
+                    current: value.min(a.threshold),
+                    threshold: a.threshold,
+                    achieved: self.backend.achieved(a.name).unwrap_or(false),
+                }
+            })
+            .collect()
+    }
+
+    fn add(&mut self, stat: Stat, amount: i32) {
+        *self.pending.entry(stat).or_default() += amount;
+    }
+
+    // what `stat` should be on steam once everything recorded is in
+    fn target(&self, stat: Stat, current: i32) -> i32 {
+        match stat {
+            Stat::GenresExplored => current.max(self.genres.len() as i32),
+            _ => current.saturating_add(self.pending.get(&stat).copied().unwrap_or(0)),
+        }
+    }
+
+    fn current(&mut self) -> Option<HashMap<Stat, i32>> {
+        STATS
+            .iter()
+            .map(|s| self.backend.get_i32(s.name()).map(|v| (*s, v)))
+            .collect()
+    }
+
+    fn flush(&mut self) -> Vec<&'static str> {
+        // kept for next time
+        let current = match self.current() {
+            Some(current) => current,
+            None => return vec![],
+        };
+
+        let changed: Vec<(Stat, i32, i32)> = STATS
+            .iter()
+            .map(|s| (*s, current[s], self.target(*s, current[s])))
+            .filter(|(_, before, after)| after != before)
+            .collect();
+        if changed.is_empty() {
+            return vec![];
+        }
+
+        let mut unlocked = vec![];
+        for (stat, before, after) in changed {
+            if !self.backend.set_i32(stat.name(), after) {
+                // still pending, or still behind the saved genres
+                println!("Unable to set Steam stat {}", stat.name());
+                continue;
+            }
+            self.pending.remove(&stat);
+
+            for achievement in CATALOG.iter().filter(|a| a.stat == stat) {
+                // also skips the ones steam doesn't know about
+                if self.backend.achieved(achievement.name) != Some(false) {
+                    continue;
+                }
+
+                if after >= achievement.threshold {
+                    if self.backend.unlock(achievement.name) {
+                        unlocked.push(achievement.name);
+                    }
+                } else if passed_milestone(before, after, achievement.threshold) {
+                    self.backend.indicate_progress(
+                        achievement.name,
+                        after as u32,
+                        achievement.threshold as u32,
+                    );
+                }
+            }
+        }
+
+        if !self.backend.store() {
+            println!("Unable to store Steam stats");
+        }
+
+        unlocked
+    }
+
+    fn save_genres(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let result = serde_json::to_string(&self.genres)
+            .map_err(|e| e.to_string())
+            .and_then(|s| fs::write(path, s).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            println!("Unable to save explored genres, {}", e);
+        }
+    }
+}
+
+fn passed_milestone(before: i32, after: i32, threshold: i32) -> bool {
+    MILESTONES.iter().any(|m| {
+        let at = (threshold as f32 * m).ceil() as i32;
+        before < at && after >= at
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::steam::stats::MockStats;
+
+    // a `MockStats` the test keeps a handle on, that can hold back stats or fail to set them
+    #[derive(Default)]
+    struct State {
+        mock: MockStats,
+        waiting: bool,
+        failing: HashSet<String>,
+        popups: Vec<(String, u32, u32)>,
+        stores: u32,
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<State>>);
+
+    impl Shared {
+        fn stat(&self, stat: Stat) -> i32 {
+            let state = self.0.lock().unwrap();
+            state.mock.stats.get(stat.name()).copied().unwrap_or(0)
+        }
+    }
+
+    impl StatsBackend for Shared {
+        fn get_i32(&mut self, name: &str) -> Option<i32> {
+            let mut state = self.0.lock().unwrap();
+            if state.waiting {
+                return None;
+            }
+            state.mock.get_i32(name)
+        }
+
+        fn set_i32(&mut self, name: &str, value: i32) -> bool {
+            let mut state = self.0.lock().unwrap();
+            !state.failing.contains(name) && state.mock.set_i32(name, value)
+        }
+
+        fn achieved(&mut self, name: &str) -> Option<bool> {
+            self.0.lock().unwrap().mock.achieved(name)
+        }
+
+        fn unlock(&mut self, name: &str) -> bool {
+            self.0.lock().unwrap().mock.unlock(name)
+        }
+
+        fn indicate_progress(&mut self, name: &str, current: u32, max: u32) -> bool {
+            let mut state = self.0.lock().unwrap();
+            state.popups.push((name.to_string(), current, max));
+            true
+        }
+
+        fn store(&mut self) -> bool {
+            let mut state = self.0.lock().unwrap();
+            state.stores += 1;
+            state.mock.store()
+        }
+    }
+
+    fn tracker() -> (Tracker, Shared) {
+        let shared = Shared::default();
+        (Tracker::new(Box::new(shared.clone()), None), shared)
+    }
+
+    fn played(genres: &[&str]) -> ListeningEvent {
+        ListeningEvent::TrackPlayed {
+            genres: genres.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn unlocks_at_threshold() {
+        let (mut tracker, shared) = tracker();
+
+        assert_eq!(tracker.record(played(&[])), vec!["FIRST_TRACK"]);
+        assert!(tracker.record(played(&[])).is_empty());
+        assert_eq!(shared.stat(Stat::TracksPlayed), 2);
+
+        shared
+            .0
+            .lock()
+            .unwrap()
+            .mock
+            .stats
+            .insert(Stat::TracksPlayed.name().into(), 99);
+        assert_eq!(tracker.record(played(&[])), vec!["TRACKS_100"]);
+        assert_eq!(shared.0.lock().unwrap().stores, 3);
+    }
+
+    #[test]
+    fn pops_up_milestones_once() {
+        let (mut tracker, shared) = tracker();
+
+        // 150 of HOURS_10's 600 minutes is a quarter
+        tracker.record(ListeningEvent::Listened { seconds: 149 * 60 });
+        assert!(shared.0.lock().unwrap().popups.is_empty());
+
+        tracker.record(ListeningEvent::Listened { seconds: 60 });
+        tracker.record(ListeningEvent::Listened { seconds: 60 });
+        assert_eq!(
+            shared.0.lock().unwrap().popups,
+            vec![("HOURS_10".to_string(), 150, 600)]
+        );
+        assert_eq!(shared.stat(Stat::MinutesListened), 151);
+    }
+
+    #[test]
+    fn carries_over_seconds() {
+        let (mut tracker, shared) = tracker();
+
+        tracker.record(ListeningEvent::Listened { seconds: 40 });
+        tracker.record(ListeningEvent::Listened { seconds: 40 });
+        assert_eq!(shared.stat(Stat::MinutesListened), 1);
+    }
+
+    #[test]
+    fn holds_on_until_stats_arrive() {
+        let (mut tracker, shared) = tracker();
+        shared.0.lock().unwrap().waiting = true;
+
+        assert!(tracker.record(played(&[])).is_empty());
+        assert!(tracker.record(played(&[])).is_empty());
+        assert!(tracker.progress().is_empty());
+        assert_eq!(shared.stat(Stat::TracksPlayed), 0);
+
+        shared.0.lock().unwrap().waiting = false;
+        assert_eq!(
+            tracker.record(ListeningEvent::Listened { seconds: 1 }),
+            vec!["FIRST_TRACK"]
+        );
+        assert_eq!(shared.stat(Stat::TracksPlayed), 2);
+    }
+
+    #[test]
+    fn requeues_failed_sets() {
+        let (mut tracker, shared) = tracker();
+        shared
+            .0
+            .lock()
+            .unwrap()
+            .failing
+            .insert(Stat::TracksPlayed.name().into());
+
+        assert!(tracker.record(played(&[])).is_empty());
+        assert_eq!(shared.stat(Stat::TracksPlayed), 0);
+
+        let progress = tracker.progress();
+        let first = progress.iter().find(|p| p.name == "FIRST_TRACK").unwrap();
+        assert_eq!((first.current, first.achieved), (1, false));
+
+        shared.0.lock().unwrap().failing.clear();
+        assert_eq!(tracker.record(played(&[])), vec!["FIRST_TRACK"]);
+        assert_eq!(shared.stat(Stat::TracksPlayed), 2);
+    }
+
+    #[test]
+    fn counts_genres_from_the_saved_set() {
+        let (mut tracker, shared) = tracker();
+        // from another install
+        shared
+            .0
+            .lock()
+            .unwrap()
+            .mock
+            .stats
+            .insert(Stat::GenresExplored.name().into(), 3);
+
+        tracker.record(played(&["Pop", "Music", "rock "]));
+        tracker.record(played(&["pop", "Jazz"]));
+        assert_eq!(shared.stat(Stat::GenresExplored), 3);
+
+        tracker.record(played(&["Soul"]));
+        assert_eq!(shared.stat(Stat::GenresExplored), 4);
+    }
+
+    #[test]
+    fn catches_up_on_genres_that_failed_to_set() {
+        let (mut tracker, shared) = tracker();
+        let genres = Stat::GenresExplored.name().to_string();
+        shared.0.lock().unwrap().failing.insert(genres.clone());
+
+        tracker.record(played(&["pop", "rock"]));
+        assert_eq!(shared.stat(Stat::GenresExplored), 0);
+
+        shared.0.lock().unwrap().failing.remove(&genres);
+        tracker.record(ListeningEvent::Listened { seconds: 1 });
+        assert_eq!(shared.stat(Stat::GenresExplored), 2);
+    }
+}
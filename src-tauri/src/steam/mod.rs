@@ -1,11 +1,19 @@
+use serde_json::Value;
 use tauri::{
-    async_runtime::RwLock,
+    async_runtime::{Mutex, RwLock},
     plugin::{Builder, TauriPlugin},
-    Manager, Runtime,
+    AppHandle, Manager, Runtime,
 };
 
+mod achievements;
 mod callbacks;
+mod presence;
+mod stats;
 
+use achievements::{AchievementProgress, ListeningEvent, Tracker};
+use presence::{Activity, ListeningParty, Presence, Track, PLAYBACK_STATE_EVENT};
+
+use stats::{MockStats, SteamStats};
 use steamworks::{Client, SingleClient};
 
 // NOTE(d3rpp)
@@ -32,28 +40,55 @@ fn run_loop(single: SingleClient) {
     }
 }
 
+// changes what we show and sends all of it again, steam only keeps the latest value anyway
+async fn update_presence<R: Runtime>(
+    app: &AppHandle<R>,
+    f: impl FnOnce(&mut Presence),
+) -> Result<(), String> {
+    let (client, presence) = match (
+        app.try_state::<RwLock<Client>>(),
+        app.try_state::<RwLock<Presence>>(),
+    ) {
+        (Some(c), Some(p)) => (c, p),
+        _ => return Err("Steam API Not Connected".into()),
+    };
+
+    let mut presence = presence.write().await;
+    f(&mut presence);
+    presence.apply(&client.read().await.friends());
+
+    Ok(())
+}
+
+/// `activity` defaults to listening, see NOTE(steam presence) for what each one shows
 #[tauri::command]
 async fn set_rich_presence<R: Runtime>(
     app: tauri::AppHandle<R>,
     _window: tauri::Window<R>,
-    key: &str,
+    activity: Option<Activity>,
     track: &str,
     album: &str,
     artist: &str,
 ) -> Result<(), String> {
-    if let Some(c) = app.try_state::<RwLock<Client>>() {
-        let friends = c.read().await.friends();
-
-        friends.set_rich_presence("title", Some(track));
-        friends.set_rich_presence("album", Some(album));
-        friends.set_rich_presence("artist", Some(artist));
-        friends.set_rich_presence("status", Some(key));
-        friends.set_rich_presence("steam_display", Some(key));
+    update_presence(&app, |p| {
+        p.activity = Some(activity.unwrap_or(Activity::Listening));
+        p.track = Track {
+            title: track.to_string(),
+            album: album.to_string(),
+            artist: artist.to_string(),
+        };
+    })
+    .await
+}
 
-        Ok(())
-    } else {
-        Err("Steam API Not Connected".into())
-    }
+/// groups us with everyone else in the same party, `None` when leaving it
+#[tauri::command]
+async fn set_listening_party<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    _window: tauri::Window<R>,
+    party: Option<ListeningParty>,
+) -> Result<(), String> {
+    update_presence(&app, |p| p.party = party).await
 }
 
 #[tauri::command]
@@ -61,16 +96,7 @@ async fn clear_rich_presence<R: Runtime>(
     app: tauri::AppHandle<R>,
     _window: tauri::Window<R>,
 ) -> Result<(), String> {
-    if let Some(c) = app.try_state::<RwLock<Client>>() {
-        let friends = c.read().await.friends();
-
-        friends.set_rich_presence("steam_display", None);
-        friends.set_rich_presence("status", None);
-
-        Ok(())
-    } else {
-        Err("Steam API Not Connected".into())
-    }
+    update_presence(&app, |p| *p = Presence::default()).await
 }
 
 #[tauri::command]
@@ -85,23 +111,39 @@ async fn grant_achievement<R: Runtime>(
     _window: tauri::Window<R>,
     achievement: String,
 ) -> bool {
-    if let Some(c) = app.try_state::<RwLock<Client>>() {
-        let c_lock = c.read().await;
-        let user = c_lock.user_stats();
-        user.request_current_stats();
-
-        let ach = user.achievement(achievement.as_str());
-
-        if ach.set().is_ok() {
-            user.store_stats().is_ok()
-        } else {
-            false
-        }
+    if let Some(t) = app.try_state::<Mutex<Tracker>>() {
+        t.lock().await.unlock(&achievement)
     } else {
         false
     }
 }
 
+/// counts towards the stats in NOTE(steam achievements), returns what it unlocked
+#[tauri::command]
+async fn record_listening<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    _window: tauri::Window<R>,
+    event: ListeningEvent,
+) -> Result<Vec<&'static str>, String> {
+    if let Some(t) = app.try_state::<Mutex<Tracker>>() {
+        Ok(t.lock().await.record(event))
+    } else {
+        Err("Steam API Not Connected".into())
+    }
+}
+
+#[tauri::command]
+async fn achievement_progress<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    _window: tauri::Window<R>,
+) -> Result<Vec<AchievementProgress>, String> {
+    if let Some(t) = app.try_state::<Mutex<Tracker>>() {
+        Ok(t.lock().await.progress())
+    } else {
+        Err("Steam API Not Connected".into())
+    }
+}
+
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
     Builder::new("steamworks")
         .invoke_handler(tauri::generate_handler![])
@@ -110,6 +152,14 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
                 Ok(r) => r,
                 Err(e) => {
                     println!("Unable to connect to Steam: {:?}", e);
+
+                    // so the achievements can still be tried out while working on them
+                    if crate::IS_DEV {
+                        app.manage(Mutex::new(Tracker::new(
+                            Box::new(MockStats::default()),
+                            None,
+                        )));
+                    }
                     return Ok(());
                 }
             };
@@ -118,15 +168,48 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
 
             tauri::async_runtime::spawn_blocking(|| run_loop(single));
 
-            app.manage(RwLock::new(client));
+            app.manage(RwLock::new(client.clone()));
+            app.manage(RwLock::new(Presence::default()));
+            app.manage(Mutex::new(Tracker::new(
+                Box::new(SteamStats::new(client.clone())),
+                app.path_resolver()
+                    .app_config_dir()
+                    .map(|d| d.join("steam-genres.json")),
+            )));
+
+            let handle = app.clone();
+            app.listen_global(PLAYBACK_STATE_EVENT, move |event| {
+                let activity = event
+                    .payload()
+                    .and_then(|p| serde_json::from_str::<Value>(p).ok())
+                    .as_ref()
+                    .and_then(Activity::from_playback);
+
+                if let Some(activity) = activity {
+                    let handle = handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        // nothing is shown once it's been cleared, playing again shouldn't bring it back
+                        update_presence(&handle, |p| {
+                            if p.activity.is_some() {
+                                p.activity = Some(activity);
+                            }
+                        })
+                        .await
+                        .ok();
+                    });
+                }
+            });
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             set_rich_presence,
+            set_listening_party,
             clear_rich_presence,
             active,
-            grant_achievement
+            grant_achievement,
+            record_listening,
+            achievement_progress
         ])
         .build()
 }
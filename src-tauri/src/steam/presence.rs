@@ -0,0 +1,137 @@
+use serde_json::Value;
+use steamworks::Friends;
+
+// NOTE(steam presence)
+//
+// steam only shows rich presence through the tokens in our localization file on steamworks,
+// `steam_display` picks one and the other keys are substituted into it:
+//
+//   #Listening   Listening to %title% by %artist%
+//   #Paused      Paused
+//   #Browsing    Browsing
+//
+// `status` is the plain text the friends list falls back to. friends in the same listening
+// party are grouped by `steam_player_group`.
+//
+// the frontend emits `playback-state-changed` with MusicKit's `{ state }`, a number or its
+// name, which moves between the three without the webview having to say so. paused or
+// stopped drops the track, it's only shown while it's actually playing.
+
+pub const PLAYBACK_STATE_EVENT: &str = "playback-state-changed";
+
+// every key we set, so clearing doesn't leave anything behind
+const KEYS: [&str; 7] = [
+    "steam_display",
+    "status",
+    "title",
+    "album",
+    "artist",
+    "steam_player_group",
+    "steam_player_group_size",
+];
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Activity {
+    Listening,
+    Paused,
+    Browsing,
+}
+
+impl Activity {
+    fn token(&self) -> &'static str {
+        match self {
+            Activity::Listening => "#Listening",
+            Activity::Paused => "#Paused",
+            Activity::Browsing => "#Browsing",
+        }
+    }
+
+    /// `None` for states that don't change what's shown, like loading or seeking
+    pub fn from_playback(payload: &Value) -> Option<Self> {
+        let state = payload.get("state").unwrap_or(payload);
+
+        if let Some(state) = state.as_u64() {
+            // MusicKit.PlaybackStates
+            return match state {
+                2 => Some(Activity::Listening),
+                3 => Some(Activity::Paused),
+                0 | 4 | 5 | 10 => Some(Activity::Browsing),
+                _ => None,
+            };
+        }
+
+        match state.as_str()?.to_lowercase().as_str() {
+            "playing" => Some(Activity::Listening),
+            "paused" => Some(Activity::Paused),
+            "none" | "stopped" | "ended" | "completed" => Some(Activity::Browsing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Track {
+    pub title: String,
+    pub album: String,
+    pub artist: String,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ListeningParty {
+    pub id: String,
+    pub size: Option<u32>,
+}
+
+/// what we last told steam, kept so playback changes can update it
+#[derive(Default)]
+pub struct Presence {
+    pub activity: Option<Activity>,
+    pub track: Track,
+    pub party: Option<ListeningParty>,
+}
+
+impl Presence {
+    pub fn apply<M>(&self, friends: &Friends<M>) {
+        let activity = match self.activity {
+            Some(activity) => activity,
+            None => {
+                for key in KEYS {
+                    friends.set_rich_presence(key, None);
+                }
+                return;
+            }
+        };
+
+        let track = match activity {
+            Activity::Listening => Some(&self.track),
+            _ => None,
+        };
+
+        let status = match track {
+            Some(t) if !t.artist.is_empty() => format!("Listening to {} by {}", t.title, t.artist),
+            Some(t) => format!("Listening to {}", t.title),
+            None if activity == Activity::Paused => "Paused".to_string(),
+            None => "Browsing".to_string(),
+        };
+
+        friends.set_rich_presence("title", track.map(|t| t.title.as_str()));
+        friends.set_rich_presence("album", track.map(|t| t.album.as_str()));
+        friends.set_rich_presence("artist", track.map(|t| t.artist.as_str()));
+        friends.set_rich_presence("status", Some(&status));
+
+        let size = self
+            .party
+            .as_ref()
+            .and_then(|p| p.size)
+            .map(|s| s.to_string());
+        friends.set_rich_presence(
+            "steam_player_group",
+            self.party.as_ref().map(|p| p.id.as_str()),
+        );
+        friends.set_rich_presence("steam_player_group_size", size.as_deref());
+
+        // last, so the substitutions are already there when it switches
+        friends.set_rich_presence("steam_display", Some(activity.token()));
+    }
+}
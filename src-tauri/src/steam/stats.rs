@@ -0,0 +1,94 @@
+use std::collections::{HashMap, HashSet};
+
+use steamworks::Client;
+
+/// the parts of steam's user stats the achievement tracker uses
+pub trait StatsBackend {
+    /// `None` until steam has sent us the current values
+    fn get_i32(&mut self, name: &str) -> Option<i32>;
+    fn set_i32(&mut self, name: &str, value: i32) -> bool;
+    fn achieved(&mut self, name: &str) -> Option<bool>;
+    fn unlock(&mut self, name: &str) -> bool;
+    /// shows the "x of y" popup, doesn't change anything
+    fn indicate_progress(&mut self, name: &str, current: u32, max: u32) -> bool;
+    /// nothing is kept until this is called
+    fn store(&mut self) -> bool;
+}
+
+pub struct SteamStats {
+    client: Client,
+}
+
+impl SteamStats {
+    pub fn new(client: Client) -> Self {
+        // once, the values stay up to date for as long as we're running
+        client.user_stats().request_current_stats();
+        Self { client }
+    }
+}
+
+impl StatsBackend for SteamStats {
+    fn get_i32(&mut self, name: &str) -> Option<i32> {
+        self.client.user_stats().get_stat_i32(name).ok()
+    }
+
+    fn set_i32(&mut self, name: &str, value: i32) -> bool {
+        self.client.user_stats().set_stat_i32(name, value).is_ok()
+    }
+
+    fn achieved(&mut self, name: &str) -> Option<bool> {
+        self.client.user_stats().achievement(name).get().ok()
+    }
+
+    fn unlock(&mut self, name: &str) -> bool {
+        self.client.user_stats().achievement(name).set().is_ok()
+    }
+
+    fn indicate_progress(&mut self, name: &str, current: u32, max: u32) -> bool {
+        self.client
+            .user_stats()
+            .indicate_achievement_progress(name, current, max)
+            .is_ok()
+    }
+
+    fn store(&mut self) -> bool {
+        self.client.user_stats().store_stats().is_ok()
+    }
+}
+
+/// stands in for steam when it isn't running, everything is kept in memory
+#[derive(Default)]
+pub struct MockStats {
+    pub stats: HashMap<String, i32>,
+    pub achieved: HashSet<String>,
+}
+
+impl StatsBackend for MockStats {
+    fn get_i32(&mut self, name: &str) -> Option<i32> {
+        Some(self.stats.get(name).copied().unwrap_or_default())
+    }
+
+    fn set_i32(&mut self, name: &str, value: i32) -> bool {
+        self.stats.insert(name.to_string(), value);
+        true
+    }
+
+    fn achieved(&mut self, name: &str) -> Option<bool> {
+        Some(self.achieved.contains(name))
+    }
+
+    fn unlock(&mut self, name: &str) -> bool {
+        self.achieved.insert(name.to_string());
+        true
+    }
+
+    fn indicate_progress(&mut self, name: &str, current: u32, max: u32) -> bool {
+        // where steam would pop it up
+        println!("[steam mock] {} {}/{}", name, current, max);
+        true
+    }
+
+    fn store(&mut self) -> bool {
+        true
+    }
+}
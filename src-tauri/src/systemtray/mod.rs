@@ -1,9 +1,21 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
 use tauri::{
     AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu,
     SystemTrayMenuItem,
 };
 
-use crate::bridge::Bridge;
+use crate::{bridge::Bridge, config::TraySettings};
+
+lazy_static::lazy_static! {
+    // kept so the song can come back when `tray.show_now_playing` is turned back on
+    static ref CURRENT_SONG: Mutex<String> = Mutex::new("Cider".to_string());
+}
+
+static SHOW_NOW_PLAYING: AtomicBool = AtomicBool::new(true);
 
 #[tauri::command]
 pub fn play(app: AppHandle) {
@@ -31,10 +43,29 @@ pub fn play(app: AppHandle) {
 
 #[tauri::command]
 pub fn change_song(app: AppHandle, song: String) {
+    *CURRENT_SONG.lock().unwrap() = song.clone();
+
+    if SHOW_NOW_PLAYING.load(Ordering::Relaxed) {
+        app.tray_handle()
+            .get_item("songString")
+            .set_title(song)
+            .unwrap();
+    }
+}
+
+pub fn apply_settings(app: &AppHandle, settings: &TraySettings) {
+    SHOW_NOW_PLAYING.store(settings.show_now_playing, Ordering::Relaxed);
+
+    let title = if settings.show_now_playing {
+        CURRENT_SONG.lock().unwrap().clone()
+    } else {
+        "Cider".to_string()
+    };
+
     app.tray_handle()
         .get_item("songString")
-        .set_title(song)
-        .unwrap();
+        .set_title(title)
+        .ok();
 }
 
 #[tauri::command]
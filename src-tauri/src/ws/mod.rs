@@ -1,104 +1,58 @@
 use futures::{stream::SplitSink, SinkExt, StreamExt};
-use serde::Serialize;
-use std::{collections::LinkedList, convert::Infallible, sync::Arc};
+use std::{
+    collections::LinkedList,
+    sync::{atomic::AtomicBool, Arc},
+};
 use tauri::{
-    async_runtime::{JoinHandle, Mutex, RwLock},
+    async_runtime::RwLock,
     plugin::{Builder as PluginBuilder, TauriPlugin},
-    Manager, Runtime, State,
+    AppHandle, Runtime,
 };
 use warp::{
     filters::ws::{Message, WebSocket},
-    http::StatusCode,
-    Filter,
+    reply::Response,
+    Filter, Rejection, Reply,
 };
 
+use crate::http::{self, error::HttpError, Feature};
+
 lazy_static::lazy_static! {
     static ref WS_CLIENTS: Arc<RwLock<LinkedList<SplitSink<WebSocket, Message>>>> = Arc::new(RwLock::new(LinkedList::new()));
 }
 
-pub struct WebSocketState {
-    pub server_thread: Arc<Mutex<Option<JoinHandle<()>>>>,
-}
-
-const PORT_NUMBER: u16 = 10766u16;
-
-// example error response
-#[derive(Serialize, Debug)]
-struct ApiErrorResult {
-    detail: String,
-}
-
 async fn store_ws_sender(ws: warp::ws::WebSocket) {
     let (sender, _) = ws.split();
     WS_CLIENTS.write().await.push_front(sender);
 }
 
-async fn handle_rejection(
-    err: warp::reject::Rejection,
-) -> Result<impl warp::reply::Reply, Infallible> {
-    let code;
-    let message;
+/// `/ws` on the shared http server, only there while the websocket is enabled
+pub fn routes(
+    enabled: Arc<AtomicBool>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone + Send + Sync + 'static {
+    warp::path("ws")
+        .and(warp::path::end())
+        .and(http::enabled(enabled))
+        .and(warp::ws())
+        .map(|ws: warp::ws::Ws| ws.on_upgrade(store_ws_sender).into_response())
+}
 
-    if err.is_not_found() {
-        code = StatusCode::NOT_FOUND;
-        message = "Not Found";
-    } else if err
-        .find::<warp::filters::body::BodyDeserializeError>()
-        .is_some()
-    {
-        code = StatusCode::BAD_REQUEST;
-        message = "Invalid Body";
-    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
-        code = StatusCode::METHOD_NOT_ALLOWED;
-        message = "Method not Allowed";
-    } else {
-        code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Internal Server Error";
+pub async fn close_clients() {
+    let mut clients = WS_CLIENTS.write().await;
+    for client in clients.iter_mut() {
+        let _ = client.close().await;
     }
-
-    let json = warp::reply::json(&ApiErrorResult {
-        detail: message.into(),
-    });
-    Ok(warp::reply::with_status(json, code))
+    clients.clear();
 }
 
+/// returns the port the websocket is served on
 #[tauri::command]
-pub fn start_server(ws_state: State<'_, WebSocketState>) -> u16 {
-    let health_check = warp::path("health-check").map(|| "OK".to_string());
-
-    let ws = warp::path("ws")
-        .and(warp::ws())
-        .map(|ws: warp::ws::Ws| ws.on_upgrade(store_ws_sender));
-
-    let routes = health_check
-        .or(ws)
-        .with(warp::cors().allow_any_origin())
-        .recover(handle_rejection);
-
-    let thread_ref = ws_state.server_thread.clone();
-
-    tokio::task::block_in_place(move || {
-        *thread_ref.blocking_lock() = Some(tauri::async_runtime::spawn(
-            warp::serve(routes).run(([127, 0, 0, 1], PORT_NUMBER)),
-        ));
-    });
-
-    PORT_NUMBER
+pub async fn start_server<R: Runtime>(handle: AppHandle<R>) -> Result<u16, HttpError> {
+    http::enable(&handle, Feature::WebSocket, None).await
 }
 
 #[tauri::command]
-pub fn stop_server(ws_state: State<'_, WebSocketState>) {
-    tokio::task::block_in_place(move || {
-        let mut ws_state_lock = ws_state.server_thread.blocking_lock();
-        if let Some(handle) = ws_state_lock.as_ref() {
-            // literally delete the server
-            // this is not a clean exit
-            // but it's an exit
-            handle.abort();
-        }
-        *ws_state_lock = None;
-        WS_CLIENTS.blocking_write().clear();
-    });
+pub async fn stop_server<R: Runtime>(handle: AppHandle<R>) -> Result<(), HttpError> {
+    http::disable(&handle, Feature::WebSocket).await
 }
 
 #[tauri::command]
@@ -113,13 +67,6 @@ where
     R: Runtime,
 {
     PluginBuilder::new("ws")
-        .setup(|app| {
-            app.manage(WebSocketState {
-                server_thread: Arc::new(Mutex::new(None)),
-            });
-
-            Ok(())
-        })
         .invoke_handler(tauri::generate_handler![
             start_server,
             stop_server,